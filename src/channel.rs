@@ -0,0 +1,276 @@
+//! # `mnemos-alloc` Channels
+//!
+//! A bounded, async, multi-producer single-consumer channel, for sending
+//! items from task to task. The channel's buffer is a [`HeapRingBuffer`],
+//! shared between the [`Sender`]s and the [`Receiver`] with a [`HeapArc`].
+//!
+//! Created with [`channel`] or [`try_channel`].
+
+use core::{
+    fmt,
+    future::{poll_fn, Future},
+    pin::pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::Poll,
+};
+
+use maitake::sync::WaitQueue;
+
+use crate::{
+    containers::HeapArc,
+    heap::{AHeap, AllocError, HeapGuard},
+    ring::HeapRingBuffer,
+};
+
+/// The sending half of a channel.
+///
+/// Senders can be cloned to send from several tasks at once.
+pub struct Sender<T> {
+    chan: HeapArc<Chan<T>>,
+}
+
+/// The receiving half of a channel.
+pub struct Receiver<T> {
+    chan: HeapArc<Chan<T>>,
+}
+
+struct Chan<T> {
+    ring: HeapRingBuffer<T>,
+    /// Serializes the senders, which all share the ring's producer side
+    send_lock: AtomicBool,
+    senders: AtomicUsize,
+    rx_alive: AtomicBool,
+    /// Woken when an item is sent, or the last sender is dropped
+    recv_wait: WaitQueue,
+    /// Woken when an item is received, or the receiver is dropped
+    send_wait: WaitQueue,
+}
+
+/// Allocate a channel with room for at least `capacity` items, waiting for
+/// space to become available if necessary.
+///
+/// The capacity is rounded up to the next power of two.
+pub async fn channel<T>(heap: &'static AHeap, capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let ring = HeapRingBuffer::new(heap, capacity).await;
+    let chan = heap.allocate_arc(Chan::new(ring)).await;
+    Chan::split(chan)
+}
+
+/// Attempt to allocate a channel with room for at least `capacity` items.
+///
+/// The capacity is rounded up to the next power of two.
+pub fn try_channel<T>(
+    guard: &mut HeapGuard,
+    capacity: usize,
+) -> Result<(Sender<T>, Receiver<T>), AllocError> {
+    let ring = HeapRingBuffer::try_new(guard, capacity)?;
+    let chan = guard
+        .alloc_arc_or_err(Chan::new(ring))
+        .map_err(|(_, err)| err)?;
+    Ok(Chan::split(chan))
+}
+
+/// Wait for `queue` to be woken, unless `ready` becomes true while
+/// registering for the wakeup.
+async fn wait_unless(queue: &WaitQueue, ready: impl Fn() -> bool) {
+    let mut wait = pin!(queue.wait());
+
+    // Poll once to register our waker
+    let registered = poll_fn(|cx| match wait.as_mut().poll(cx) {
+        Poll::Ready(_) => Poll::Ready(false),
+        Poll::Pending => Poll::Ready(true),
+    })
+    .await;
+
+    // Anything that happened before we registered won't wake us, so check
+    // again before actually waiting.
+    if registered && !ready() {
+        let _ = wait.await;
+    }
+}
+
+/// Let other tasks run, before carrying on.
+async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await;
+}
+
+// === impl Sender ===
+
+impl<T> Sender<T> {
+    /// Send an item, waiting for space in the channel if it is full.
+    ///
+    /// If the receiver has been dropped, the item is returned.
+    pub async fn send(&self, mut item: T) -> Result<(), T> {
+        loop {
+            match self.try_send(item) {
+                Ok(()) => return Ok(()),
+                Err(it) if self.is_closed() => return Err(it),
+                Err(it) => item = it,
+            }
+            let chan = &*self.chan;
+            if chan.send_lock.load(Ordering::Acquire) {
+                // Another sender is mid-push, which won't take long, but
+                // doesn't wake anyone when done
+                yield_now().await;
+                continue;
+            }
+            wait_unless(&chan.send_wait, || !chan.ring.is_full() || self.is_closed()).await;
+        }
+    }
+
+    /// Attempt to send an item without waiting.
+    ///
+    /// If the channel is full, the receiver has been dropped, or another
+    /// sender is in the middle of sending (e.g. in a task that this
+    /// interrupted), the item is returned.
+    pub fn try_send(&self, item: T) -> Result<(), T> {
+        let chan = &*self.chan;
+        if self.is_closed() {
+            return Err(item);
+        }
+
+        // This is only held for the duration of a single push, but never
+        // wait for it: its holder may be what we interrupted.
+        if chan
+            .send_lock
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(item);
+        }
+        // SAFETY: The lock ensures we are the only producer
+        let res = unsafe { chan.ring.push(item) };
+        chan.send_lock.store(false, Ordering::Release);
+
+        if res.is_ok() {
+            chan.recv_wait.wake_all();
+        }
+        res
+    }
+
+    /// Returns whether the receiver has been dropped.
+    pub fn is_closed(&self) -> bool {
+        !self.chan.rx_alive.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.chan.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.chan.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.chan.recv_wait.wake_all();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("is_closed", &self.is_closed())
+            .finish()
+    }
+}
+
+// === impl Receiver ===
+
+impl<T> Receiver<T> {
+    /// Receive an item, waiting for one to be sent if the channel is empty.
+    ///
+    /// Returns `None` once all of the senders have been dropped, and all of
+    /// the items they sent have been received.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            if let Some(item) = self.try_recv() {
+                return Some(item);
+            }
+            if self.is_closed() {
+                // A final item may have been sent just before closing
+                return self.try_recv();
+            }
+            let chan = &*self.chan;
+            wait_unless(&chan.recv_wait, || {
+                !chan.ring.is_empty() || self.is_closed()
+            })
+            .await;
+        }
+    }
+
+    /// Attempt to receive an item without waiting.
+    pub fn try_recv(&mut self) -> Option<T> {
+        let chan = &*self.chan;
+        // SAFETY: There is only one receiver, and `&mut self` means it isn't
+        // popping concurrently.
+        let item = unsafe { chan.ring.pop() }?;
+        chan.send_wait.wake_all();
+        Some(item)
+    }
+
+    /// Returns whether all of the senders have been dropped.
+    pub fn is_closed(&self) -> bool {
+        self.chan.senders.load(Ordering::Acquire) == 0
+    }
+
+    /// The number of items waiting to be received.
+    pub fn len(&self) -> usize {
+        self.chan.ring.len()
+    }
+
+    /// Returns whether there are no items waiting to be received.
+    pub fn is_empty(&self) -> bool {
+        self.chan.ring.is_empty()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        // Any remaining items are dropped along with the ring
+        self.chan.rx_alive.store(false, Ordering::Release);
+        self.chan.send_wait.wake_all();
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver")
+            .field("len", &self.len())
+            .field("is_closed", &self.is_closed())
+            .finish()
+    }
+}
+
+// === impl Chan ===
+
+impl<T> Chan<T> {
+    fn new(ring: HeapRingBuffer<T>) -> Self {
+        Self {
+            ring,
+            send_lock: AtomicBool::new(false),
+            senders: AtomicUsize::new(1),
+            rx_alive: AtomicBool::new(true),
+            recv_wait: WaitQueue::new(),
+            send_wait: WaitQueue::new(),
+        }
+    }
+
+    fn split(chan: HeapArc<Self>) -> (Sender<T>, Receiver<T>) {
+        let rx = Receiver { chan: chan.clone() };
+        (Sender { chan }, rx)
+    }
+}
@@ -1,15 +1,36 @@
-use crate::node::{Active, ActiveArr};
+use crate::heap::{AHeap, AllocError, HeapGuard};
+use crate::node::{Active, ActiveArr, Node};
+use core::alloc::Layout;
 use core::marker::PhantomData;
 use core::mem::MaybeUninit;
+#[cfg(feature = "debug-arc")]
+use core::panic::Location;
 use core::ptr::{addr_of, addr_of_mut, drop_in_place};
 use core::slice::{from_raw_parts, from_raw_parts_mut};
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::Ordering;
+// Under loom, the reference counts (and the fences ordering them) are model
+// checked, see `tests/loom.rs`
+#[cfg(not(loom))]
+use core::sync::atomic as refcount_atomic;
 use core::{
+    borrow::{Borrow, BorrowMut},
     fmt,
-    mem::forget,
-    ops::{Deref, DerefMut},
+    future::Future,
+    hash::{Hash, Hasher},
+    hint,
+    mem::{forget, offset_of, ManuallyDrop},
+    ops::{Bound, Deref, DerefMut, Range, RangeBounds},
+    pin::Pin,
     ptr::NonNull,
+    task::{Context, Poll},
 };
+#[cfg(loom)]
+use loom::sync::atomic as refcount_atomic;
+use refcount_atomic::fence;
+#[cfg(feature = "thin-arc")]
+use refcount_atomic::AtomicU32;
+#[cfg(not(feature = "thin-arc"))]
+use refcount_atomic::AtomicUsize;
 
 /// An Anachro Heap Box Type
 pub struct HeapBox<T> {
@@ -17,9 +38,215 @@ pub struct HeapBox<T> {
     pub(crate) pd: PhantomData<Active<T>>,
 }
 
+/// An owned [`HeapBox`], in the form of a single pointer.
+///
+/// This is useful for sending boxes over queues or channels that move
+/// pointer-sized items: the token has the same size as a pointer, but (unlike
+/// a raw pointer from [`HeapBox::leak`]) it can only be turned back into a
+/// `HeapBox` once, with [`HeapBox::from_token`]. Dropping an unconsumed token
+/// drops the box.
+#[repr(transparent)]
+pub struct HeapBoxToken<T> {
+    pub(crate) ptr: NonNull<T>,
+}
+
+/// A box whose contents are aligned to more than `T` requires.
+///
+/// Created with [`HeapGuard::alloc_aligned_box`]. Unlike a [`HeapBox`], the
+/// handle stores the layout the contents were allocated with (including the
+/// raised alignment), which is needed to free the right block.
+pub struct HeapAlignedBox<T> {
+    pub(crate) ptr: NonNull<T>,
+    pub(crate) layout: Layout,
+}
+
+/// A [`HeapBox`] whose contents have been unsized, e.g. into a trait object.
+///
+/// Created from a box of any concrete type with [`heap_box_dyn!`], without
+/// reallocating. As that type is no longer known, the handle stores the
+/// layout of the node the box was allocated as, which is needed to free it.
+///
+/// [`heap_box_dyn!`]: crate::heap_box_dyn
+pub struct HeapDynBox<T: ?Sized> {
+    pub(crate) ptr: NonNull<T>,
+    pub(crate) node: NonNull<Active<()>>,
+    pub(crate) node_layout: Layout,
+}
+
+/// The integer type used for the reference counts of a [`HeapArc`].
+///
+/// With the `thin-arc` feature, this is a `u32`, which makes every arc
+/// allocation smaller on 64-bit targets, but lowers the number of handles
+/// that one arc can have, see `MAX_REFCOUNT`.
+#[cfg(not(feature = "thin-arc"))]
+pub(crate) type RefCount = AtomicUsize;
+#[cfg(feature = "thin-arc")]
+pub(crate) type RefCount = AtomicU32;
+
+/// Drop the `len` elements at `start`, and then free the array node `node`.
+///
+/// Like `Vec`, if one of the elements panics while being dropped, the rest
+/// are still dropped (and a second panic aborts), and the node is still
+/// freed.
+unsafe fn drop_array<T, U>(start: *mut T, len: usize, node: NonNull<ActiveArr<U>>) {
+    struct FreeOnDrop<U>(NonNull<ActiveArr<U>>);
+
+    impl<U> Drop for FreeOnDrop<U> {
+        fn drop(&mut self) {
+            unsafe { ActiveArr::<U>::yeet(self.0) }
+        }
+    }
+
+    let _free = FreeOnDrop(node);
+    drop_in_place(core::ptr::slice_from_raw_parts_mut(start, len));
+}
+
+/// Like `drop_array`, but dropping the elements from last to first.
+unsafe fn drop_array_rev<T>(start: *mut T, len: usize, node: NonNull<ActiveArr<T>>) {
+    /// The elements before `left` that are still to be dropped. If one of
+    /// them panics, this drops the rest while unwinding.
+    struct Rest<T> {
+        start: *mut T,
+        left: usize,
+        node: NonNull<ActiveArr<T>>,
+    }
+
+    impl<T> Rest<T> {
+        unsafe fn drop_elements(&mut self) {
+            while self.left > 0 {
+                // Don't count the element again if it panics
+                self.left -= 1;
+                drop_in_place(self.start.add(self.left));
+            }
+        }
+    }
+
+    impl<T> Drop for Rest<T> {
+        fn drop(&mut self) {
+            unsafe {
+                self.drop_elements();
+                ActiveArr::<T>::yeet(self.node);
+            }
+        }
+    }
+
+    let mut rest = Rest {
+        start,
+        left: len,
+        node,
+    };
+    rest.drop_elements();
+}
+
+/// Drop an arc's contents at `data`, after calling its drop hook on them,
+/// if it has one.
+///
+/// The contents are still dropped if the hook panics.
+unsafe fn drop_data<T: ?Sized>(on_last_drop: Option<unsafe fn(NonNull<()>)>, data: *mut T) {
+    struct DropData<T: ?Sized>(*mut T);
+
+    impl<T: ?Sized> Drop for DropData<T> {
+        fn drop(&mut self) {
+            unsafe { drop_in_place(self.0) }
+        }
+    }
+
+    let data = DropData(data);
+    if let Some(on_last_drop) = on_last_drop {
+        on_last_drop(NonNull::new_unchecked(data.0.cast()));
+    }
+}
+
+/// The value of an `ArcInner`'s weak count while [`HeapArc::is_unique`]
+/// checks its strong count, which stops new `HeapWeak`s from being created
+/// in the meantime. It is only ever set while there are no `HeapWeak`s.
+#[cfg(not(feature = "thin-arc"))]
+const WEAK_LOCKED: usize = usize::MAX;
+#[cfg(feature = "thin-arc")]
+const WEAK_LOCKED: u32 = u32::MAX;
+
+/// Load a reference count, as a `usize`.
+#[inline(always)]
+// The cast is only a no-op without `thin-arc`
+#[allow(clippy::unnecessary_cast)]
+fn load_count(count: &RefCount) -> usize {
+    count.load(Ordering::SeqCst) as usize
+}
+
+/// Like `alloc::sync::Arc`, incrementing a reference count past this aborts.
+///
+/// This leaves enough headroom that, even if many threads increment it at
+/// once, the count can't wrap around before one of them notices.
+#[cfg(not(feature = "thin-arc"))]
+const MAX_REFCOUNT: usize = isize::MAX as usize;
+#[cfg(feature = "thin-arc")]
+const MAX_REFCOUNT: usize = i32::MAX as usize;
+
+/// Add one to a reference count, aborting if it exceeds `MAX_REFCOUNT`.
+#[inline(always)]
+// The cast is only a no-op without `thin-arc`
+#[allow(clippy::unnecessary_cast)]
+fn inc_count(count: &RefCount) {
+    let old = count.fetch_add(1, Ordering::SeqCst) as usize;
+    if old > MAX_REFCOUNT {
+        refcount_overflow();
+    }
+}
+
+/// Add `n` to a reference count, all at once.
+#[inline(always)]
+// The casts are only no-ops without `thin-arc`
+#[allow(clippy::unnecessary_cast)]
+fn add_count(count: &RefCount, n: usize) {
+    if n > MAX_REFCOUNT {
+        refcount_overflow();
+    }
+    let old = count.fetch_add(n as _, Ordering::SeqCst) as usize;
+    if old > MAX_REFCOUNT - n {
+        refcount_overflow();
+    }
+}
+
+/// Abort, as a reference count has overflowed.
+///
+/// Carrying on (or unwinding, which drops other handles) could free the
+/// contents while they are still in use. `core` has no way to abort, but
+/// panicking while already panicking does.
+#[cold]
+#[inline(never)]
+fn refcount_overflow() -> ! {
+    struct Abort;
+
+    impl Drop for Abort {
+        fn drop(&mut self) {
+            panic!("reference count overflow");
+        }
+    }
+
+    let _abort = Abort;
+    panic!("reference count overflow");
+}
+
+// NOTE: `repr(C)` is required so that the counts (and the drop hook) sit at
+// the same offsets in every `ArcInner<T>`, whatever `T` is:
+// `HeapDynArc::counts` reads those of an `ArcInner<U>` through an
+// `ArcInner<()>`, after `U` has been erased.
+// Without it, the compiler is free to reorder the fields differently
+// depending on the size, alignment, and niches of `T`.
+#[repr(C)]
 pub(crate) struct ArcInner<T> {
+    /// The number of `HeapArc`s
+    pub(crate) refcnt: RefCount,
+    /// The number of `HeapWeak`s, plus one held collectively by all of the
+    /// `HeapArc`s. The allocation is freed when this reaches zero.
+    pub(crate) weak: RefCount,
+    /// Called on the data right before it is dropped, see
+    /// `HeapArc::new_with_drop`. This is really a `fn(&mut T)`, erased so
+    /// that `HeapArc<T>` stays covariant in `T`.
+    pub(crate) on_last_drop: Option<unsafe fn(NonNull<()>)>,
+    #[cfg(feature = "debug-arc")]
+    pub(crate) location: &'static Location<'static>,
     pub(crate) data: T,
-    pub(crate) refcnt: AtomicUsize,
 }
 
 pub struct HeapArc<T> {
@@ -27,251 +254,3351 @@ pub struct HeapArc<T> {
     pub(crate) pd: PhantomData<Active<ArcInner<T>>>,
 }
 
+/// A [`HeapArc`] whose contents have been unsized, e.g. into a trait object.
+///
+/// Created from an arc of any concrete type with [`heap_arc_dyn!`], without
+/// reallocating. Like [`HeapDynBox`], the handle stores the layout of the
+/// node the arc was allocated as, as well as where its reference counts are,
+/// which no longer depends on a known type.
+///
+/// [`heap_arc_dyn!`]: crate::heap_arc_dyn
+pub struct HeapDynArc<T: ?Sized> {
+    pub(crate) ptr: NonNull<T>,
+    /// The `ArcInner`, only used to access the reference counts, which come
+    /// before the data whatever its type (thanks to `repr(C)`).
+    pub(crate) inner: NonNull<ArcInner<()>>,
+    pub(crate) node: NonNull<Active<()>>,
+    pub(crate) node_layout: Layout,
+}
+
+/// A uniquely owned box, that can later be turned into a [`HeapArc`]
+/// without moving its contents.
+///
+/// This is allocated with room for the arc's reference counts up front, so
+/// [`HeapSharableBox::into_arc`] just re-wraps the same allocation. It is
+/// otherwise used like a [`HeapBox`].
+pub struct HeapSharableBox<T> {
+    // Always has a strong count of one, and no weak references.
+    pub(crate) arc: HeapArc<T>,
+}
+
+/// A weak reference to the contents of a [`HeapArc`].
+///
+/// A `HeapWeak` keeps the allocation alive, but not the contained value. It
+/// must be [upgraded](HeapWeak::upgrade) to access the value, which fails if
+/// all of the `HeapArc`s have already been dropped.
+///
+/// Created with [`HeapArc::downgrade`].
+pub struct HeapWeak<T> {
+    pub(crate) ptr: NonNull<Active<ArcInner<T>>>,
+    pub(crate) pd: PhantomData<Active<ArcInner<T>>>,
+}
+
+/// A reference to some part of a [`HeapArc`]'s contents, which keeps the
+/// whole arc alive.
+///
+/// Created with [`HeapArc::map_ref`].
+pub struct HeapArcRef<U> {
+    pub(crate) ptr: NonNull<U>,
+    pub(crate) owner: ArcRefOwner,
+}
+
+/// A type-erased handle to the `HeapArc` backing a `HeapArcRef`
+#[derive(Clone, Copy)]
+pub(crate) struct ArcRefOwner {
+    // The leaked data pointer of the `HeapArc<T>`
+    data: NonNull<()>,
+    increment: unsafe fn(NonNull<()>),
+    release: unsafe fn(NonNull<()>),
+}
+
+/// Diagnostic information about a live [`HeapArc`], returned by
+/// [`HeapArc::debug_refcount_info`].
+#[cfg(feature = "debug-arc")]
+#[derive(Debug, Clone, Copy)]
+pub struct ArcDebugInfo {
+    /// The strong count at the time of the call.
+    pub count: usize,
+    /// Where the arc was allocated.
+    pub location: &'static Location<'static>,
+}
+
 /// An Anachro Heap Array Type
+///
+/// When the array is dropped, its elements are dropped in order, from first
+/// to last, like a slice. See [`HeapArray::drop_in_reverse`] for the
+/// opposite order.
 pub struct HeapArray<T> {
     pub(crate) ptr: NonNull<ActiveArr<T>>,
-    pub(crate) pd: PhantomData<Active<T>>,
+    // The live elements are `start..start + len` of the node's storage. This
+    // is the whole storage, unless elements have been removed from the front.
+    pub(crate) start: usize,
+    pub(crate) len: usize,
+    // NOTE: This is deliberately NOT `PhantomData<ActiveArr<T>>`: the
+    // `[T; 0]` in an `ActiveArr<T>` does not tell drop check that we own
+    // (and drop) some number of `T`s. `PhantomData<T>` does, and is
+    // covariant in `T`, like `alloc::boxed::Box<[T]>`.
+    pub(crate) pd: PhantomData<T>,
+}
+
+/// An iterator that moves the elements out of a [`HeapArray`] or a
+/// [`HeapFixedVec`].
+///
+/// Elements can be taken from either end. Dropping the iterator drops the
+/// elements that have not been taken yet, and frees the allocation.
+pub struct HeapArrayIntoIter<T> {
+    // `None` if this came from a vec that was never allocated
+    ptr: Option<NonNull<ActiveArr<T>>>,
+    // The elements that have not been taken yet are `head..tail` of the
+    // node's storage
+    head: usize,
+    tail: usize,
+    pd: PhantomData<T>,
+}
+
+/// An error from a [`HeapFixedVec`] operation that would have needed more
+/// room than the vec has, or referred to an element it does not have.
+///
+/// The vec is left unchanged by an operation that fails with this error,
+/// unless the operation says otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixedVecError {
+    /// The operation would need `requested` elements, more than the
+    /// `capacity` of the vec.
+    ///
+    /// If computing the number of elements needed overflowed, `requested`
+    /// is `usize::MAX`.
+    CapacityExceeded { requested: usize, capacity: usize },
+    /// `index` is past the end of the `len` initialized elements.
+    OutOfBounds { index: usize, len: usize },
+}
+
+/// An iterator didn't yield exactly as many items as the array being filled
+/// from it has room for.
+///
+/// Returned by [`AHeap::allocate_array_from_iter`], after dropping the items
+/// yielded so far and freeing the array.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IterLengthError {
+    /// The iterator ran out after `yielded` items.
+    TooShort { yielded: usize, capacity: usize },
+    /// The iterator had items left after `capacity` items.
+    TooLong { capacity: usize },
+}
+
+/// A range of elements was out of bounds, or ended before it started.
+///
+/// Returned by the `subslice` methods, e.g. [`HeapArray::subslice`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfBounds {
+    /// The start of the requested range (inclusive)
+    pub start: usize,
+    /// The end of the requested range (exclusive)
+    ///
+    /// If computing the end of the range overflowed, this is `usize::MAX`.
+    pub end: usize,
+    /// The number of elements that could have been accessed
+    pub len: usize,
+}
+
+/// An Anachro Heap Shared Array Type
+///
+/// Like a [`HeapArray`], but reference counted like a [`HeapArc`]. Cloning
+/// the handle bumps the count, and the elements are dropped (and the
+/// allocation freed) when the last handle is dropped.
+pub struct HeapArcArray<T> {
+    pub(crate) ptr: NonNull<ActiveArr<T>>,
+    pub(crate) pd: PhantomData<T>,
+}
+
+/// A view of part of a [`HeapArcArray`], which keeps the whole array alive.
+///
+/// Created with [`HeapArcArray::slice`].
+pub struct HeapArcSlice<T> {
+    arr: HeapArcArray<T>,
+    range: Range<usize>,
 }
 
 /// An Anachro Heap Array Type
 pub struct HeapFixedVec<T> {
+    // This is dangling if the storage has not been allocated yet, see
+    // `HeapFixedVec::new_lazy`.
     pub(crate) ptr: NonNull<ActiveArr<MaybeUninit<T>>>,
     pub(crate) len: usize,
-    pub(crate) pd: PhantomData<Active<MaybeUninit<T>>>,
+    // The capacity to allocate on the first push, if `ptr` is dangling.
+    pub(crate) lazy_capacity: usize,
+    // NOTE: The storage is made of `MaybeUninit<T>`s, but the first `len` of
+    // them are live `T`s that we drop, so tell drop check that we own `T`s,
+    // like `alloc::vec::Vec<T>`.
+    pub(crate) pd: PhantomData<T>,
+}
+
+/// A growable vec, like `alloc::vec::Vec`.
+///
+/// The elements are stored in a [`HeapFixedVec`], which is replaced with a
+/// larger one (moving the elements) when it runs out of room. Since growing
+/// may need to wait for memory, [`HeapVec::push`] is async, and takes the
+/// heap to allocate from.
+pub struct HeapVec<T> {
+    pub(crate) inner: HeapFixedVec<T>,
 }
 
+/// Builds a [`HeapArray`] one element at a time, in an allocation of an
+/// exact, fixed capacity.
+///
+/// Created with [`HeapGuard::alloc_array_builder`].
+pub struct HeapArrayBuilder<T> {
+    pub(crate) vec: HeapFixedVec<T>,
+}
+
+/// Either a borrowed `T`, or a `T` owned in a [`HeapBox`], like
+/// `alloc::borrow::Cow`.
+///
+/// This avoids allocating until the value actually needs to be mutated, see
+/// [`HeapCow::to_mut`].
+pub enum HeapCow<'a, T> {
+    Borrowed(&'a T),
+    Owned(HeapBox<T>),
+}
+
+/// A [`HeapArc`] that is compared and hashed by the address of its
+/// allocation, rather than by its contents.
+///
+/// This allows using arcs as keys based on their identity, even when `T`
+/// itself is not `Eq` or `Hash`. Two `ByAddress`es are equal exactly when
+/// [`HeapArc::ptr_eq`] is true for the arcs they wrap.
+pub struct ByAddress<A>(pub A);
+
+/// Forwards the comparison traits, and `Hash`, of each container to its
+/// contents, as `alloc::boxed::Box` does.
+///
+/// Containers are compared (and hashed) by value, never by address. Arcs
+/// can be compared by identity with [`HeapArc::ptr_eq`], or with
+/// [`ByAddress`].
+macro_rules! forward_cmp {
+    ($($ty:ident),* $(,)?) => {
+        $(
+            impl<T: PartialEq> PartialEq for $ty<T> {
+                #[inline]
+                fn eq(&self, other: &Self) -> bool {
+                    **self == **other
+                }
+            }
+
+            impl<T: Eq> Eq for $ty<T> {}
+
+            impl<T: PartialOrd> PartialOrd for $ty<T> {
+                #[inline]
+                fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+                    (**self).partial_cmp(&**other)
+                }
+            }
+
+            impl<T: Ord> Ord for $ty<T> {
+                #[inline]
+                fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+                    (**self).cmp(&**other)
+                }
+            }
+
+            impl<T: Hash> Hash for $ty<T> {
+                #[inline]
+                fn hash<H: Hasher>(&self, state: &mut H) {
+                    (**self).hash(state)
+                }
+            }
+        )*
+    };
+}
+
+forward_cmp!(
+    HeapBox,
+    HeapAlignedBox,
+    HeapSharableBox,
+    HeapArc,
+    HeapArray,
+    HeapArcArray,
+    HeapArcSlice,
+    HeapFixedVec,
+    HeapVec,
+);
+
 // === impl HeapBox ===
 
 unsafe impl<T: Send> Send for HeapBox<T> {}
 unsafe impl<T: Sync> Sync for HeapBox<T> {}
 
+// Like `alloc::boxed::Box`, moving a `HeapBox` never moves the pointee, so it
+// is always `Unpin`, regardless of `T`.
+impl<T> Unpin for HeapBox<T> {}
+
+impl<T> From<HeapBox<T>> for Pin<HeapBox<T>> {
+    /// See [`HeapBox::into_pin`].
+    fn from(boxed: HeapBox<T>) -> Self {
+        HeapBox::into_pin(boxed)
+    }
+}
+
+impl<F: Future + Unpin> Future for HeapBox<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        F::poll(Pin::new(&mut **self), cx)
+    }
+}
+
 impl<T> Deref for HeapBox<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
         unsafe { &*Active::<T>::data(self.ptr).as_ptr() }
     }
-}
+}
+
+impl<T> DerefMut for HeapBox<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *Active::<T>::data(self.ptr).as_ptr() }
+    }
+}
+
+impl<T> HeapBox<T> {
+    /// The bytes each box's allocation takes up beyond the `T` itself.
+    ///
+    /// This covers the node's header and padding, but not any rounding up
+    /// to the heap's allocation granularity. It can be checked at compile
+    /// time, e.g. `const _: () = assert!(HeapBox::<Frame>::OVERHEAD_BYTES <= 16);`.
+    pub const OVERHEAD_BYTES: usize = Active::<T>::OVERHEAD_BYTES;
+
+    /// Allocate a new box on the current heap.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no current heap has been set with [`AHeap::set_current`].
+    #[track_caller]
+    pub fn new(value: T) -> impl Future<Output = HeapBox<T>> {
+        AHeap::expect_current().allocate(value)
+    }
+
+    /// Allocate a box with uninitialized contents on the current heap.
+    ///
+    /// This never places a `T` on the stack, so it can be used to build up
+    /// structs too large for the stack in place.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no current heap has been set with [`AHeap::set_current`].
+    #[track_caller]
+    pub fn new_uninit() -> impl Future<Output = HeapBox<MaybeUninit<T>>> {
+        AHeap::expect_current().allocate_uninit()
+    }
+
+    pub unsafe fn from_leaked(ptr: NonNull<T>) -> Self {
+        Self {
+            ptr: Active::<T>::from_leaked_ptr(ptr),
+            pd: PhantomData,
+        }
+    }
+
+    /// Pin this box's contents in place.
+    ///
+    /// This is always sound, as the contents of a `HeapBox` are never moved
+    /// until they are dropped.
+    pub fn into_pin(this: Self) -> Pin<Self> {
+        unsafe { Pin::new_unchecked(this) }
+    }
+
+    /// Move the contents out of the box, and free its allocation.
+    ///
+    /// This is an associated function (`HeapBox::into_inner(b)`), so that it
+    /// doesn't shadow methods of `T` with the same name.
+    pub fn into_inner(this: Self) -> T {
+        let this = ManuallyDrop::new(this);
+        unsafe {
+            let value = Active::<T>::data(this.ptr).as_ptr().read();
+            // The contents were moved out, so must not be dropped here
+            Active::<T>::yeet(this.ptr);
+            value
+        }
+    }
+
+    /// Leak the contents of this box, never to be recovered (probably)
+    pub fn leak(self) -> NonNull<T> {
+        let nn = unsafe { Active::<T>::data(self.ptr) };
+        forget(self);
+        nn
+    }
+
+    /// Reinterpret the box as a box of a `U`, reusing the same allocation,
+    /// e.g. to unwrap a `#[repr(transparent)]` newtype, or to wrap one.
+    ///
+    /// Nothing is dropped or moved: the contents are just treated as a `U`
+    /// from now on, and dropped as one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` and `U` differ in size or alignment, as the allocation
+    /// would then be freed with the wrong layout.
+    ///
+    /// # Safety
+    ///
+    /// The contents must be a valid `U`, as with `core::mem::transmute`.
+    pub unsafe fn cast<U>(self) -> HeapBox<U> {
+        assert!(
+            Layout::new::<T>() == Layout::new::<U>(),
+            "can't cast a box to a type with a different layout"
+        );
+        let ptr = self.ptr.cast::<Active<U>>();
+        forget(self);
+        HeapBox {
+            ptr,
+            pd: PhantomData,
+        }
+    }
+
+    /// Returns whether the two boxes are backed by the same allocation.
+    ///
+    /// Unlike `==`, this compares the boxes' identities, not their contents.
+    /// Two live boxes never share an allocation, so this is only `true` for
+    /// a box compared with itself, or with a box wrongly reconstructed from
+    /// the same leaked pointer, which makes it mostly useful in assertions.
+    pub fn same_allocation(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+
+    /// The layout of the block that the heap allocated for this box.
+    ///
+    /// This includes the allocator's per-allocation header, and any rounding
+    /// applied by the heap, so it is larger than `Layout::new::<T>()`.
+    pub fn layout(&self) -> Layout {
+        unsafe { Active::<T>::alloc_layout(self.ptr) }
+    }
+
+    /// Replace the contents of the box with the result of calling `f` on the
+    /// current contents, in place.
+    ///
+    /// The contents stay at the same address, and no allocation is needed.
+    ///
+    /// ## Panics
+    ///
+    /// If `f` panics, the box would be left without any contents, so the
+    /// process is aborted instead (by panicking again while unwinding).
+    pub fn replace_with<F: FnOnce(T) -> T>(&mut self, f: F) {
+        struct AbortOnUnwind;
+
+        impl Drop for AbortOnUnwind {
+            fn drop(&mut self) {
+                panic!("`HeapBox::replace_with` closure panicked, aborting");
+            }
+        }
+
+        unsafe {
+            let slot = Active::<T>::data(self.ptr).as_ptr();
+            let bomb = AbortOnUnwind;
+            let new = f(slot.read());
+            forget(bomb);
+            slot.write(new);
+        }
+    }
+
+    /// Transform the contents of the box with `f`, producing a box of the
+    /// result, or the error returned by `f`.
+    ///
+    /// If a `U` fits in the same allocation as a `T`, the allocation is
+    /// reused. Otherwise, the old allocation is freed before `f` is called,
+    /// and a new one is allocated from `heap` for the result.
+    ///
+    /// Either way, the contents are moved into `f`, so if it returns an
+    /// error (or panics), they are gone, and the allocation is freed.
+    pub async fn try_map<U, E, F>(self, heap: &'static AHeap, f: F) -> Result<HeapBox<U>, E>
+    where
+        F: FnOnce(T) -> Result<U, E>,
+    {
+        // Frees the allocation if `f` panics
+        struct FreeOnUnwind<T>(NonNull<Active<T>>);
+
+        impl<T> Drop for FreeOnUnwind<T> {
+            fn drop(&mut self) {
+                unsafe { Active::<T>::yeet(self.0) }
+            }
+        }
+
+        let same_node = Layout::new::<Node<T>>() == Layout::new::<Node<U>>()
+            && Active::<T>::DATA_OFFSET == Active::<U>::DATA_OFFSET;
+        let ptr = self.ptr;
+        forget(self);
+        let value = unsafe { Active::<T>::data(ptr).as_ptr().read() };
+
+        if !same_node {
+            unsafe { Active::<T>::yeet(ptr) };
+            return Ok(heap.allocate(f(value)?).await);
+        }
+
+        let guard = FreeOnUnwind(ptr);
+        let res = f(value);
+        forget(guard);
+        match res {
+            Ok(new) => unsafe {
+                let ptr = ptr.cast::<Active<U>>();
+                Active::<U>::data(ptr).as_ptr().write(new);
+                Ok(HeapBox {
+                    ptr,
+                    pd: PhantomData,
+                })
+            },
+            Err(e) => {
+                unsafe { Active::<T>::yeet(ptr) };
+                Err(e)
+            }
+        }
+    }
+
+    /// Convert this box into a pointer-sized token, which can be turned back
+    /// into a box (exactly once) with [`HeapBox::from_token`].
+    pub fn into_token(self) -> HeapBoxToken<T> {
+        HeapBoxToken { ptr: self.leak() }
+    }
+
+    /// Re-create a box from a token returned by [`HeapBox::into_token`].
+    pub fn from_token(token: HeapBoxToken<T>) -> Self {
+        let ptr = token.ptr;
+        forget(token);
+        unsafe { Self::from_leaked(ptr) }
+    }
+}
+
+impl<T: Clone> HeapBox<T> {
+    /// Overwrite the contents of this box with a clone of `src`.
+    ///
+    /// This reuses the existing allocation (via [`Clone::clone_from`]), so
+    /// the address of the boxed value does not change.
+    pub fn clone_from_value(&mut self, src: &T) {
+        T::clone_from(&mut **self, src);
+    }
+
+    /// Allocate a new box holding a clone of the contents, waiting for space
+    /// to become available if necessary.
+    ///
+    /// NOTE: `HeapBox` doesn't implement `Clone`, as allocating needs a heap
+    /// to allocate from, and may need to wait for space or fail.
+    pub async fn clone_in(&self, heap: &'static AHeap) -> HeapBox<T> {
+        heap.allocate((**self).clone()).await
+    }
+
+    /// Attempt to allocate a new box holding a clone of the contents.
+    pub fn try_clone(&self, guard: &mut HeapGuard) -> Result<Self, AllocError> {
+        // Clone straight into the new allocation. If cloning panics, the
+        // (uninitialized) box is just freed.
+        let mut new = guard.alloc_uninit::<T>()?;
+        new.write((**self).clone());
+        Ok(unsafe { new.assume_init() })
+    }
+}
+
+impl<T> HeapBox<MaybeUninit<T>> {
+    /// Convert a box from [`HeapBox::new_uninit`] into a `HeapBox<T>`, once
+    /// its contents have been written, without moving them.
+    ///
+    /// Dropping the box without calling this never drops the contents, as
+    /// with any `MaybeUninit`, but still frees the allocation.
+    ///
+    /// # Safety
+    ///
+    /// The contents must have been initialized.
+    pub unsafe fn assume_init(self) -> HeapBox<T> {
+        // `MaybeUninit<T>` has the same layout as `T`, so this is the same
+        // kind of node
+        let ptr = self.ptr.cast::<Active<T>>();
+        forget(self);
+        HeapBox {
+            ptr,
+            pd: PhantomData,
+        }
+    }
+}
+
+impl<T, const N: usize> HeapBox<[MaybeUninit<T>; N]> {
+    /// Initialize each element of the array in place, with `f` called with
+    /// the index of each element in order.
+    ///
+    /// If `f` panics, the elements initialized so far are dropped, and the
+    /// allocation is freed.
+    pub fn init_with<F: FnMut(usize) -> T>(mut self, mut f: F) -> HeapBox<[T; N]> {
+        // Drops the initialized prefix if `f` panics
+        struct InitGuard<'a, T> {
+            slots: &'a mut [MaybeUninit<T>],
+            init: usize,
+        }
+
+        impl<T> Drop for InitGuard<'_, T> {
+            fn drop(&mut self) {
+                for slot in self.slots[..self.init].iter_mut() {
+                    unsafe { slot.assume_init_drop() }
+                }
+            }
+        }
+
+        let mut guard = InitGuard {
+            slots: &mut self[..],
+            init: 0,
+        };
+        while guard.init < N {
+            let item = f(guard.init);
+            guard.slots[guard.init].write(item);
+            guard.init += 1;
+        }
+        forget(guard);
+
+        // SAFETY: All elements are initialized, and `[MaybeUninit<T>; N]` has
+        // the same layout as `[T; N]`.
+        let ptr = self.ptr.cast::<Active<[T; N]>>();
+        forget(self);
+        HeapBox {
+            ptr,
+            pd: PhantomData,
+        }
+    }
+}
+
+impl<T> Drop for HeapBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let item_ptr = Active::<T>::data(self.ptr).as_ptr();
+            drop_in_place(item_ptr);
+            Active::<T>::yeet(self.ptr);
+        }
+    }
+}
+
+/// Compares the contents of the box with `other`.
+impl<T: PartialEq> PartialEq<&T> for HeapBox<T> {
+    #[inline]
+    fn eq(&self, other: &&T) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for HeapBox<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for HeapBox<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> fmt::Pointer for HeapBox<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&self.ptr, f)
+    }
+}
+
+impl<T> AsRef<T> for HeapBox<T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T> AsMut<T> for HeapBox<T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+impl<T> Borrow<T> for HeapBox<T> {
+    #[inline]
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T> BorrowMut<T> for HeapBox<T> {
+    #[inline]
+    fn borrow_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+// === impl HeapArrayBuilder ===
+
+impl<T> HeapArrayBuilder<T> {
+    /// Append an element, returning it if the builder is already full.
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        self.vec.push(item)
+    }
+
+    /// The number of elements pushed so far.
+    pub fn len(&self) -> usize {
+        self.vec.len()
+    }
+
+    /// Returns whether no elements have been pushed yet.
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+
+    /// The number of elements the finished array will hold.
+    pub fn capacity(&self) -> usize {
+        self.vec.capacity()
+    }
+
+    /// Returns whether every element has been pushed.
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    /// Finish building the array, if every element has been pushed.
+    ///
+    /// Otherwise, the builder is handed back.
+    pub fn build(self) -> Result<HeapArray<T>, Self> {
+        if !self.is_full() {
+            return Err(self);
+        }
+        Ok(self.vec.into_initialized_array())
+    }
+
+    /// Finish building the array, with only the elements pushed so far.
+    ///
+    /// NOTE: The unused capacity is NOT returned to the heap until the array
+    /// is dropped, see [`HeapArrayBuilder::build_exact`].
+    pub fn build_partial(self) -> HeapArray<T> {
+        self.vec.into_initialized_array()
+    }
+
+    /// Finish building the array, with only the elements pushed so far, in
+    /// an allocation of exactly that many elements.
+    ///
+    /// If the builder is full, this doesn't allocate at all. Otherwise, the
+    /// elements are moved into a new, exactly sized, array, waiting for space
+    /// to become available if necessary, and the original node is freed.
+    pub async fn build_exact(mut self, heap: &'static AHeap) -> HeapArray<T> {
+        if self.is_full() {
+            return self.vec.into_initialized_array();
+        }
+
+        let len = self.len();
+        let new = heap
+            .allocate_array_with(MaybeUninit::<T>::uninit, len)
+            .await;
+        unsafe {
+            let (src, _count) = self.vec.storage();
+            let (dst, _count) = ActiveArr::<MaybeUninit<T>>::data(new.ptr);
+            core::ptr::copy_nonoverlapping(src.as_ptr(), dst.as_ptr(), len);
+        }
+        // The elements were moved out, only free the original node
+        self.vec.len = 0;
+        drop(self);
+
+        let ptr = new.ptr.cast::<ActiveArr<T>>();
+        forget(new);
+        HeapArray {
+            ptr,
+            start: 0,
+            len,
+            pd: PhantomData,
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for HeapArrayBuilder<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeapArrayBuilder")
+            .field("items", &&*self.vec)
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+// === impl HeapCow ===
+
+impl<T> HeapCow<'_, T> {
+    /// Returns whether the value is borrowed.
+    pub fn is_borrowed(&self) -> bool {
+        matches!(self, HeapCow::Borrowed(_))
+    }
+
+    /// Returns whether the value is owned.
+    pub fn is_owned(&self) -> bool {
+        matches!(self, HeapCow::Owned(_))
+    }
+}
+
+impl<T: Clone> HeapCow<'_, T> {
+    /// Obtain a mutable reference to the value, first cloning it into a new
+    /// allocation if it is borrowed.
+    ///
+    /// Returns `None` (leaving the value borrowed) if the allocation failed.
+    pub fn to_mut(&mut self, guard: &mut HeapGuard) -> Option<&mut T> {
+        if let HeapCow::Borrowed(borrowed) = *self {
+            let owned = guard.alloc_box(borrowed.clone()).ok()?;
+            *self = HeapCow::Owned(owned);
+        }
+        match self {
+            HeapCow::Owned(owned) => Some(&mut **owned),
+            HeapCow::Borrowed(_) => unreachable!(),
+        }
+    }
+}
+
+impl<T> Deref for HeapCow<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            HeapCow::Borrowed(borrowed) => borrowed,
+            HeapCow::Owned(owned) => owned,
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for HeapCow<'_, T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+// === impl ByAddress ===
+
+impl<T> ByAddress<HeapArc<T>> {
+    /// Unwrap the arc.
+    pub fn into_inner(self) -> HeapArc<T> {
+        self.0
+    }
+}
+
+impl<T> Deref for ByAddress<HeapArc<T>> {
+    type Target = HeapArc<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> Clone for ByAddress<HeapArc<T>> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> PartialEq for ByAddress<HeapArc<T>> {
+    fn eq(&self, other: &Self) -> bool {
+        HeapArc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T> Eq for ByAddress<HeapArc<T>> {}
+
+impl<T> Hash for ByAddress<HeapArc<T>> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.ptr.hash(state)
+    }
+}
+
+impl<T> fmt::Debug for ByAddress<HeapArc<T>> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ByAddress").field(&self.0.ptr).finish()
+    }
+}
+
+// === impl HeapAlignedBox ===
+
+unsafe impl<T: Send> Send for HeapAlignedBox<T> {}
+unsafe impl<T: Sync> Sync for HeapAlignedBox<T> {}
+
+impl<T> Unpin for HeapAlignedBox<T> {}
+
+impl<T> HeapAlignedBox<T> {
+    /// The layout the contents were allocated with.
+    ///
+    /// This has the size of a `T`, and the requested alignment (or that of
+    /// `T`, if it is larger).
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+}
+
+impl<T> Deref for HeapAlignedBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for HeapAlignedBox<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for HeapAlignedBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            drop_in_place(self.ptr.as_ptr());
+            crate::heap::deallocate_raw(self.ptr.cast(), self.layout);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for HeapAlignedBox<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+// === impl HeapDynBox ===
+
+unsafe impl<T: ?Sized + Send> Send for HeapDynBox<T> {}
+unsafe impl<T: ?Sized + Sync> Sync for HeapDynBox<T> {}
+
+// Like `HeapBox`, moving the handle never moves the pointee.
+impl<T: ?Sized> Unpin for HeapDynBox<T> {}
+
+/// Unsize a [`HeapBox`] into a [`HeapDynBox`], e.g.
+/// `heap_box_dyn!(boxed => dyn Command)`.
+///
+/// The conversion is an implicit coercion, so it only compiles if the
+/// contents of the box can be unsized to the given type.
+#[macro_export]
+macro_rules! heap_box_dyn {
+    ($boxed:expr => $ty:ty) => {{
+        // Evaluate the argument outside of the `unsafe` block, so that it
+        // can't call unsafe functions without the caller noticing
+        let boxed = $boxed;
+        // SAFETY: The pointer is only ever coerced, which can only unsize it
+        unsafe { $crate::containers::HeapDynBox::from_box(boxed, |ptr| -> *mut $ty { ptr }) }
+    }};
+}
+
+impl<T: ?Sized> HeapDynBox<T> {
+    /// Unsize the contents of `boxed` with `coerce`, without reallocating.
+    ///
+    /// Prefer [`heap_box_dyn!`](crate::heap_box_dyn), which does this safely.
+    ///
+    /// ## Safety
+    ///
+    /// `coerce` must return the pointer it is given, only unsized, e.g.
+    /// `|ptr| ptr as *mut dyn Command`.
+    pub unsafe fn from_box<U>(boxed: HeapBox<U>, coerce: impl FnOnce(*mut U) -> *mut T) -> Self {
+        let data = Active::<U>::data(boxed.ptr).as_ptr();
+        let ptr = coerce(data);
+        debug_assert_eq!(ptr.cast::<U>(), data);
+        let node = boxed.ptr.cast();
+        forget(boxed);
+        Self {
+            ptr: NonNull::new_unchecked(ptr),
+            node,
+            node_layout: Layout::new::<Node<U>>(),
+        }
+    }
+}
+
+impl<T: ?Sized> HeapDynBox<T> {
+    /// Pin this box's contents in place.
+    ///
+    /// This is always sound, for the same reasons as [`HeapBox::into_pin`].
+    /// A `Pin<HeapDynBox<dyn Future<Output = T>>>` is a future itself.
+    pub fn into_pin(this: Self) -> Pin<Self> {
+        unsafe { Pin::new_unchecked(this) }
+    }
+}
+
+impl<F: ?Sized + Future + Unpin> Future for HeapDynBox<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        F::poll(Pin::new(&mut **self), cx)
+    }
+}
+
+impl<T: ?Sized> Deref for HeapDynBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for HeapDynBox<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T: ?Sized> Drop for HeapDynBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            drop_in_place(self.ptr.as_ptr());
+            Active::yeet_erased(self.node, self.node_layout);
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for HeapDynBox<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+// === impl HeapDynArc ===
+
+// The same bounds as `HeapArc`'s
+unsafe impl<T: ?Sized + Send + Sync> Send for HeapDynArc<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for HeapDynArc<T> {}
+
+impl<T: ?Sized> Unpin for HeapDynArc<T> {}
+
+/// Unsize a [`HeapArc`] into a [`HeapDynArc`], e.g.
+/// `heap_arc_dyn!(arc => dyn Sensor)`.
+///
+/// Like [`heap_box_dyn!`](crate::heap_box_dyn), the conversion is an
+/// implicit coercion, so it only compiles if the contents of the arc can be
+/// unsized to the given type.
+#[macro_export]
+macro_rules! heap_arc_dyn {
+    ($arc:expr => $ty:ty) => {{
+        // Evaluate the argument outside of the `unsafe` block, so that it
+        // can't call unsafe functions without the caller noticing
+        let arc = $arc;
+        // SAFETY: The pointer is only ever coerced, which can only unsize it
+        unsafe { $crate::containers::HeapDynArc::from_arc(arc, |ptr| -> *mut $ty { ptr }) }
+    }};
+}
+
+impl<T: ?Sized> HeapDynArc<T> {
+    /// Unsize the contents of `arc` with `coerce`, without reallocating.
+    ///
+    /// The strong reference held by `arc` is taken over by the new handle.
+    /// Any [`HeapWeak`]s of the original type remain valid, though they can
+    /// only be upgraded to a `HeapArc<U>`.
+    ///
+    /// Prefer [`heap_arc_dyn!`](crate::heap_arc_dyn), which does this safely.
+    ///
+    /// ## Safety
+    ///
+    /// `coerce` must return the pointer it is given, only unsized, e.g.
+    /// `|ptr| ptr as *mut dyn Sensor`.
+    pub unsafe fn from_arc<U>(arc: HeapArc<U>, coerce: impl FnOnce(*mut U) -> *mut T) -> Self {
+        let aiptr = Active::<ArcInner<U>>::data(arc.ptr).as_ptr();
+        let data = addr_of_mut!((*aiptr).data);
+        let ptr = coerce(data);
+        debug_assert_eq!(ptr.cast::<U>(), data);
+        let node = arc.ptr.cast();
+        forget(arc);
+        Self {
+            ptr: NonNull::new_unchecked(ptr),
+            inner: NonNull::new_unchecked(aiptr.cast()),
+            node,
+            node_layout: Layout::new::<Node<ArcInner<U>>>(),
+        }
+    }
+
+    /// The number of `HeapDynArc`s (and `HeapArc`s) pointing to this
+    /// allocation.
+    pub fn strong_count(this: &Self) -> usize {
+        load_count(&this.counts().refcnt)
+    }
+
+    /// Returns whether the two arcs point to the same allocation.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.inner == other.inner
+    }
+
+    fn counts(&self) -> &ArcInner<()> {
+        // SAFETY: The counts of an `ArcInner<U>` are laid out the same as
+        // those of an `ArcInner<()>`, and the `()` takes up no space.
+        unsafe { self.inner.as_ref() }
+    }
+}
+
+impl<T: ?Sized> Deref for HeapDynArc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: ?Sized> Clone for HeapDynArc<T> {
+    fn clone(&self) -> Self {
+        inc_count(&self.counts().refcnt);
+        Self {
+            ptr: self.ptr,
+            inner: self.inner,
+            node: self.node,
+            node_layout: self.node_layout,
+        }
+    }
+}
+
+impl<T: ?Sized> Drop for HeapDynArc<T> {
+    fn drop(&mut self) {
+        // See `HeapArc::drop` and `ArcInner::release_weak`
+        let old = self.counts().refcnt.fetch_sub(1, Ordering::Release);
+        debug_assert_ne!(old, 0);
+        if old != 1 {
+            return;
+        }
+        fence(Ordering::Acquire);
+        unsafe { drop_data(self.counts().on_last_drop, self.ptr.as_ptr()) };
+
+        // Release the weak reference held by all the strong ones
+        let old = self.counts().weak.fetch_sub(1, Ordering::Release);
+        debug_assert_ne!(old, 0);
+        if old == 1 {
+            fence(Ordering::Acquire);
+            unsafe { Active::yeet_erased(self.node, self.node_layout) };
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for HeapDynArc<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+// === impl HeapBoxToken ===
+
+// The token owns the box, so it has the same bounds as `HeapBox`.
+unsafe impl<T: Send> Send for HeapBoxToken<T> {}
+unsafe impl<T: Sync> Sync for HeapBoxToken<T> {}
+
+impl<T> Drop for HeapBoxToken<T> {
+    fn drop(&mut self) {
+        drop(unsafe { HeapBox::from_leaked(self.ptr) });
+    }
+}
+
+impl<T> fmt::Debug for HeapBoxToken<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("HeapBoxToken").field(&self.ptr).finish()
+    }
+}
+
+// === impl HeapSharableBox ===
+
+unsafe impl<T: Send> Send for HeapSharableBox<T> {}
+unsafe impl<T: Sync> Sync for HeapSharableBox<T> {}
+
+impl<T> Unpin for HeapSharableBox<T> {}
+
+impl<T> Deref for HeapSharableBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.arc
+    }
+}
+
+impl<T> DerefMut for HeapSharableBox<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: We are the only reference to the arc, strong or weak.
+        unsafe {
+            let aitem_ptr = Active::<ArcInner<T>>::data(self.arc.ptr).as_ptr();
+            &mut *addr_of_mut!((*aitem_ptr).data)
+        }
+    }
+}
+
+impl<T> HeapSharableBox<T> {
+    /// Turn this box into a [`HeapArc`], without moving the contents.
+    pub fn into_arc(self) -> HeapArc<T> {
+        self.arc
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for HeapSharableBox<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+// === impl ArcInner ===
+
+impl<T> ArcInner<T> {
+    /// Like `Active::OVERHEAD_BYTES`, but also counting the reference counts.
+    pub(crate) const OVERHEAD_BYTES: usize =
+        core::mem::size_of::<Node<ArcInner<T>>>() - core::mem::size_of::<T>();
+
+    pub unsafe fn from_leaked_ptr(data: NonNull<T>) -> NonNull<ArcInner<T>> {
+        debug_assert!(data.as_ptr().is_aligned(), "leaked pointer is misaligned");
+        let ptr = data
+            .cast::<u8>()
+            .as_ptr()
+            .sub(offset_of!(ArcInner<T>, data))
+            .cast::<ArcInner<T>>();
+        debug_assert!(ptr.is_aligned(), "leaked pointer is not from a `HeapArc`");
+        NonNull::new_unchecked(ptr)
+    }
+
+    /// Release one weak reference, freeing the allocation if it was the last.
+    ///
+    /// The contained data MUST have already been dropped.
+    unsafe fn release_weak(ptr: NonNull<Active<ArcInner<T>>>) {
+        let aitem_ptr = Active::<ArcInner<T>>::data(ptr).as_ptr();
+        let old = (*aitem_ptr).weak.fetch_sub(1, Ordering::Release);
+        debug_assert_ne!(old, 0);
+        if old == 1 {
+            // See `HeapArc::drop`
+            fence(Ordering::Acquire);
+            Active::<ArcInner<T>>::yeet(ptr);
+        }
+    }
+}
+
+// === impl HeapArc ===
+
+// These require the same bounds as `alloc::sync::Arc`'s `Send` and `Sync`
+// impls.
+unsafe impl<T: Send + Sync> Send for HeapArc<T> {}
+unsafe impl<T: Send + Sync> Sync for HeapArc<T> {}
+
+// Moving a `HeapArc` never moves the pointee.
+impl<T> Unpin for HeapArc<T> {}
+
+impl<T> HeapArc<T> {
+    /// The bytes each arc's allocation takes up beyond the `T` itself,
+    /// including the reference counts, see [`HeapBox::OVERHEAD_BYTES`].
+    pub const OVERHEAD_BYTES: usize = ArcInner::<T>::OVERHEAD_BYTES;
+
+    /// Allocate a new arc on the current heap.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no current heap has been set with [`AHeap::set_current`].
+    #[track_caller]
+    pub fn new(value: T) -> impl Future<Output = HeapArc<T>> {
+        AHeap::expect_current().allocate_arc(value)
+    }
+
+    /// Allocate a new arc on the current heap, which calls `on_last_drop` on
+    /// the value right before it is dropped, when the last arc to it is
+    /// dropped.
+    ///
+    /// This runs extra cleanup, such as closing a hardware resource, without
+    /// having to implement `Drop` for `T` (which may be a foreign type). The
+    /// function is not called if the contents are moved out instead, e.g.
+    /// with [`HeapArc::try_unwrap`]. Use [`AHeap::allocate_arc_with_drop`] to
+    /// allocate on a specific heap.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no current heap has been set with [`AHeap::set_current`].
+    #[track_caller]
+    pub fn new_with_drop(value: T, on_last_drop: fn(&mut T)) -> impl Future<Output = HeapArc<T>> {
+        AHeap::expect_current().allocate_arc_with_drop(value, on_last_drop)
+    }
+
+    /// Allocate a new pinned arc on the current heap.
+    ///
+    /// See [`HeapArc::into_pin`] for details.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no current heap has been set with [`AHeap::set_current`].
+    #[track_caller]
+    pub fn pin(value: T) -> impl Future<Output = Pin<HeapArc<T>>> {
+        let alloc = Self::new(value);
+        async move { Self::into_pin(alloc.await) }
+    }
+
+    /// Pin this arc's contents in place.
+    ///
+    /// This is sound because the contents of a `HeapArc` live in a single
+    /// heap node which is never moved: every clone points at the same node,
+    /// and the contents are only dropped (in place) once the last clone is
+    /// dropped. `HeapArc` only ever hands out shared references to its
+    /// contents, so the pinned form only gives access to a `Pin<&T>` (through
+    /// [`Pin::as_ref`]), and there is no way to move (or mutably access) the
+    /// contents once pinned.
+    ///
+    /// NOTE: Weak references and leaked pointers to a pinned arc must not be
+    /// used to move the contents out either.
+    pub fn into_pin(this: Self) -> Pin<Self> {
+        unsafe { Pin::new_unchecked(this) }
+    }
+
+    /// Create a new [`HeapWeak`] reference to this arc's contents.
+    // The cast is only a no-op without `thin-arc`
+    #[allow(clippy::unnecessary_cast)]
+    pub fn downgrade(this: &Self) -> HeapWeak<T> {
+        let weak = unsafe { &Active::<ArcInner<T>>::data(this.ptr).as_ref().weak };
+        let mut cur = weak.load(Ordering::SeqCst);
+        loop {
+            // `is_unique` is checking the strong count, which doesn't take
+            // long, so wait for it to finish.
+            if cur == WEAK_LOCKED {
+                hint::spin_loop();
+                cur = weak.load(Ordering::SeqCst);
+                continue;
+            }
+            if cur as usize > MAX_REFCOUNT {
+                refcount_overflow();
+            }
+            match weak.compare_exchange_weak(cur, cur + 1, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => break,
+                Err(actual) => cur = actual,
+            }
+        }
+        HeapWeak {
+            ptr: this.ptr,
+            pd: PhantomData,
+        }
+    }
+
+    /// The number of `HeapArc`s pointing to this allocation.
+    pub fn strong_count(this: &Self) -> usize {
+        unsafe {
+            let aitem_nn = Active::<ArcInner<T>>::data(this.ptr);
+            load_count(&aitem_nn.as_ref().refcnt)
+        }
+    }
+
+    /// The number of [`HeapWeak`]s pointing to this allocation.
+    // The cast is only a no-op without `thin-arc`
+    #[allow(clippy::unnecessary_cast)]
+    pub fn weak_count(this: &Self) -> usize {
+        unsafe {
+            let aitem_nn = Active::<ArcInner<T>>::data(this.ptr);
+            match load_count(&aitem_nn.as_ref().weak) {
+                // Only locked while there are no weak references
+                weak if weak == WEAK_LOCKED as usize => 0,
+                // Don't count the weak reference held by the strong ones
+                weak => weak - 1,
+            }
+        }
+    }
+
+    /// Returns whether this is the only `HeapArc` or [`HeapWeak`] pointing
+    /// to the allocation.
+    ///
+    /// If this returns `true`, nothing else can obtain a new handle to the
+    /// allocation except through this arc, so the answer stays true until this
+    /// arc is cloned or downgraded.
+    pub fn is_unique(this: &Self) -> bool {
+        let inner = unsafe { Active::<ArcInner<T>>::data(this.ptr).as_ref() };
+        // Like `alloc::sync::Arc`, lock the weak count while checking the
+        // strong one, so that another strong reference can't be downgraded
+        // in between. While there are no weaks, nothing can be upgraded, so
+        // the strong count can't grow behind our back either.
+        if inner
+            .weak
+            .compare_exchange(1, WEAK_LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+        let unique = inner.refcnt.load(Ordering::Acquire) == 1;
+        inner.weak.store(1, Ordering::Release);
+        unique
+    }
+
+    /// Call `f` with the current strong count, and the contents of the arc.
+    ///
+    /// The count is loaded once, so this is handy for diagnostics that print
+    /// both, e.g. `"[count=3] Object { .. }"`. Other handles may still be
+    /// cloned or dropped while `f` runs.
+    pub fn with_info<R>(this: &Self, f: impl FnOnce(usize, &T) -> R) -> R {
+        f(Self::strong_count(this), this)
+    }
+
+    /// Returns whether the two arcs point to the same allocation.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+
+    /// Allocate a fixed vec holding `n` clones of the arc, waiting for space
+    /// to become available if necessary.
+    ///
+    /// The strong count is increased by `n` with a single update, so the
+    /// intermediate counts are never observed.
+    pub async fn clone_into_fixed_vec(
+        this: &Self,
+        heap: &'static AHeap,
+        n: usize,
+    ) -> HeapFixedVec<HeapArc<T>> {
+        let mut vec = heap.allocate_fixed_vec(n).await;
+        unsafe {
+            let aitem_nn = Active::<ArcInner<T>>::data(this.ptr);
+            add_count(&aitem_nn.as_ref().refcnt, n);
+        }
+        for _ in 0..n {
+            // The reference count has already been taken care of
+            let clone = HeapArc {
+                ptr: this.ptr,
+                pd: PhantomData,
+            };
+            // Can't fail, there's room for all of them
+            let _ = vec.push(clone);
+        }
+        vec
+    }
+
+    /// Consume the arc, returning a pointer to its contents.
+    ///
+    /// Like `alloc::sync::Arc::into_raw`, this does NOT change the reference
+    /// count: the returned pointer "holds" the strong reference that `this`
+    /// did, until it is turned back into an arc with
+    /// [`HeapArc::from_raw`].
+    pub fn into_raw(this: Self) -> *const T {
+        this.leak().as_ptr()
+    }
+
+    /// Reconstruct an arc from a pointer returned by [`HeapArc::into_raw`].
+    ///
+    /// Like `alloc::sync::Arc::from_raw`, this does NOT change the reference
+    /// count: it takes back the strong reference held by the pointer.
+    ///
+    /// ## Safety
+    ///
+    /// `ptr` must have been returned by `HeapArc::<T>::into_raw` (or
+    /// [`HeapArc::leak`]), and each such pointer may only be passed to
+    /// `from_raw` once.
+    pub unsafe fn from_raw(ptr: *const T) -> Self {
+        Self::from_leaked(NonNull::new_unchecked(ptr.cast_mut()))
+    }
+
+    /// Leak the contents of this box, never to be recovered (probably)
+    ///
+    /// This is the same as [`HeapArc::into_raw`], which should be preferred.
+    pub fn leak(self) -> NonNull<T> {
+        unsafe {
+            let nn = Active::<ArcInner<T>>::data(self.ptr);
+            forget(self);
+            let data_ptr = addr_of_mut!((*nn.as_ptr()).data);
+            NonNull::new_unchecked(data_ptr)
+        }
+    }
+
+    /// Create a clone of a given leaked HeapArc<T>. DOES increase the refcount.
+    pub unsafe fn clone_from_leaked(ptr: NonNull<T>) -> Self {
+        let new = Self::from_leaked(ptr);
+
+        let aitem_nn = Active::<ArcInner<T>>::data(new.ptr);
+        inc_count(&aitem_nn.as_ref().refcnt);
+
+        new
+    }
+
+    /// Re-takes ownership of a leaked HeapArc<T>. Does NOT increase the refcount.
+    ///
+    /// This is the same as [`HeapArc::from_raw`], which should be preferred.
+    pub unsafe fn from_leaked(ptr: NonNull<T>) -> Self {
+        let arc_inner_nn: NonNull<ArcInner<T>> = ArcInner::from_leaked_ptr(ptr);
+        Self {
+            ptr: Active::<ArcInner<T>>::from_leaked_ptr(arc_inner_nn),
+            pd: PhantomData,
+        }
+    }
+
+    /// Set the function called on the contents right before they are
+    /// dropped.
+    ///
+    /// # Safety
+    ///
+    /// No other handle to the arc may exist yet, as the hook isn't atomic.
+    pub(crate) unsafe fn set_on_last_drop(this: &mut Self, on_last_drop: fn(&mut T)) {
+        // SAFETY: A `&mut T` and a `NonNull<()>` are ABI compatible, and the
+        // hook is only ever called with a pointer to this arc's contents
+        let erased = core::mem::transmute::<fn(&mut T), unsafe fn(NonNull<()>)>(on_last_drop);
+        (*Active::<ArcInner<T>>::data(this.ptr).as_ptr()).on_last_drop = Some(erased);
+    }
+
+    /// Move the contents out of the arc, if this is the only strong
+    /// reference to them, otherwise hand the arc back.
+    ///
+    /// Any weak references are left dangling, and can no longer be upgraded.
+    fn try_take(this: Self) -> Result<T, Self> {
+        unsafe {
+            let aitem_ptr = Active::<ArcInner<T>>::data(this.ptr).as_ptr();
+            // Going from one to zero (rather than just checking the count)
+            // stops any weak references from upgrading in the meantime.
+            if (*aitem_ptr)
+                .refcnt
+                .compare_exchange(1, 0, Ordering::SeqCst, Ordering::SeqCst)
+                .is_err()
+            {
+                return Err(this);
+            }
+            let data = addr_of!((*aitem_ptr).data).read();
+            // Release the weak reference held by all the strong ones
+            ArcInner::<T>::release_weak(this.ptr);
+            forget(this);
+            Ok(data)
+        }
+    }
+
+    /// Drop this strong reference, and if it was the last one, move the
+    /// contents out of the arc instead of dropping them.
+    ///
+    /// Unlike `HeapArc::try_unwrap(this).ok()`, if every strong reference is
+    /// dropped with this (even concurrently), exactly one of them obtains
+    /// the contents. Any weak references can no longer be upgraded
+    /// afterwards.
+    pub fn into_inner(this: Self) -> Option<T> {
+        let this = ManuallyDrop::new(this);
+        unsafe {
+            let aitem_ptr = Active::<ArcInner<T>>::data(this.ptr).as_ptr();
+            // See `HeapArc::drop`
+            let old = (*aitem_ptr).refcnt.fetch_sub(1, Ordering::Release);
+            debug_assert_ne!(old, 0);
+            if old != 1 {
+                return None;
+            }
+            fence(Ordering::Acquire);
+            let data = addr_of!((*aitem_ptr).data).read();
+            // Release the weak reference held by all the strong ones
+            ArcInner::<T>::release_weak(this.ptr);
+            Some(data)
+        }
+    }
+
+    /// Move the contents out of the arc, and free its allocation, if this is
+    /// the only strong reference to them. Otherwise, the arc is handed back.
+    ///
+    /// Any weak references can no longer be upgraded afterwards.
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        Self::try_take(this)
+    }
+
+    /// Obtain a mutable reference to the contents, if there are no other
+    /// `HeapArc`s or [`HeapWeak`]s pointing to them.
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        if !Self::is_unique(this) {
+            return None;
+        }
+        // SAFETY: Nothing else can reach the contents while we hold `this`
+        // mutably: `is_unique` locks the weak count while checking the
+        // strong one, so no other handle was created in the meantime.
+        unsafe {
+            let aitem_ptr = Active::<ArcInner<T>>::data(this.ptr).as_ptr();
+            Some(&mut *addr_of_mut!((*aitem_ptr).data))
+        }
+    }
+
+    pub unsafe fn increment_count(ptr: NonNull<T>) {
+        let arc_inner_nn: NonNull<ArcInner<T>> = ArcInner::from_leaked_ptr(ptr);
+        inc_count(&arc_inner_nn.as_ref().refcnt);
+    }
+
+    /// Returns the current strong count of this arc, along with the place
+    /// where it was originally allocated.
+    ///
+    /// This is useful for tracking down arcs that are kept alive longer than
+    /// expected, e.g. by a reference cycle.
+    #[cfg(feature = "debug-arc")]
+    pub fn debug_refcount_info(this: &Self) -> ArcDebugInfo {
+        unsafe {
+            let aitem_nn = Active::<ArcInner<T>>::data(this.ptr);
+            let aitem = aitem_nn.as_ref();
+            ArcDebugInfo {
+                count: load_count(&aitem.refcnt),
+                location: aitem.location,
+            }
+        }
+    }
+}
+
+impl<T> Deref for HeapArc<T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        unsafe {
+            let aiptr: *mut ArcInner<T> = Active::<ArcInner<T>>::data(self.ptr).as_ptr();
+            let dptr: *const T = addr_of!((*aiptr).data);
+            &*dptr
+        }
+    }
+}
+
+impl<T> Drop for HeapArc<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let (aiptr, needs_drop) = {
+                let aitem_ptr = Active::<ArcInner<T>>::data(self.ptr).as_ptr();
+                // Release our uses of the contents to whoever drops them...
+                let old = (*aitem_ptr).refcnt.fetch_sub(1, Ordering::Release);
+                debug_assert_ne!(old, 0);
+                (aitem_ptr, old == 1)
+            };
+
+            if needs_drop {
+                // ...and, if that's us, acquire everyone else's, so they all
+                // happen before the contents are dropped. This is the same
+                // as `alloc::sync::Arc`.
+                fence(Ordering::Acquire);
+                drop_data((*aiptr).on_last_drop, addr_of_mut!((*aiptr).data));
+                // Release the weak reference held by all the strong ones
+                ArcInner::<T>::release_weak(self.ptr);
+            }
+        }
+    }
+}
+
+impl<T> Clone for HeapArc<T> {
+    fn clone(&self) -> Self {
+        unsafe {
+            let aitem_nn = Active::<ArcInner<T>>::data(self.ptr);
+            inc_count(&aitem_nn.as_ref().refcnt);
+
+            HeapArc {
+                ptr: self.ptr,
+                pd: PhantomData,
+            }
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for HeapArc<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for HeapArc<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> fmt::Pointer for HeapArc<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&self.ptr, f)
+    }
+}
+
+/// Compares the contents of the arc with `other`.
+impl<T: PartialEq> PartialEq<&T> for HeapArc<T> {
+    #[inline]
+    fn eq(&self, other: &&T) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: Clone> HeapArc<T> {
+    /// Obtain the contents of the arc, by moving them out if this is the
+    /// only strong reference to them, or by cloning them otherwise.
+    pub fn unwrap_or_clone(this: Self) -> T {
+        Self::try_take(this).unwrap_or_else(|arc| (*arc).clone())
+    }
+}
+
+impl<T: Send + Sync> HeapArc<T> {
+    /// Project this arc into a reference to some part of its contents, such
+    /// as one of its fields.
+    ///
+    /// The returned `HeapArcRef` holds this arc's reference count, so the
+    /// whole allocation stays alive until it (and any clones of it) are
+    /// dropped.
+    pub fn map_ref<U, F>(this: Self, f: F) -> HeapArcRef<U>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        unsafe fn increment<T>(data: NonNull<()>) {
+            HeapArc::<T>::increment_count(data.cast());
+        }
+
+        unsafe fn release<T>(data: NonNull<()>) {
+            drop(HeapArc::<T>::from_leaked(data.cast()));
+        }
+
+        let ptr = NonNull::from(f(&*this));
+        let data = this.leak().cast::<()>();
+        HeapArcRef {
+            ptr,
+            owner: ArcRefOwner {
+                data,
+                increment: increment::<T>,
+                release: release::<T>,
+            },
+        }
+    }
+}
+
+// === impl HeapWeak ===
+
+// These require the same bounds as `alloc::sync::Weak`'s `Send` and `Sync`
+// impls.
+unsafe impl<T: Send + Sync> Send for HeapWeak<T> {}
+unsafe impl<T: Send + Sync> Sync for HeapWeak<T> {}
+
+impl<T> Unpin for HeapWeak<T> {}
+
+impl<T> HeapWeak<T> {
+    /// Attempt to obtain a [`HeapArc`] to the contents.
+    ///
+    /// Returns `None` if all of the `HeapArc`s have already been dropped, in
+    /// which case the contents have been dropped too.
+    // The cast is only a no-op without `thin-arc`
+    #[allow(clippy::unnecessary_cast)]
+    pub fn upgrade(&self) -> Option<HeapArc<T>> {
+        let aitem = unsafe { Active::<ArcInner<T>>::data(self.ptr).as_ref() };
+        // `self` is a weak reference, so `HeapArc::is_unique` can't be
+        // holding the weak count locked, and won't until `self` is dropped.
+        debug_assert_ne!(aitem.weak.load(Ordering::Relaxed), WEAK_LOCKED);
+        let mut cur = aitem.refcnt.load(Ordering::SeqCst);
+        loop {
+            // Once the strong count reaches zero, the data is (being) dropped,
+            // and must never be resurrected.
+            if cur == 0 {
+                return None;
+            }
+            if cur as usize > MAX_REFCOUNT {
+                refcount_overflow();
+            }
+            match aitem.refcnt.compare_exchange_weak(
+                cur,
+                cur + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => {
+                    return Some(HeapArc {
+                        ptr: self.ptr,
+                        pd: PhantomData,
+                    })
+                }
+                Err(actual) => cur = actual,
+            }
+        }
+    }
+
+    /// The number of `HeapArc`s pointing to this allocation.
+    pub fn strong_count(&self) -> usize {
+        unsafe {
+            let aitem_nn = Active::<ArcInner<T>>::data(self.ptr);
+            load_count(&aitem_nn.as_ref().refcnt)
+        }
+    }
+
+    /// The number of `HeapWeak`s pointing to this allocation.
+    ///
+    /// Returns zero if there are no `HeapArc`s remaining.
+    pub fn weak_count(&self) -> usize {
+        unsafe {
+            let aitem = Active::<ArcInner<T>>::data(self.ptr).as_ref();
+            let weak = load_count(&aitem.weak);
+            if aitem.refcnt.load(Ordering::SeqCst) == 0 {
+                0
+            } else {
+                // Don't count the weak reference held by the strong ones
+                weak - 1
+            }
+        }
+    }
+}
+
+impl<T> Clone for HeapWeak<T> {
+    fn clone(&self) -> Self {
+        unsafe {
+            let aitem_nn = Active::<ArcInner<T>>::data(self.ptr);
+            inc_count(&aitem_nn.as_ref().weak);
+        }
+        HeapWeak {
+            ptr: self.ptr,
+            pd: PhantomData,
+        }
+    }
+}
+
+impl<T> Drop for HeapWeak<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ArcInner::<T>::release_weak(self.ptr);
+        }
+    }
+}
+
+impl<T> fmt::Debug for HeapWeak<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(HeapWeak)")
+    }
+}
+
+// === impl HeapArcRef ===
+
+// `map_ref` requires the original `T` to be `Send + Sync`, so the only
+// thing left to care about is sharing the `U`.
+unsafe impl<U: Sync> Send for HeapArcRef<U> {}
+unsafe impl<U: Sync> Sync for HeapArcRef<U> {}
+
+impl<U> Deref for HeapArcRef<U> {
+    type Target = U;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<U> Clone for HeapArcRef<U> {
+    fn clone(&self) -> Self {
+        unsafe {
+            (self.owner.increment)(self.owner.data);
+        }
+        HeapArcRef {
+            ptr: self.ptr,
+            owner: self.owner,
+        }
+    }
+}
+
+impl<U> Drop for HeapArcRef<U> {
+    fn drop(&mut self) {
+        unsafe {
+            (self.owner.release)(self.owner.data);
+        }
+    }
+}
+
+impl<U: fmt::Display> fmt::Display for HeapArcRef<U> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<U: fmt::Debug> fmt::Debug for HeapArcRef<U> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// "Plain old data" types, which can be compared byte-for-byte.
+///
+/// # Safety
+///
+/// Implementors MUST NOT contain any padding bytes, and two values MUST be
+/// equal (by `PartialEq`) if and only if their bytes are equal.
+pub unsafe trait Pod: Copy {}
+
+macro_rules! impl_pod {
+    ($($t:ty),*) => {
+        $(unsafe impl Pod for $t {})*
+    };
+}
+
+impl_pod!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+// === impl HeapArray ===
+
+unsafe impl<T: Send> Send for HeapArray<T> {}
+unsafe impl<T: Sync> Sync for HeapArray<T> {}
+
+impl<T> Unpin for HeapArray<T> {}
+
+impl<T> Deref for HeapArray<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { from_raw_parts(self.live_ptr(), self.len) }
+    }
+}
+
+impl<T> DerefMut for HeapArray<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { from_raw_parts_mut(self.live_ptr(), self.len) }
+    }
+}
+
+impl<T: Default> HeapArray<T> {
+    /// Allocate a new array of `count` default values on the current heap.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no current heap has been set with [`AHeap::set_current`].
+    #[track_caller]
+    pub fn new(count: usize) -> impl Future<Output = HeapArray<T>> {
+        AHeap::expect_current().allocate_array_with(T::default, count)
+    }
+}
+
+impl<T> HeapArray<T> {
+    // pub unsafe fn from_leaked(ptr: *mut T, count: usize) -> Self {
+    //     Self { ptr, count }
+    // }
+
+    /// Leak the contents of this box, never to be recovered (probably)
+    pub fn leak(self) -> (NonNull<T>, usize) {
+        unsafe {
+            let nn_ptr = NonNull::new_unchecked(self.live_ptr());
+            let len = self.len;
+            forget(self);
+            (nn_ptr, len)
+        }
+    }
+
+    /// Reinterpret the array as uninitialized storage, without reallocating.
+    ///
+    /// The elements are forgotten rather than dropped, so this is best
+    /// suited to types like `u8` that don't need dropping.
+    pub fn into_uninit(self) -> HeapArray<MaybeUninit<T>> {
+        // SAFETY: `MaybeUninit<T>` has the same layout as `T`, so this is
+        // the same kind of node
+        let arr = HeapArray {
+            ptr: self.ptr.cast::<ActiveArr<MaybeUninit<T>>>(),
+            start: self.start,
+            len: self.len,
+            pd: PhantomData,
+        };
+        forget(self);
+        arr
+    }
+
+    /// The layout of the block that the heap allocated for this array.
+    ///
+    /// This includes the allocator's per-allocation header, and any rounding
+    /// applied by the heap. It is based on the capacity the array was
+    /// originally allocated with, even if elements have since been removed.
+    pub fn layout(&self) -> Layout {
+        unsafe { ActiveArr::<T>::alloc_layout(self.ptr) }
+    }
+
+    /// Remove and return the first element of the array, or `None` if it is
+    /// empty.
+    ///
+    /// This is O(1): the remaining elements are not moved, and the storage of
+    /// the removed element is reclaimed when the whole array is freed.
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        unsafe {
+            let item = self.live_ptr().read();
+            self.start += 1;
+            self.len -= 1;
+            Some(item)
+        }
+    }
+
+    /// Split off the first element by value, handing back the remaining
+    /// elements as an array.
+    ///
+    /// Returns `None` (freeing the allocation) if the array is empty.
+    pub fn split_first_owned(mut self) -> Option<(T, HeapArray<T>)> {
+        let first = self.pop_front()?;
+        Some((first, self))
+    }
+
+    /// Split the array in two at `mid`, producing two independently owned
+    /// arrays: the first holds the elements `[0, mid)`, the second holds the
+    /// elements `[mid, len)`.
+    ///
+    /// The first array reuses the existing allocation, while the elements of
+    /// the second are moved into a new allocation from `guard`. If that
+    /// allocation fails, the original array is returned, untouched.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `mid > len`.
+    pub fn split_into(
+        mut self,
+        guard: &mut HeapGuard,
+        mid: usize,
+    ) -> Result<(HeapArray<T>, HeapArray<T>), HeapArray<T>> {
+        assert!(mid <= self.len, "mid > len");
+        let mut tail = match guard.alloc_fixed_vec::<T>(self.len - mid) {
+            Ok(tail) => tail,
+            Err(_) => return Err(self),
+        };
+        self.move_tail(&mut tail, mid);
+        Ok((self, tail.into_initialized_array()))
+    }
+
+    /// Like [`HeapArray::split_into`], but waiting for space to become
+    /// available for the second array if necessary.
+    ///
+    /// If `at` is zero or `len`, the first or second array (respectively)
+    /// is empty.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `at > len`.
+    pub async fn split_off(
+        mut self,
+        heap: &'static AHeap,
+        at: usize,
+    ) -> (HeapArray<T>, HeapArray<T>) {
+        assert!(at <= self.len, "at > len");
+        let mut tail = heap.allocate_fixed_vec::<T>(self.len - at).await;
+        self.move_tail(&mut tail, at);
+        (self, tail.into_initialized_array())
+    }
+
+    /// Move the elements `[mid, len)` into the empty `tail`, which must have
+    /// room for exactly that many.
+    fn move_tail(&mut self, tail: &mut HeapFixedVec<T>, mid: usize) {
+        let tail_len = self.len - mid;
+        debug_assert_eq!((tail.len, tail.capacity()), (0, tail_len));
+        unsafe {
+            let (dst, _count) = tail.storage();
+            core::ptr::copy_nonoverlapping(
+                self.live_ptr().add(mid),
+                dst.as_ptr().cast::<T>(),
+                tail_len,
+            );
+            // The moved elements are now owned by `tail` only
+            tail.len = tail_len;
+            self.len = mid;
+        }
+    }
+
+    /// Transform each element of the array with `f`, collecting the results
+    /// into a new array, and stopping at the first error.
+    ///
+    /// If `f` returns an error, the elements transformed so far and the
+    /// elements not yet transformed are all dropped, and both arrays are
+    /// freed.
+    pub async fn try_map<U, E, F>(
+        mut self,
+        heap: &'static AHeap,
+        mut f: F,
+    ) -> Result<HeapArray<U>, E>
+    where
+        F: FnMut(T) -> Result<U, E>,
+    {
+        let mut out = heap.allocate_fixed_vec::<U>(self.len).await;
+        // Moving elements out from the front means `self` only ever holds
+        // (and drops) the untransformed elements.
+        while let Some(item) = self.pop_front() {
+            // Can't fail, the capacity matches our original length
+            let _ = out.push(f(item)?);
+        }
+        Ok(out.into_initialized_array())
+    }
+
+    /// Iterate over all contiguous windows of length `n`, like
+    /// [`slice::windows`], but returning `None` instead of panicking if `n`
+    /// is zero.
+    pub fn windows_checked(&self, n: usize) -> Option<impl Iterator<Item = &[T]>> {
+        if n == 0 {
+            return None;
+        }
+        Some(self.windows(n))
+    }
+
+    /// Obtain the elements in `range`, or an error if it is out of bounds,
+    /// instead of panicking like indexing does.
+    pub fn subslice(&self, range: impl RangeBounds<usize>) -> Result<&[T], OutOfBounds> {
+        let range = check_range(range, self.len)?;
+        Ok(&self[range])
+    }
+
+    /// Like [`HeapArray::subslice`], but obtains the elements mutably.
+    pub fn subslice_mut(
+        &mut self,
+        range: impl RangeBounds<usize>,
+    ) -> Result<&mut [T], OutOfBounds> {
+        let range = check_range(range, self.len)?;
+        Ok(&mut self[range])
+    }
+
+    /// Replace each element with the result of calling `f` on it, in place,
+    /// from first to last.
+    ///
+    /// The elements stay in the same allocation, so unlike
+    /// [`HeapArray::try_map`], nothing is allocated.
+    ///
+    /// ## Panics
+    ///
+    /// While `f` runs, the element it was given is missing from the array.
+    /// If `f` panics, the array would be left with a hole in it, so the
+    /// process is aborted instead (by panicking again while unwinding), like
+    /// [`HeapBox::replace_with`].
+    pub fn update_each<F: FnMut(T) -> T>(&mut self, mut f: F) {
+        struct AbortOnUnwind;
+
+        impl Drop for AbortOnUnwind {
+            fn drop(&mut self) {
+                panic!("`HeapArray::update_each` closure panicked, aborting");
+            }
+        }
+
+        unsafe {
+            let start = self.live_ptr();
+            let bomb = AbortOnUnwind;
+            for i in 0..self.len {
+                let slot = start.add(i);
+                slot.write(f(slot.read()));
+            }
+            forget(bomb);
+        }
+    }
+
+    /// Drop the array, dropping its elements from last to first.
+    ///
+    /// This is the reverse of the order used when the array is dropped
+    /// normally, and is useful for arrays of guards that must be released
+    /// in the opposite order to how they were acquired.
+    ///
+    /// Like a normal drop, if one of the elements panics while being
+    /// dropped, the rest are still dropped, and the node is still freed.
+    pub fn drop_in_reverse(self) {
+        let this = ManuallyDrop::new(self);
+        unsafe { drop_array_rev(this.live_ptr(), this.len, this.ptr) }
+    }
+
+    /// Convert this array into a shared array, holding the same elements.
+    ///
+    /// An array's node already has room for a reference count, so if the
+    /// array still covers its whole storage, this doesn't allocate at all.
+    /// Otherwise (if elements have been removed from it), the elements are
+    /// moved into a new, exactly sized, shared array, waiting for space to
+    /// become available if necessary, and the original node is freed.
+    pub async fn into_shared(mut self, heap: &'static AHeap) -> HeapArcArray<T> {
+        let (_nn_ptr, capacity) = unsafe { ActiveArr::<T>::data(self.ptr) };
+        if self.start == 0 && self.len == capacity {
+            // The reference count of an exclusive array is always one
+            let ptr = self.ptr;
+            forget(self);
+            return HeapArcArray {
+                ptr,
+                pd: PhantomData,
+            };
+        }
+
+        let new = heap
+            .allocate_array_with(MaybeUninit::<T>::uninit, self.len)
+            .await;
+        unsafe {
+            let (dst, _count) = ActiveArr::<MaybeUninit<T>>::data(new.ptr);
+            core::ptr::copy_nonoverlapping(self.live_ptr(), dst.as_ptr().cast::<T>(), self.len);
+        }
+        // The elements were moved out, only free the original node
+        self.len = 0;
+        drop(self);
+
+        let ptr = new.ptr.cast::<ActiveArr<T>>();
+        forget(new);
+        HeapArcArray {
+            ptr,
+            pd: PhantomData,
+        }
+    }
+
+    /// Pointer to the first live element
+    #[inline(always)]
+    fn live_ptr(&self) -> *mut T {
+        unsafe {
+            let (nn_ptr, _count) = ActiveArr::<T>::data(self.ptr);
+            nn_ptr.as_ptr().add(self.start)
+        }
+    }
+}
+
+impl<T: Pod> HeapArray<T> {
+    /// Compare the contents of this array with `other` byte-for-byte.
+    ///
+    /// This is equivalent to `&**self == other`, but is done as a single
+    /// `memcmp`-style comparison rather than element by element.
+    pub fn bytes_eq(&self, other: &[T]) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
+        let size = core::mem::size_of_val::<[T]>(self);
+        unsafe {
+            let ours = from_raw_parts(self.as_ptr().cast::<u8>(), size);
+            let theirs = from_raw_parts(other.as_ptr().cast::<u8>(), size);
+            ours == theirs
+        }
+    }
+}
+
+impl<T: Copy> HeapArray<T> {
+    /// Copy all of the elements of `src` into this array, with a single
+    /// `memcpy`.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `src` is not exactly the same length as this array.
+    pub fn copy_from_slice(&mut self, src: &[T]) {
+        assert_eq!(
+            self.len,
+            src.len(),
+            "source slice length does not match the array length"
+        );
+        // SAFETY: `live_ptr` is valid for `len` elements, and `src` can't
+        // overlap with it, as we hold `&mut self`.
+        unsafe { core::ptr::copy_nonoverlapping(src.as_ptr(), self.live_ptr(), self.len) }
+    }
+}
+
+impl<T: Clone + PartialEq> HeapArray<T> {
+    /// Clone the elements into a new array, leaving out consecutive
+    /// duplicates, like `Vec::dedup` but without changing this array.
+    ///
+    /// The new array is allocated with exactly the number of elements it
+    /// holds.
+    pub async fn dedup_to_new(&self, heap: &'static AHeap) -> HeapArray<T> {
+        let run_starts = || (0..self.len).filter(|&i| i == 0 || self[i - 1] != self[i]);
+
+        let mut out = heap.allocate_fixed_vec(run_starts().count()).await;
+        for i in run_starts() {
+            // Can't fail, there's room for exactly all of the runs
+            let _ = out.push(self[i].clone());
+        }
+        out.into_initialized_array()
+    }
+}
+
+impl<T> Drop for HeapArray<T> {
+    fn drop(&mut self) {
+        unsafe { drop_array(self.live_ptr(), self.len, self.ptr) }
+    }
+}
+
+impl<T> fmt::Debug for HeapArray<T>
+where
+    [T]: fmt::Debug,
+{
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> fmt::Pointer for HeapArray<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&self.ptr, f)
+    }
+}
+
+impl<T> AsRef<[T]> for HeapArray<T> {
+    #[inline]
+    fn as_ref(&self) -> &[T] {
+        self
+    }
+}
+
+impl<T> AsMut<[T]> for HeapArray<T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut [T] {
+        self
+    }
+}
+
+impl<T> Borrow<[T]> for HeapArray<T> {
+    #[inline]
+    fn borrow(&self) -> &[T] {
+        self
+    }
+}
+
+impl<T> BorrowMut<[T]> for HeapArray<T> {
+    #[inline]
+    fn borrow_mut(&mut self) -> &mut [T] {
+        self
+    }
+}
+
+/// Compares the elements of the array with `other`.
+impl<T: PartialEq> PartialEq<&[T]> for HeapArray<T> {
+    #[inline]
+    fn eq(&self, other: &&[T]) -> bool {
+        **self == **other
+    }
+}
+
+impl<T> IntoIterator for HeapArray<T> {
+    type Item = T;
+    type IntoIter = HeapArrayIntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let iter = HeapArrayIntoIter {
+            ptr: Some(self.ptr),
+            head: self.start,
+            tail: self.start + self.len,
+            pd: PhantomData,
+        };
+        forget(self);
+        iter
+    }
+}
+
+impl<'a, T> IntoIterator for &'a HeapArray<T> {
+    type Item = &'a T;
+    type IntoIter = core::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut HeapArray<T> {
+    type Item = &'a mut T;
+    type IntoIter = core::slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+// === impl HeapArrayIntoIter ===
+
+unsafe impl<T: Send> Send for HeapArrayIntoIter<T> {}
+unsafe impl<T: Sync> Sync for HeapArrayIntoIter<T> {}
+
+impl<T> HeapArrayIntoIter<T> {
+    /// The elements that have not been taken yet.
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { from_raw_parts(self.remaining(), self.tail - self.head) }
+    }
+
+    /// The elements that have not been taken yet, mutably.
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { from_raw_parts_mut(self.remaining(), self.tail - self.head) }
+    }
+
+    /// Pointer to the first element that has not been taken yet
+    fn remaining(&self) -> *mut T {
+        match self.ptr {
+            Some(ptr) => unsafe {
+                let (nn_ptr, _count) = ActiveArr::<T>::data(ptr);
+                nn_ptr.as_ptr().add(self.head)
+            },
+            None => NonNull::dangling().as_ptr(),
+        }
+    }
+}
+
+impl<T> Iterator for HeapArrayIntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.head == self.tail {
+            return None;
+        }
+        let item = unsafe { self.remaining().read() };
+        self.head += 1;
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.tail - self.head;
+        (len, Some(len))
+    }
+}
+
+impl<T> DoubleEndedIterator for HeapArrayIntoIter<T> {
+    fn next_back(&mut self) -> Option<T> {
+        if self.head == self.tail {
+            return None;
+        }
+        self.tail -= 1;
+        let last = self.tail - self.head;
+        Some(unsafe { self.remaining().add(last).read() })
+    }
+}
+
+impl<T> ExactSizeIterator for HeapArrayIntoIter<T> {}
+
+impl<T> core::iter::FusedIterator for HeapArrayIntoIter<T> {}
+
+impl<T> Drop for HeapArrayIntoIter<T> {
+    fn drop(&mut self) {
+        if let Some(ptr) = self.ptr {
+            unsafe { drop_array(self.remaining(), self.tail - self.head, ptr) }
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for HeapArrayIntoIter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("HeapArrayIntoIter")
+            .field(&self.as_slice())
+            .finish()
+    }
+}
+
+// === impl HeapArcArray ===
+
+unsafe impl<T: Send + Sync> Send for HeapArcArray<T> {}
+unsafe impl<T: Send + Sync> Sync for HeapArcArray<T> {}
+
+impl<T> Unpin for HeapArcArray<T> {}
+
+impl<T> Deref for HeapArcArray<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe {
+            let (nn_ptr, count) = ActiveArr::<T>::data(self.ptr);
+            from_raw_parts(nn_ptr.as_ptr(), count)
+        }
+    }
+}
+
+impl<T> HeapArcArray<T> {
+    /// The number of handles (and views) sharing this array.
+    pub fn strong_count(this: &Self) -> usize {
+        unsafe { ActiveArr::<T>::refcnt(this.ptr).load(Ordering::SeqCst) }
+    }
+
+    /// Returns whether both handles share the same array.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+
+    /// Obtain mutable access to the elements, if this is the only handle to
+    /// the array.
+    ///
+    /// This allows filling in a shared array in place, before cloning any
+    /// handles to it.
+    pub fn get_mut(this: &mut Self) -> Option<&mut [T]> {
+        unsafe {
+            // Acquire, to see any writes made through handles that have
+            // since been dropped.
+            if ActiveArr::<T>::refcnt(this.ptr).load(Ordering::Acquire) != 1 {
+                return None;
+            }
+            let (nn_ptr, count) = ActiveArr::<T>::data(this.ptr);
+            Some(from_raw_parts_mut(nn_ptr.as_ptr(), count))
+        }
+    }
+
+    /// Obtain a shared view of the elements in `range`, or an error if it is
+    /// out of bounds.
+    ///
+    /// The view holds a reference to the whole array, so the array stays
+    /// alive until all of its views and handles are dropped.
+    pub fn slice(
+        this: &Self,
+        range: impl RangeBounds<usize>,
+    ) -> Result<HeapArcSlice<T>, OutOfBounds> {
+        let range = check_range(range, this.len())?;
+        Ok(HeapArcSlice {
+            arr: this.clone(),
+            range,
+        })
+    }
+}
+
+impl<T> HeapArray<MaybeUninit<T>> {
+    /// Convert an array of uninitialized elements, such as one from
+    /// [`HeapArray::into_uninit`], back into an array of `T`s, without
+    /// reallocating.
+    ///
+    /// ## Safety
+    ///
+    /// All of the elements must have been initialized.
+    pub unsafe fn assume_all_init(this: Self) -> HeapArray<T> {
+        let arr = HeapArray {
+            ptr: this.ptr.cast::<ActiveArr<T>>(),
+            start: this.start,
+            len: this.len,
+            pd: PhantomData,
+        };
+        forget(this);
+        arr
+    }
+}
+
+impl<T> HeapArcArray<MaybeUninit<T>> {
+    /// Convert a shared array of uninitialized elements, such as one created
+    /// with [`HeapGuard::alloc_arc_array_uninit`], into an array of `T`s.
+    ///
+    /// ## Safety
+    ///
+    /// All of the elements must have been initialized.
+    ///
+    /// This should be called while `this` is the only handle to the array.
+    /// Otherwise, if one of the other (uninitialized) handles is the last
+    /// to be dropped, the elements are leaked rather than dropped.
+    pub unsafe fn assume_init(this: Self) -> HeapArcArray<T> {
+        let ptr = this.ptr.cast::<ActiveArr<T>>();
+        forget(this);
+        HeapArcArray {
+            ptr,
+            pd: PhantomData,
+        }
+    }
+}
+
+impl<T> Clone for HeapArcArray<T> {
+    fn clone(&self) -> Self {
+        unsafe {
+            // Not a `RefCount`, but the same limit applies
+            let old = ActiveArr::<T>::refcnt(self.ptr).fetch_add(1, Ordering::SeqCst);
+            if old > MAX_REFCOUNT {
+                refcount_overflow();
+            }
+        }
+        HeapArcArray {
+            ptr: self.ptr,
+            pd: PhantomData,
+        }
+    }
+}
+
+impl<T> Drop for HeapArcArray<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let old = ActiveArr::<T>::refcnt(self.ptr).fetch_sub(1, Ordering::Release);
+            debug_assert_ne!(old, 0);
+            if old != 1 {
+                return;
+            }
+            // See `HeapArc::drop`
+            fence(Ordering::Acquire);
+
+            let (nn_ptr, count) = ActiveArr::<T>::data(self.ptr);
+            drop_array(nn_ptr.as_ptr(), count, self.ptr);
+        }
+    }
+}
+
+// === impl HeapArcSlice ===
+
+impl<T> HeapArcSlice<T> {
+    /// Narrow the view to the elements in `range`, relative to the start of
+    /// this view, or return an error if it is out of bounds.
+    pub fn slice(this: &Self, range: impl RangeBounds<usize>) -> Result<Self, OutOfBounds> {
+        let range = check_range(range, this.len())?;
+        Ok(HeapArcSlice {
+            arr: this.arr.clone(),
+            range: (this.range.start + range.start)..(this.range.start + range.end),
+        })
+    }
+
+    /// The range of the whole array covered by this view.
+    pub fn range(this: &Self) -> Range<usize> {
+        this.range.clone()
+    }
+}
+
+impl<T> Deref for HeapArcSlice<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        &self.arr[self.range.clone()]
+    }
+}
+
+impl<T> Clone for HeapArcSlice<T> {
+    fn clone(&self) -> Self {
+        Self {
+            arr: self.arr.clone(),
+            range: self.range.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for HeapArcSlice<T>
+where
+    [T]: fmt::Debug,
+{
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+// === impl HeapFixedVec ===
+
+unsafe impl<T: Send> Send for HeapFixedVec<T> {}
+unsafe impl<T: Sync> Sync for HeapFixedVec<T> {}
+
+impl<T> Unpin for HeapFixedVec<T> {}
+
+impl<T> Deref for HeapFixedVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe {
+            let (nn_ptr, _count) = self.storage();
+            from_raw_parts(nn_ptr.as_ptr().cast::<T>(), self.len)
+        }
+    }
+}
+
+impl<T> DerefMut for HeapFixedVec<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe {
+            let (nn_ptr, _count) = self.storage();
+            from_raw_parts_mut(nn_ptr.as_ptr().cast::<T>(), self.len)
+        }
+    }
+}
+
+impl<T> HeapFixedVec<T> {
+    /// Create an empty vec with zero capacity, without allocating.
+    ///
+    /// Pushing to it always fails. This is mostly useful as a placeholder,
+    /// e.g. in `static`s; see also [`HeapFixedVec::new_lazy`].
+    pub const fn new_empty() -> Self {
+        Self::new_lazy(0)
+    }
+
+    /// Create an empty vec, that allocates room for `capacity` elements on
+    /// the current heap when it is first pushed to.
+    ///
+    /// This can be used in `const` and `static` contexts, before the heap
+    /// has been set up. Until the first successful push, the vec has zero
+    /// capacity. A push fails (returning the item, as usual) if there is no
+    /// current heap, or if the heap is locked or out of memory at the time.
+    pub const fn new_lazy(capacity: usize) -> Self {
+        Self {
+            ptr: NonNull::dangling(),
+            len: 0,
+            lazy_capacity: capacity,
+            pd: PhantomData,
+        }
+    }
+
+    /// Returns whether the storage of the vec has been allocated.
+    pub fn is_allocated(&self) -> bool {
+        self.ptr != NonNull::dangling()
+    }
+
+    /// Obtain a pointer to the storage, as well as the capacity.
+    #[inline(always)]
+    fn storage(&self) -> (NonNull<MaybeUninit<T>>, usize) {
+        if !self.is_allocated() {
+            return (NonNull::dangling(), 0);
+        }
+        unsafe { ActiveArr::<MaybeUninit<T>>::data(self.ptr) }
+    }
+
+    /// Allocate the storage of a lazy vec, if it hasn't been already.
+    fn ensure_allocated(&mut self) {
+        if self.is_allocated() || self.lazy_capacity == 0 {
+            return;
+        }
+        let vec = AHeap::current()
+            .and_then(|heap| heap.lock().ok())
+            .and_then(|mut guard| guard.alloc_fixed_vec(self.lazy_capacity).ok());
+        if let Some(vec) = vec {
+            // We must still be empty, so the placeholder has nothing to drop
+            *self = vec;
+        }
+    }
+
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        self.ensure_allocated();
+        let (nn_ptr, count) = self.storage();
+        if count == self.len {
+            return Err(item);
+        }
+        unsafe {
+            nn_ptr.as_ptr().cast::<T>().add(self.len).write(item);
+            // Only count the slot once it has been written
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    /// Push the element returned by `f`, which is only called if there is
+    /// room for it.
+    ///
+    /// `f` runs before the vec is touched, so if it panics, the vec is left
+    /// as it was. If the vec is full, `f` is not called.
+    pub fn push_with<F: FnOnce() -> T>(&mut self, f: F) -> Result<(), FixedVecError> {
+        self.ensure_allocated();
+        if self.is_full() {
+            return Err(FixedVecError::CapacityExceeded {
+                requested: self.len + 1,
+                capacity: self.capacity(),
+            });
+        }
+        let item = f();
+        // `f` can't have touched the vec, so there is still room
+        let res = self.push(item);
+        debug_assert!(res.is_ok());
+        Ok(())
+    }
+
+    /// Convert the initialized elements into a `HeapArray`, without moving
+    /// them.
+    ///
+    /// The vec must be allocated.
+    pub(crate) fn into_initialized_array(self) -> HeapArray<T> {
+        debug_assert!(self.is_allocated());
+        // SAFETY: `MaybeUninit<T>` has the same layout as `T`, and the
+        // array only covers the initialized elements.
+        let arr = HeapArray {
+            ptr: self.ptr.cast::<ActiveArr<T>>(),
+            start: 0,
+            len: self.len,
+            pd: PhantomData,
+        };
+        forget(self);
+        arr
+    }
+
+    /// Convert a full vec into a `HeapArray`, without moving the elements
+    /// or reallocating. Otherwise, the vec is handed back unchanged.
+    ///
+    /// This is sound as every slot has been initialized, and
+    /// `MaybeUninit<T>` has the same layout as `T`, so the vec's node is
+    /// also a valid array node.
+    pub fn into_array(self) -> Result<HeapArray<T>, Self> {
+        if !self.is_allocated() || !self.is_full() {
+            return Err(self);
+        }
+        Ok(self.into_initialized_array())
+    }
+
+    /// Like [`HeapFixedVec::push`], but returns a mutable reference to the
+    /// newly pushed element.
+    pub fn push_mut(&mut self, item: T) -> Result<&mut T, T> {
+        self.ensure_allocated();
+        let (nn_ptr, count) = self.storage();
+        if count == self.len {
+            return Err(item);
+        }
+        unsafe {
+            let slot = nn_ptr.as_ptr().cast::<T>().add(self.len);
+            slot.write(item);
+            self.len += 1;
+            Ok(&mut *slot)
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        let (_nn_ptr, count) = self.storage();
+        count == self.len
+    }
+
+    /// The layout of the block that the heap allocated for this vec.
+    ///
+    /// This includes the allocator's per-allocation header, and any rounding
+    /// applied by the heap.
+    ///
+    /// If the vec has not been allocated yet, this is a zero-sized layout.
+    pub fn layout(&self) -> Layout {
+        if !self.is_allocated() {
+            return Layout::new::<()>();
+        }
+        unsafe { ActiveArr::<MaybeUninit<T>>::alloc_layout(self.ptr) }
+    }
+
+    /// Iterate mutably over the initialized elements (`0..len`), along with
+    /// their indices.
+    ///
+    /// Only the initialized elements are visited, never the uninitialized
+    /// spare capacity at the end of the storage.
+    pub fn indexed_iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut T)> {
+        let (nn_ptr, _count) = self.storage();
+        let init: &mut [MaybeUninit<T>] = unsafe { from_raw_parts_mut(nn_ptr.as_ptr(), self.len) };
+        init.iter_mut()
+            .map(|slot| unsafe { slot.assume_init_mut() })
+            .enumerate()
+    }
+
+    /// Remove and yield, by value, every element for which `pred` returns
+    /// true, keeping the order of the remaining elements.
+    ///
+    /// The remaining elements are shifted down as iteration proceeds. If the
+    /// iterator is dropped before it is exhausted, the rest of the elements
+    /// are still checked, and any matching ones are dropped.
+    pub fn extract_if<'a, F>(&'a mut self, pred: F) -> impl Iterator<Item = T> + 'a
+    where
+        F: FnMut(&mut T) -> bool + 'a,
+    {
+        let old_len = self.len;
+        // If the iterator is leaked, leak the elements rather than leaving
+        // moved-out elements in the initialized range.
+        self.len = 0;
+        ExtractIf {
+            vec: self,
+            idx: 0,
+            del: 0,
+            old_len,
+            pred,
+            panicked: false,
+        }
+    }
+
+    /// Swap the elements at indices `a` and `b`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either index is out of bounds of the initialized elements,
+    /// e.g. `>= self.len()`, even if it is within the capacity of the vec.
+    pub fn swap(&mut self, a: usize, b: usize) {
+        assert!(
+            a < self.len && b < self.len,
+            "swap indices ({a}, {b}) out of bounds for length {}",
+            self.len
+        );
+        (**self).swap(a, b);
+    }
+
+    /// Remove and return the last element, if there is one.
+    pub fn pop(&mut self) -> Option<T> {
+        let len = self.len.checked_sub(1)?;
+        unsafe {
+            let (nn_ptr, _count) = self.storage();
+            self.len = len;
+            Some(nn_ptr.as_ptr().add(len).cast::<T>().read())
+        }
+    }
+
+    /// Drop the elements from `new_len` onwards, if there are any.
+    ///
+    /// This has no effect if `new_len >= self.len()`, and never changes the
+    /// capacity of the vec.
+    ///
+    /// The length is updated before the elements are dropped, so if one of
+    /// their destructors panics, the vec never counts an element that has
+    /// already been dropped. The rest of the elements are still dropped.
+    pub fn truncate(&mut self, new_len: usize) {
+        let Some(tail) = self.len.checked_sub(new_len) else {
+            return;
+        };
+        let (nn_ptr, _count) = self.storage();
+        self.len = new_len;
+        unsafe {
+            let start = nn_ptr.as_ptr().add(new_len).cast::<T>();
+            drop_in_place(core::ptr::slice_from_raw_parts_mut(start, tail));
+        }
+    }
+
+    /// Drop all of the elements, see [`HeapFixedVec::truncate`].
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Sort the initialized elements (`0..len`) by the key that `f` extracts,
+    /// without preserving the order of equal elements.
+    ///
+    /// This never allocates, and never touches the spare capacity.
+    pub fn sort_unstable_by_key<K: Ord, F: FnMut(&T) -> K>(&mut self, f: F) {
+        (**self).sort_unstable_by_key(f);
+    }
+
+    /// Remove and return the element at `index`, replacing it with the last
+    /// element.
+    ///
+    /// This doesn't preserve the order of the elements, but is O(1).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds of the initialized elements.
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        assert!(
+            index < self.len,
+            "swap_remove index ({index}) out of bounds for length {}",
+            self.len
+        );
+        let last = self.len - 1;
+        (**self).swap(index, last);
+        unsafe {
+            let (nn_ptr, _count) = self.storage();
+            self.len = last;
+            nn_ptr.as_ptr().add(last).cast::<T>().read()
+        }
+    }
+
+    /// Decompose this vec into a pointer to its storage, its length, and its
+    /// capacity, without dropping any elements or freeing the allocation.
+    ///
+    /// The vec can be reassembled later with [`HeapFixedVec::from_raw_parts`].
+    pub fn into_raw_parts(self) -> (NonNull<MaybeUninit<T>>, usize, usize) {
+        let (nn_ptr, count) = self.storage();
+        let len = self.len;
+        forget(self);
+        (nn_ptr, len, count)
+    }
+
+    /// Re-takes ownership of a vec previously decomposed with
+    /// [`HeapFixedVec::into_raw_parts`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` and `capacity` MUST be the values returned by `into_raw_parts`,
+    /// the vec must not already have been reassembled, and the first `len`
+    /// elements of the storage MUST be initialized. `len` must not be larger
+    /// than `capacity`.
+    pub unsafe fn from_raw_parts(
+        ptr: NonNull<MaybeUninit<T>>,
+        len: usize,
+        capacity: usize,
+    ) -> Self {
+        if ptr == NonNull::dangling() && capacity == 0 {
+            // Never allocated
+            return Self::new_empty();
+        }
+        let aptr = ActiveArr::<MaybeUninit<T>>::from_leaked_ptr(ptr);
+        debug_assert_eq!(ActiveArr::<MaybeUninit<T>>::data(aptr).1, capacity);
+        debug_assert!(len <= capacity);
+        Self {
+            ptr: aptr,
+            len,
+            lazy_capacity: 0,
+            pd: PhantomData,
+        }
+    }
+
+    /// Move all of the elements of `other` onto the end of `self`, leaving
+    /// `other` empty.
+    ///
+    /// If `self` does not have enough remaining capacity to hold ALL of the
+    /// elements of `other`, an error is returned, and neither vec is modified.
+    pub fn append(&mut self, other: &mut HeapFixedVec<T>) -> Result<(), ()> {
+        self.ensure_allocated();
+        let (dst, count) = self.storage();
+        if count - self.len < other.len {
+            return Err(());
+        }
+        unsafe {
+            let (src, _count) = other.storage();
+            core::ptr::copy_nonoverlapping(
+                src.as_ptr().cast::<T>(),
+                dst.as_ptr().cast::<T>().add(self.len),
+                other.len,
+            );
+        }
+        // The moved elements are now owned by `self`, make sure `other`
+        // doesn't drop them too.
+        self.len += other.len;
+        other.len = 0;
+        Ok(())
+    }
 
-impl<T> DerefMut for HeapBox<T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { &mut *Active::<T>::data(self.ptr).as_ptr() }
+    /// The number of elements the vec can hold.
+    pub fn capacity(&self) -> usize {
+        let (_nn_ptr, count) = self.storage();
+        count
     }
-}
 
-impl<T> HeapBox<T> {
-    pub unsafe fn from_leaked(ptr: NonNull<T>) -> Self {
-        Self {
-            ptr: Active::<T>::from_leaked_ptr(ptr),
-            pd: PhantomData,
-        }
+    /// A pointer to the first element of the vec, e.g. for passing to C.
+    ///
+    /// The pointer is only valid for reading `len` elements, NOT the whole
+    /// capacity, and only until the vec is next modified. It is dangling
+    /// (but non-null and aligned) if the vec has no storage yet.
+    pub fn as_ptr(&self) -> *const T {
+        let (nn_ptr, _count) = self.storage();
+        nn_ptr.as_ptr().cast::<T>()
     }
 
-    /// Leak the contents of this box, never to be recovered (probably)
-    pub fn leak(self) -> NonNull<T> {
-        let nn = unsafe { Active::<T>::data(self.ptr) };
-        forget(self);
-        nn
+    /// A mutable pointer to the first element of the vec, e.g. for passing
+    /// to C.
+    ///
+    /// Like [`HeapFixedVec::as_ptr`], the pointer is only valid for `len`
+    /// elements. Use [`HeapFixedVec::spare_capacity_mut`] to write past the
+    /// end of the initialized elements.
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        let (nn_ptr, _count) = self.storage();
+        nn_ptr.as_ptr().cast::<T>()
     }
-}
 
-impl<T> Drop for HeapBox<T> {
-    fn drop(&mut self) {
-        unsafe {
-            let item_ptr = Active::<T>::data(self.ptr).as_ptr();
-            drop_in_place(item_ptr);
-            Active::<T>::yeet(self.ptr);
-        }
+    /// The unused capacity of the vec, from its length up to its capacity.
+    ///
+    /// This can be filled in place, e.g. by DMA, and then committed with
+    /// [`HeapFixedVec::set_len`].
+    pub fn spare_capacity_mut(&mut self) -> &mut [MaybeUninit<T>] {
+        self.ensure_allocated();
+        let (nn_ptr, count) = self.storage();
+        unsafe { from_raw_parts_mut(nn_ptr.as_ptr().add(self.len), count - self.len) }
     }
-}
 
-// === impl ArcInner ===
+    /// Obtain the initialized elements in `range`, or an error if it is out
+    /// of bounds, instead of panicking like indexing does.
+    ///
+    /// The range is checked against the length of the vec, not its capacity.
+    pub fn subslice(&self, range: impl RangeBounds<usize>) -> Result<&[T], OutOfBounds> {
+        let range = check_range(range, self.len)?;
+        Ok(&self[range])
+    }
 
-impl<T> ArcInner<T> {
-    pub unsafe fn from_leaked_ptr(data: NonNull<T>) -> NonNull<ArcInner<T>> {
-        let ptr = data
-            .cast::<u8>()
-            .as_ptr()
-            .offset(Self::data_offset())
-            .cast::<ArcInner<T>>();
-        NonNull::new_unchecked(ptr)
+    /// Like [`HeapFixedVec::subslice`], but obtains the elements mutably.
+    pub fn subslice_mut(
+        &mut self,
+        range: impl RangeBounds<usize>,
+    ) -> Result<&mut [T], OutOfBounds> {
+        let range = check_range(range, self.len)?;
+        Ok(&mut self[range])
     }
 
-    #[inline(always)]
-    fn data_offset() -> isize {
-        let dummy: ArcInner<MaybeUninit<T>> = ArcInner {
-            data: MaybeUninit::uninit(),
-            refcnt: AtomicUsize::new(0),
-        };
-        let dummy_ptr: *const ArcInner<MaybeUninit<T>> = &dummy;
-        let data_ptr = unsafe { addr_of!((*dummy_ptr).data) };
-        unsafe { dummy_ptr.cast::<u8>().offset_from(data_ptr.cast::<u8>()) }
+    /// Iterate over the initialized elements starting at `start`, wrapping
+    /// around to the front of the vec and ending at `start - 1`.
+    ///
+    /// This allows treating the vec as a ring, e.g. to read it oldest first
+    /// when `start` is the position of the next write. `start` is taken
+    /// modulo the length of the vec, so it can be a running position. Only
+    /// the first `len` elements are ever visited, never the spare capacity.
+    pub fn rotate_view(&self, start: usize) -> impl Iterator<Item = &T> {
+        let start = start.checked_rem(self.len).unwrap_or(0);
+        let (front, back) = self.split_at(start);
+        back.iter().chain(front)
     }
-}
 
-// === impl HeapArc ===
+    /// Set the length of the vec.
+    ///
+    /// ## Safety
+    ///
+    /// `new_len` must not be greater than the capacity, and the elements up
+    /// to `new_len` must be initialized. Any elements beyond `new_len` are
+    /// leaked rather than dropped.
+    pub unsafe fn set_len(&mut self, new_len: usize) {
+        debug_assert!(new_len <= self.capacity());
+        self.len = new_len;
+    }
 
-// These require the same bounds as `alloc::sync::Arc`'s `Send` and `Sync`
-// impls.
-unsafe impl<T: Send + Sync> Send for HeapArc<T> {}
-unsafe impl<T: Send + Sync> Sync for HeapArc<T> {}
+    fn check_len(&self, requested: Option<usize>) -> Result<usize, FixedVecError> {
+        let capacity = self.capacity();
+        match requested {
+            Some(requested) if requested <= capacity => Ok(requested),
+            requested => Err(FixedVecError::CapacityExceeded {
+                requested: requested.unwrap_or(usize::MAX),
+                capacity,
+            }),
+        }
+    }
 
-impl<T> HeapArc<T> {
-    /// Leak the contents of this box, never to be recovered (probably)
-    pub fn leak(self) -> NonNull<T> {
+    /// Insert `item` at `index`, shifting all of the elements after it up
+    /// by one.
+    ///
+    /// If the vec is full, `item` is handed back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is past the end of the initialized elements.
+    pub fn insert(&mut self, index: usize, item: T) -> Result<(), T> {
+        assert!(
+            index <= self.len,
+            "insert index ({index}) out of bounds for length {}",
+            self.len
+        );
+        self.ensure_allocated();
+        if self.is_full() {
+            return Err(item);
+        }
         unsafe {
-            let nn = Active::<ArcInner<T>>::data(self.ptr);
-            forget(self);
-            let data_ptr = addr_of_mut!((*nn.as_ptr()).data);
-            NonNull::new_unchecked(data_ptr)
+            let (nn_ptr, _count) = self.storage();
+            let slot = nn_ptr.as_ptr().cast::<T>().add(index);
+            core::ptr::copy(slot, slot.add(1), self.len - index);
+            slot.write(item);
         }
+        self.len += 1;
+        Ok(())
     }
 
-    /// Create a clone of a given leaked HeapArc<T>. DOES increase the refcount.
-    pub unsafe fn clone_from_leaked(ptr: NonNull<T>) -> Self {
-        let new = Self::from_leaked(ptr);
+    /// Remove and return the element at `index`, shifting all of the
+    /// elements after it down by one.
+    ///
+    /// See [`HeapFixedVec::swap_remove`] for an O(1) removal that doesn't
+    /// preserve the order of the elements.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds of the initialized elements.
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(
+            index < self.len,
+            "remove index ({index}) out of bounds for length {}",
+            self.len
+        );
+        unsafe {
+            let (nn_ptr, _count) = self.storage();
+            let slot = nn_ptr.as_ptr().cast::<T>().add(index);
+            let item = slot.read();
+            core::ptr::copy(slot.add(1), slot, self.len - index - 1);
+            self.len -= 1;
+            item
+        }
+    }
 
-        let aitem_nn = Active::<ArcInner<T>>::data(new.ptr);
-        aitem_nn.as_ref().refcnt.fetch_add(1, Ordering::SeqCst);
+    /// Push all of the items of `iter` onto the end of the vec.
+    ///
+    /// If the iterator's lower size bound shows that its items will not fit,
+    /// nothing is pushed. Otherwise, if the iterator turns out to have more
+    /// items than fit, the items that fit are pushed, the first one that did
+    /// not fit is dropped, and the rest are left in the iterator.
+    pub fn extend<I>(&mut self, iter: I) -> Result<(), FixedVecError>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        self.ensure_allocated();
+        let mut iter = iter.into_iter();
+        let (lower, _upper) = iter.size_hint();
+        self.check_len(self.len.checked_add(lower))?;
 
-        new
+        for item in iter.by_ref() {
+            if self.push(item).is_err() {
+                let (lower, _upper) = iter.size_hint();
+                let requested = self.len.checked_add(1).and_then(|n| n.checked_add(lower));
+                return self.check_len(requested).map(drop);
+            }
+        }
+        Ok(())
     }
 
-    /// Re-takes ownership of a leaked HeapArc<T>. Does NOT increase the refcount.
-    pub unsafe fn from_leaked(ptr: NonNull<T>) -> Self {
-        let arc_inner_nn: NonNull<ArcInner<T>> = ArcInner::from_leaked_ptr(ptr);
-        Self {
-            ptr: Active::<ArcInner<T>>::from_leaked_ptr(arc_inner_nn),
-            pd: PhantomData,
+    /// Push items from `iter` onto the end of the vec until it is full,
+    /// returning how many were pushed.
+    ///
+    /// No item is taken from the iterator once the vec is full, so nothing
+    /// is dropped: pass `iter.by_ref()` to keep the items that didn't fit.
+    pub fn push_all<I>(&mut self, iter: I) -> usize
+    where
+        I: IntoIterator<Item = T>,
+    {
+        self.ensure_allocated();
+        let mut iter = iter.into_iter();
+        let mut pushed = 0;
+        while self.len < self.capacity() {
+            let Some(item) = iter.next() else {
+                break;
+            };
+            // Can't fail, there's room
+            let _ = self.push(item);
+            pushed += 1;
         }
+        pushed
     }
+}
 
-    pub unsafe fn increment_count(ptr: NonNull<T>) {
-        let arc_inner_nn: NonNull<ArcInner<T>> = ArcInner::from_leaked_ptr(ptr);
-        arc_inner_nn.as_ref().refcnt.fetch_add(1, Ordering::SeqCst);
-    }
+struct ExtractIf<'a, T, F>
+where
+    F: FnMut(&mut T) -> bool,
+{
+    vec: &'a mut HeapFixedVec<T>,
+    // The next element to check
+    idx: usize,
+    // How many elements have been removed so far
+    del: usize,
+    old_len: usize,
+    pred: F,
+    // Set while `pred` is running, so that we don't call it again on drop if
+    // it panicked.
+    panicked: bool,
 }
 
-impl<T> Deref for HeapArc<T> {
-    type Target = T;
+impl<'a, T, F> Iterator for ExtractIf<'a, T, F>
+where
+    F: FnMut(&mut T) -> bool,
+{
+    type Item = T;
 
-    #[inline(always)]
-    fn deref(&self) -> &Self::Target {
-        unsafe {
-            let aiptr: *mut ArcInner<T> = Active::<ArcInner<T>>::data(self.ptr).as_ptr();
-            let dptr: *const T = addr_of!((*aiptr).data);
-            &*dptr
+    fn next(&mut self) -> Option<T> {
+        let (nn_ptr, _count) = self.vec.storage();
+        let base = nn_ptr.as_ptr().cast::<T>();
+        while self.idx < self.old_len {
+            let i = self.idx;
+            unsafe {
+                let cur = base.add(i);
+                self.panicked = true;
+                let matched = (self.pred)(&mut *cur);
+                self.panicked = false;
+                self.idx += 1;
+                if matched {
+                    self.del += 1;
+                    return Some(cur.read());
+                } else if self.del > 0 {
+                    core::ptr::copy_nonoverlapping(cur, base.add(i - self.del), 1);
+                }
+            }
         }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.old_len - self.idx))
     }
 }
 
-impl<T> Drop for HeapArc<T> {
+impl<'a, T, F> Drop for ExtractIf<'a, T, F>
+where
+    F: FnMut(&mut T) -> bool,
+{
     fn drop(&mut self) {
-        unsafe {
-            let (aiptr, needs_drop) = {
-                let aitem_ptr = Active::<ArcInner<T>>::data(self.ptr).as_ptr();
-                let old = (*aitem_ptr).refcnt.fetch_sub(1, Ordering::SeqCst);
-                debug_assert_ne!(old, 0);
-                (aitem_ptr, old == 1)
-            };
+        if !self.panicked {
+            self.for_each(drop);
+        }
 
-            if needs_drop {
-                drop_in_place(aiptr);
-                Active::<ArcInner<T>>::yeet(self.ptr);
+        // Shift down whatever is left unchecked (only if `pred` panicked),
+        // and restore the length.
+        let (nn_ptr, _count) = self.vec.storage();
+        let base = nn_ptr.as_ptr().cast::<T>();
+        let tail = self.old_len - self.idx;
+        if self.del > 0 && tail > 0 {
+            unsafe {
+                core::ptr::copy(base.add(self.idx), base.add(self.idx - self.del), tail);
             }
         }
+        self.vec.len = self.old_len - self.del;
     }
 }
 
-impl<T> Clone for HeapArc<T> {
-    fn clone(&self) -> Self {
-        unsafe {
-            let aitem_nn = Active::<ArcInner<T>>::data(self.ptr);
-            aitem_nn.as_ref().refcnt.fetch_add(1, Ordering::SeqCst);
-
-            HeapArc {
-                ptr: self.ptr,
-                pd: PhantomData,
+impl<T: Clone> HeapFixedVec<T> {
+    /// Resize the vec to `new_len` elements, either by dropping elements
+    /// from the end, or by pushing clones of `value`.
+    pub fn resize(&mut self, new_len: usize, value: T) -> Result<(), FixedVecError> {
+        self.ensure_allocated();
+        self.check_len(Some(new_len))?;
+        while self.len > new_len {
+            self.len -= 1;
+            unsafe {
+                let (nn_ptr, _count) = self.storage();
+                drop_in_place(nn_ptr.as_ptr().cast::<T>().add(self.len));
             }
         }
+        while self.len < new_len {
+            // Can't fail, we checked the capacity above
+            let _ = self.push(value.clone());
+        }
+        Ok(())
     }
-}
 
-impl<T: fmt::Display> fmt::Display for HeapArc<T> {
-    #[inline]
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Display::fmt(&**self, f)
+    /// Allocate a fixed vec with the given `capacity`, containing clones of
+    /// the elements of `src`.
+    ///
+    /// If cloning an element panics, the elements cloned so far are dropped
+    /// and the allocation is freed.
+    ///
+    /// # Panics
+    ///
+    /// If `src` is longer than `capacity`.
+    #[track_caller]
+    pub fn try_from_slice(
+        guard: &mut HeapGuard,
+        capacity: usize,
+        src: &[T],
+    ) -> Result<Self, AllocError> {
+        assert_slice_fits(capacity, src.len());
+        let mut vec = guard.alloc_fixed_vec(capacity)?;
+        for item in src {
+            // Can't fail, we checked the capacity above
+            let _ = vec.push(item.clone());
+        }
+        Ok(vec)
     }
-}
 
-impl<T: fmt::Debug> fmt::Debug for HeapArc<T> {
-    #[inline]
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(&**self, f)
+    /// Allocate a clone of this vec, with the same capacity.
+    ///
+    /// Only the initialized elements are cloned, the rest of the new vec's
+    /// capacity is left uninitialized. Cloning a lazy vec that hasn't been
+    /// allocated yet doesn't allocate, and produces another lazy vec.
+    pub fn try_clone(&self, guard: &mut HeapGuard) -> Result<Self, AllocError> {
+        if !self.is_allocated() {
+            return Ok(Self::new_lazy(self.lazy_capacity));
+        }
+        Self::try_from_slice(guard, self.capacity(), self)
     }
-}
 
-impl<T> fmt::Pointer for HeapArc<T> {
-    #[inline]
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Pointer::fmt(&self.ptr, f)
+    /// Overwrite all initialized elements (`0..len`) with clones of `value`.
+    ///
+    /// This does not change the length of the vec.
+    pub fn fill(&mut self, value: T) {
+        (**self).fill(value);
     }
 }
 
-// === impl HeapArray ===
-
-unsafe impl<T: Send> Send for HeapArray<T> {}
-unsafe impl<T: Sync> Sync for HeapArray<T> {}
-
-impl<T> Deref for HeapArray<T> {
-    type Target = [T];
-
-    fn deref(&self) -> &Self::Target {
+impl<T: Copy> HeapFixedVec<T> {
+    /// Like [`HeapFixedVec::try_from_slice`], but copies all of the elements
+    /// at once.
+    ///
+    /// # Panics
+    ///
+    /// If `src` is longer than `capacity`.
+    #[track_caller]
+    pub fn try_from_slice_copy(
+        guard: &mut HeapGuard,
+        capacity: usize,
+        src: &[T],
+    ) -> Result<Self, AllocError> {
+        assert_slice_fits(capacity, src.len());
+        let mut vec: Self = guard.alloc_fixed_vec(capacity)?;
         unsafe {
-            let (nn_ptr, count) = ActiveArr::<T>::data(self.ptr);
-            from_raw_parts(nn_ptr.as_ptr(), count)
+            let (nn_ptr, _count) = vec.storage();
+            core::ptr::copy_nonoverlapping(src.as_ptr(), nn_ptr.as_ptr().cast::<T>(), src.len());
         }
+        vec.len = src.len();
+        Ok(vec)
     }
-}
 
-impl<T> DerefMut for HeapArray<T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
+    /// Copy all of the elements of `src` onto the end of the vec, at once.
+    ///
+    /// If they don't all fit, nothing is copied, and the number of elements
+    /// that would have fit is returned.
+    pub fn extend_from_slice(&mut self, src: &[T]) -> Result<(), usize> {
+        self.ensure_allocated();
+        let (nn_ptr, count) = self.storage();
+        let room = count - self.len;
+        if src.len() > room {
+            return Err(room);
+        }
+        // SAFETY: There is room for `src` after the initialized elements, and
+        // `src` can't overlap with the spare capacity, as we hold `&mut self`.
         unsafe {
-            let (nn_ptr, count) = ActiveArr::<T>::data(self.ptr);
-            from_raw_parts_mut(nn_ptr.as_ptr(), count)
+            let dst = nn_ptr.as_ptr().add(self.len).cast::<T>();
+            core::ptr::copy_nonoverlapping(src.as_ptr(), dst, src.len());
+        }
+        self.len += src.len();
+        Ok(())
+    }
+
+    /// Like [`HeapFixedVec::try_clone`], but copies all of the elements at
+    /// once.
+    pub fn try_clone_copy(&self, guard: &mut HeapGuard) -> Result<Self, AllocError> {
+        if !self.is_allocated() {
+            return Ok(Self::new_lazy(self.lazy_capacity));
         }
+        Self::try_from_slice_copy(guard, self.capacity(), self)
     }
 }
 
-impl<T> HeapArray<T> {
-    // pub unsafe fn from_leaked(ptr: *mut T, count: usize) -> Self {
-    //     Self { ptr, count }
-    // }
+#[track_caller]
+fn assert_slice_fits(capacity: usize, len: usize) {
+    assert!(
+        len <= capacity,
+        "slice of {len} elements doesn't fit in a capacity of {capacity}"
+    );
+}
 
-    /// Leak the contents of this box, never to be recovered (probably)
-    pub fn leak(self) -> (NonNull<T>, usize) {
-        unsafe {
-            let (nn_ptr, count) = ActiveArr::<T>::data(self.ptr);
-            forget(self);
-            (nn_ptr, count)
+impl<T> IntoIterator for HeapFixedVec<T> {
+    type Item = T;
+    type IntoIter = HeapArrayIntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        if !self.is_allocated() {
+            return HeapArrayIntoIter {
+                ptr: None,
+                head: 0,
+                tail: 0,
+                pd: PhantomData,
+            };
         }
+        self.into_initialized_array().into_iter()
     }
 }
 
-impl<T> Drop for HeapArray<T> {
+impl<T> Drop for HeapFixedVec<T> {
     fn drop(&mut self) {
         unsafe {
-            let (start, count) = ActiveArr::<T>::data(self.ptr);
-            let start = start.as_ptr();
-            for i in 0..count {
-                drop_in_place(start.add(i));
+            if !self.is_allocated() {
+                return;
             }
-            ActiveArr::<T>::yeet(self.ptr);
+            let (start, _count) = self.storage();
+            drop_array(start.as_ptr().cast::<T>(), self.len, self.ptr);
         }
     }
 }
 
-impl<T> fmt::Debug for HeapArray<T>
+impl<T> fmt::Debug for HeapFixedVec<T>
 where
     [T]: fmt::Debug,
 {
@@ -281,71 +3608,216 @@ where
     }
 }
 
-impl<T> fmt::Pointer for HeapArray<T> {
+impl<T> fmt::Pointer for HeapFixedVec<T> {
     #[inline]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt::Pointer::fmt(&self.ptr, f)
     }
 }
 
-// === impl HeapFixedVec ===
+impl<T> AsRef<[T]> for HeapFixedVec<T> {
+    #[inline]
+    fn as_ref(&self) -> &[T] {
+        self
+    }
+}
 
-unsafe impl<T: Send> Send for HeapFixedVec<T> {}
-unsafe impl<T: Sync> Sync for HeapFixedVec<T> {}
+impl<T> AsMut<[T]> for HeapFixedVec<T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut [T] {
+        self
+    }
+}
 
-impl<T> Deref for HeapFixedVec<T> {
-    type Target = [T];
+impl<T> Borrow<[T]> for HeapFixedVec<T> {
+    #[inline]
+    fn borrow(&self) -> &[T] {
+        self
+    }
+}
 
-    fn deref(&self) -> &Self::Target {
-        unsafe {
-            let (nn_ptr, _count) = ActiveArr::<MaybeUninit<T>>::data(self.ptr);
-            from_raw_parts(nn_ptr.as_ptr().cast::<T>(), self.len)
+impl<T> BorrowMut<[T]> for HeapFixedVec<T> {
+    #[inline]
+    fn borrow_mut(&mut self) -> &mut [T] {
+        self
+    }
+}
+
+impl fmt::Display for FixedVecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FixedVecError::CapacityExceeded {
+                requested,
+                capacity,
+            } => write!(
+                f,
+                "needed room for {requested} elements, but capacity is {capacity}"
+            ),
+            FixedVecError::OutOfBounds { index, len } => {
+                write!(f, "index {index} is out of bounds for length {len}")
+            }
         }
     }
 }
 
-impl<T> DerefMut for HeapFixedVec<T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe {
-            let (nn_ptr, _count) = ActiveArr::<MaybeUninit<T>>::data(self.ptr);
-            from_raw_parts_mut(nn_ptr.as_ptr().cast::<T>(), self.len)
+impl fmt::Display for IterLengthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IterLengthError::TooShort { yielded, capacity } => write!(
+                f,
+                "iterator yielded {yielded} items, but capacity is {capacity}"
+            ),
+            IterLengthError::TooLong { capacity } => {
+                write!(f, "iterator yielded more than {capacity} items")
+            }
         }
     }
 }
 
-impl<T> HeapFixedVec<T> {
-    pub fn push(&mut self, item: T) -> Result<(), T> {
-        let (nn_ptr, count) = unsafe { ActiveArr::<MaybeUninit<T>>::data(self.ptr) };
-        if count == self.len {
-            return Err(item);
+impl fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { start, end, len } = self;
+        write!(f, "range {start}..{end} is out of bounds for length {len}")
+    }
+}
+
+/// Resolve `range` against a slice of length `len`, without panicking.
+fn check_range(range: impl RangeBounds<usize>, len: usize) -> Result<Range<usize>, OutOfBounds> {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start.saturating_add(1),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end.saturating_add(1),
+        Bound::Excluded(&end) => end,
+        Bound::Unbounded => len,
+    };
+    if start > end || end > len {
+        return Err(OutOfBounds { start, end, len });
+    }
+    Ok(start..end)
+}
+
+/// Allows formatting directly into a byte vec with `write!`.
+///
+/// Each `write_str` is all-or-nothing: if the string doesn't fit in the
+/// remaining capacity, nothing is written and `fmt::Error` is returned.
+impl fmt::Write for HeapFixedVec<u8> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.ensure_allocated();
+        let (nn_ptr, count) = self.storage();
+        if count - self.len < s.len() {
+            return Err(fmt::Error);
         }
         unsafe {
-            nn_ptr.as_ptr().cast::<T>().add(self.len).write(item);
-            self.len += 1;
+            core::ptr::copy_nonoverlapping(
+                s.as_ptr(),
+                nn_ptr.as_ptr().cast::<u8>().add(self.len),
+                s.len(),
+            );
         }
+        self.len += s.len();
         Ok(())
     }
+}
 
-    pub fn is_full(&self) -> bool {
-        let (_nn_ptr, count) = unsafe { ActiveArr::<MaybeUninit<T>>::data(self.ptr) };
-        count == self.len
+// === impl HeapVec ===
+
+impl<T> HeapVec<T> {
+    /// Create an empty vec, without allocating.
+    pub const fn new() -> Self {
+        Self {
+            inner: HeapFixedVec::new_empty(),
+        }
     }
-}
 
-impl<T> Drop for HeapFixedVec<T> {
-    fn drop(&mut self) {
+    /// The number of elements the vec can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    /// Append an element, first growing the vec if it is full.
+    ///
+    /// The capacity is doubled when growing, so pushing is amortized O(1).
+    pub async fn push(&mut self, heap: &'static AHeap, item: T) {
+        if self.len() == self.capacity() {
+            let new_capacity = core::cmp::max(4, self.capacity() * 2);
+            self.reallocate(heap, new_capacity).await;
+        }
+        // Can't fail, there's room now
+        let _ = self.inner.push(item);
+    }
+
+    /// Remove and return the last element, if there is one.
+    ///
+    /// This never shrinks the vec, see [`HeapVec::shrink_to_fit`].
+    pub fn pop(&mut self) -> Option<T> {
+        self.inner.pop()
+    }
+
+    /// Shrink the capacity of the vec to its length, returning the excess
+    /// memory to the heap.
+    ///
+    /// An empty vec frees its storage entirely.
+    pub async fn shrink_to_fit(&mut self, heap: &'static AHeap) {
+        self.shrink_to(heap, 0).await;
+    }
+
+    /// Shrink the capacity of the vec to its length, or to `min_capacity`,
+    /// whichever is larger.
+    ///
+    /// This does nothing if the capacity is already at most that large.
+    pub async fn shrink_to(&mut self, heap: &'static AHeap, min_capacity: usize) {
+        let new_capacity = core::cmp::max(self.len(), min_capacity);
+        if new_capacity < self.capacity() {
+            self.reallocate(heap, new_capacity).await;
+        }
+    }
+
+    /// Move the elements to a new allocation with room for `capacity`
+    /// elements, freeing the old one.
+    async fn reallocate(&mut self, heap: &'static AHeap, capacity: usize) {
+        debug_assert!(capacity >= self.len());
+        let mut new = if capacity == 0 {
+            HeapFixedVec::new_empty()
+        } else {
+            heap.allocate_fixed_vec(capacity).await
+        };
         unsafe {
-            let (start, _count) = ActiveArr::<MaybeUninit<T>>::data(self.ptr);
-            let start = start.as_ptr().cast::<T>();
-            for i in 0..self.len {
-                drop_in_place(start.add(i));
-            }
-            ActiveArr::<MaybeUninit<T>>::yeet(self.ptr);
+            let (src, _count) = self.inner.storage();
+            let (dst, _count) = new.storage();
+            core::ptr::copy_nonoverlapping(src.as_ptr(), dst.as_ptr(), self.inner.len);
         }
+        // The moved elements are now owned by `new`, make sure the old
+        // storage doesn't drop them too.
+        new.len = self.inner.len;
+        self.inner.len = 0;
+        self.inner = new;
     }
 }
 
-impl<T> fmt::Debug for HeapFixedVec<T>
+impl<T> Default for HeapVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Deref for HeapVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for HeapVec<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<T> fmt::Debug for HeapVec<T>
 where
     [T]: fmt::Debug,
 {
@@ -354,10 +3826,3 @@ where
         fmt::Debug::fmt(&**self, f)
     }
 }
-
-impl<T> fmt::Pointer for HeapFixedVec<T> {
-    #[inline]
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Pointer::fmt(&self.ptr, f)
-    }
-}
@@ -2,14 +2,22 @@
 use core::{
     alloc::Layout,
     cell::UnsafeCell,
+    fmt,
+    future::{poll_fn, Future},
     marker::PhantomData,
-    mem::MaybeUninit,
-    ptr::NonNull,
-    sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+    mem::{forget, MaybeUninit},
+    panic::Location,
+    pin::pin,
+    ptr::{addr_of_mut, null_mut, NonNull},
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicUsize, Ordering},
+    task::Poll,
 };
 
 use crate::{
-    containers::{ArcInner, HeapArc, HeapArray, HeapBox, HeapFixedVec},
+    containers::{
+        ArcInner, HeapAlignedBox, HeapArc, HeapArcArray, HeapArray, HeapArrayBuilder, HeapBox,
+        HeapFixedVec, HeapSharableBox, IterLengthError, RefCount,
+    },
     node::{Active, ActiveArr, ActiveUnsized, Node, NodeRef, Recycle},
 };
 
@@ -17,14 +25,144 @@ use cordyceps::mpsc_queue::{Links, MpscQueue};
 use linked_list_allocator::Heap;
 use maitake::sync::WaitQueue;
 
+/// The heap used by the "current heap" convenience constructors.
+static CURRENT_HEAP: AtomicPtr<AHeap> = AtomicPtr::new(null_mut());
+
 /// An Anachro Heap item
 pub struct AHeap {
     freelist: MpscQueue<Recycle>,
     state: AtomicU8,
     heap: UnsafeCell<Heap>,
     heap_wait: WaitQueue,
-    inhibit_alloc: AtomicBool,
+    /// The key of the waiting allocation that goes next, or `NO_WAITER`.
+    /// While set, other async allocations wait their turn, see `Waiter`.
+    queue_head: AtomicUsize,
+    /// Hands out the tickets that order waiting allocations
+    next_ticket: AtomicUsize,
     any_frees: AtomicBool,
+    /// Incremented every time a node is released, used by waiting
+    /// allocations to detect frees that raced with registering for a wakeup
+    free_epoch: AtomicUsize,
+    /// Set when `lock` fails, so that the holder of the lock lets waiting
+    /// allocations retry once it is released
+    lock_contended: AtomicBool,
+    /// See `HeapBuilder::min_alloc_granularity`
+    granularity: usize,
+    /// See `HeapBuilder::strategy`
+    strategy: AllocStrategy,
+    /// The bounds of the region managed by the underlying allocator
+    region_start: usize,
+    region_end: usize,
+    /// See `HeapBuilder::with_node_cache`. The layout of the cached blocks is
+    /// fixed at bootstrap. Each shard of the cache has its own lock, separate
+    /// from the heap's.
+    cache_layout: Option<Layout>,
+    cache_capacity: usize,
+    cache: [CacheShard; CACHE_SHARDS],
+    cache_hits: AtomicUsize,
+    cache_misses: AtomicUsize,
+    /// See `AHeap::alloc_count` and `AHeap::free_count`
+    alloc_count: AtomicUsize,
+    free_count: AtomicUsize,
+    /// See `AHeap::set_low_watermark`. A threshold of zero means disabled.
+    low_watermark: AtomicUsize,
+    low_watermark_cb: AtomicPtr<()>,
+    /// Set once the callback has fired, until free space recovers
+    below_watermark: AtomicBool,
+    /// See `AHeap::size_histogram`
+    #[cfg(feature = "size-histogram")]
+    size_histogram: [core::sync::atomic::AtomicU32; AHeap::HISTOGRAM_BUCKETS],
+    /// See `AHeap::used_bytes`, `AHeap::free_bytes` and
+    /// `AHeap::high_water_mark`.
+    #[cfg(feature = "heap-accounting")]
+    total_bytes: AtomicUsize,
+    #[cfg(feature = "heap-accounting")]
+    used_bytes: AtomicUsize,
+    #[cfg(feature = "heap-accounting")]
+    high_water_mark: AtomicUsize,
+    /// See `HeapGuard::set_trace_hook`. Only accessed while locked.
+    #[cfg(feature = "trace-alloc")]
+    trace_hook: UnsafeCell<Option<&'static dyn AllocTraceHook>>,
+    /// Additional regions, see `HeapGuard::add_region`.
+    ///
+    /// This list is only ever appended to (while locked), so it can be
+    /// walked at any time.
+    regions: AtomicPtr<Region>,
+}
+
+/// An additional region of memory managed by an `AHeap`.
+///
+/// This header lives at the start of the region itself.
+struct Region {
+    next: AtomicPtr<Region>,
+    start: usize,
+    end: usize,
+    /// Only accessed while the `AHeap` is locked
+    heap: UnsafeCell<Heap>,
+}
+
+/// A region of memory was too small to be added to a heap.
+///
+/// See [`HeapGuard::add_region`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionTooSmall;
+
+/// The number of shards the node cache is split into.
+///
+/// Each shard has its own lock, and threads start looking for a block in
+/// different shards, so allocations and frees of the cached size class
+/// rarely contend with each other, and never with the heap's lock.
+const CACHE_SHARDS: usize = 4;
+
+/// One shard of the node cache, see `CACHE_SHARDS`: a free-list of
+/// same-sized blocks, used to skip the underlying allocator for one hot size
+/// class.
+struct CacheShard {
+    /// The first block in the list, or `CacheShard::LOCKED` while a thread
+    /// is using the shard
+    head: AtomicPtr<CacheSlot>,
+    /// Only accessed while the shard is locked
+    len: UnsafeCell<usize>,
+}
+
+/// The header written into a block while it sits in the node cache
+struct CacheSlot {
+    next: *mut CacheSlot,
+}
+
+/// Observes the allocations and frees made by an [`AHeap`], e.g. to track
+/// down leaks.
+///
+/// Registered with [`HeapGuard::set_trace_hook`]. The hook is called while
+/// the heap is locked, so it must not attempt to allocate from the same heap.
+///
+/// Only available with the `trace-alloc` feature.
+#[cfg(feature = "trace-alloc")]
+pub trait AllocTraceHook: Sync {
+    /// Called after allocating the block at `ptr`, to hold a `type_name`.
+    ///
+    /// `layout` is that of the whole block, including any headers. Arrays
+    /// are reported as `[T]`, and raw allocations as `[u8]`.
+    fn on_alloc(&self, ptr: NonNull<u8>, layout: Layout, type_name: &'static str);
+
+    /// Called before freeing the block at `ptr`, with the same `layout` it
+    /// was allocated with.
+    ///
+    /// The type is not known at this point, but can be recovered by
+    /// matching `ptr` against a previous call to `on_alloc`.
+    fn on_free(&self, ptr: NonNull<u8>, layout: Layout);
+}
+
+/// Hit and miss counts for a heap's node cache.
+///
+/// See [`HeapBuilder::with_node_cache`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeCacheStats {
+    /// Allocations that were served directly from the cache.
+    pub hits: usize,
+    /// Allocations of the cached size class that found the cache empty, and
+    /// fell back to the main heap.
+    pub misses: usize,
 }
 
 // SAFETY: Safety is checked through the `state` member, which uses
@@ -42,6 +180,14 @@ impl AHeap {
     /// time while the heap is being initialized.
     const BUSY_LOCKED: u8 = 2;
 
+    /// `queue_head` when no allocation is waiting its turn.
+    const NO_WAITER: usize = usize::MAX;
+
+    /// The priority used by allocations that don't specify one.
+    ///
+    /// This is the lowest priority, see [`AHeap::allocate_prioritized`].
+    pub const DEFAULT_PRIORITY: u8 = 0;
+
     /// Construct a thread safe async allocator from a pool of memory.
     ///
     /// Safety: The pool of memory MUST be valid for the 'static lifetime, e.g.
@@ -49,89 +195,526 @@ impl AHeap {
     /// Additionally, we must semantically have exclusive access to this region
     /// of memory: There must be no other live references, or pointers to this
     /// region that are dereferenced after this call.
+    ///
+    /// See [`HeapBuilder`] for constructing a heap with non-default options.
     pub unsafe fn bootstrap(addr: *mut u8, size: usize) -> Result<(NonNull<Self>, HeapGuard), ()> {
-        // First, we go all bump-allocator to emplace ourselves within this region
-        let mut cursor = addr;
-        let end = (addr as usize).checked_add(size).ok_or(())?;
-        let mut used = 0;
+        HeapBuilder::new().bootstrap(addr, size)
+    }
 
-        let stub_ptr;
-        let aheap_ptr;
+    /// Round `layout` up to the heap's minimum allocation granularity.
+    ///
+    /// This is applied both when allocating and when freeing, so the block
+    /// returned to the underlying allocator always matches the block that
+    /// was carved out of it.
+    pub(crate) fn round_layout(&self, layout: Layout) -> Option<Layout> {
+        let gran = self.granularity;
+        if gran <= 1 {
+            return Some(layout);
+        }
+        let size = layout.size().checked_add(gran - 1)? / gran * gran;
+        Layout::from_size_align(size, layout.align()).ok()
+    }
 
-        // We start with the stub node required for our mpsc queue.
-        {
-            let stub_layout = Layout::new::<Recycle>();
-            let stub_offset = cursor.align_offset(stub_layout.align());
-            let stub_size = stub_layout.size();
-            used += stub_offset;
-            used += stub_size;
+    /// Returns whether `ptr` points into the region of memory managed by
+    /// this heap.
+    ///
+    /// This is only a range check: it does NOT mean that `ptr` is (or was)
+    /// a live allocation.
+    pub fn owns(&self, ptr: NonNull<u8>) -> bool {
+        let addr = ptr.as_ptr().addr();
+        (self.region_start..self.region_end).contains(&addr)
+            || self.regions().any(|region| region.contains(addr))
+    }
 
-            if used > size {
-                return Err(());
-            }
+    /// The largest alignment that this heap can provide.
+    ///
+    /// This depends on the addresses of the heap's regions, rather than on
+    /// their contents: it is the largest power of two that has a multiple
+    /// inside one of them. Allocations aligned to more than this fail with
+    /// [`AllocErrorKind::UnsupportedAlign`], which can never succeed. It may
+    /// increase when a region is added with [`HeapGuard::add_region`].
+    pub fn max_align(&self) -> usize {
+        self.regions()
+            .map(|region| max_align_in(region.start, region.end))
+            .fold(max_align_in(self.region_start, self.region_end), usize::max)
+    }
 
-            cursor = cursor.wrapping_add(stub_offset);
-            stub_ptr = cursor.cast::<Recycle>();
-            stub_ptr.write(Recycle {
-                links: Links::new_stub(),
-                node_layout: stub_layout,
-            });
-            cursor = cursor.add(stub_size);
+    /// Iterate over the additional regions, in priority order.
+    fn regions(&self) -> impl Iterator<Item = &Region> {
+        let mut next = self.regions.load(Ordering::Acquire);
+        core::iter::from_fn(move || {
+            // SAFETY: regions are never removed, and live for 'static
+            let region = unsafe { next.as_ref()? };
+            next = region.next.load(Ordering::Acquire);
+            Some(region)
+        })
+    }
+
+    /// Call `callback` when the amount of free memory drops below
+    /// `threshold_bytes`.
+    ///
+    /// The callback is called at the end of the allocation that first takes
+    /// the free memory below the threshold. It is not called again until
+    /// enough memory has been freed to go back above the threshold, so it
+    /// fires once per "low memory" episode rather than on every allocation.
+    /// This can be used to start shedding load before allocations actually
+    /// start failing.
+    ///
+    /// NOTE: The callback runs while the heap is locked, so any attempt to
+    /// allocate from it (on this heap) will fail.
+    ///
+    /// A `threshold_bytes` of zero disables the callback.
+    pub fn set_low_watermark(&self, threshold_bytes: usize, callback: fn()) {
+        self.low_watermark_cb
+            .store(callback as *mut (), Ordering::Release);
+        self.below_watermark.store(false, Ordering::Release);
+        self.low_watermark.store(threshold_bytes, Ordering::Release);
+    }
+
+    /// The placement strategy this heap was built with.
+    pub fn strategy(&self) -> AllocStrategy {
+        self.strategy
+    }
+
+    /// Obtain the hit and miss counts of the node cache.
+    ///
+    /// These are always zero if the heap was built without a node cache.
+    pub fn node_cache_stats(&self) -> NodeCacheStats {
+        NodeCacheStats {
+            hits: self.cache_hits.load(Ordering::Relaxed),
+            misses: self.cache_misses.load(Ordering::Relaxed),
         }
+    }
 
-        // Next we allocate ourselves
-        {
-            let aheap_layout = Layout::new::<Self>();
-            let aheap_offset = cursor.align_offset(aheap_layout.align());
-            let aheap_size = aheap_layout.size();
-            used += aheap_offset;
-            used += aheap_size;
+    /// The total number of allocations made from this heap.
+    ///
+    /// This can be read at any time without locking the heap, e.g. to
+    /// sample the allocation rate. It wraps around on overflow.
+    pub fn alloc_count(&self) -> usize {
+        self.alloc_count.load(Ordering::Relaxed)
+    }
 
-            if used > size {
-                return Err(());
-            }
+    /// The total number of allocations freed back to this heap.
+    ///
+    /// Like [`AHeap::alloc_count`], this doesn't lock the heap, and wraps
+    /// around on overflow. Frees are counted when the memory is actually
+    /// returned to the heap, which may be some time after the allocation
+    /// was dropped, if the heap was locked at the time.
+    pub fn free_count(&self) -> usize {
+        self.free_count.load(Ordering::Relaxed)
+    }
 
-            cursor = cursor.wrapping_add(aheap_offset);
-            aheap_ptr = cursor.cast::<Self>();
+    /// The number of buckets in [`AHeap::size_histogram`].
+    #[cfg(feature = "size-histogram")]
+    pub const HISTOGRAM_BUCKETS: usize = 16;
 
-            // Increment the cursor, as we use it for the heap initialization
-            cursor = cursor.add(aheap_size);
+    /// The number of allocations made from this heap, by size.
+    ///
+    /// Bucket `i` counts the allocations of `2^i` up to (but not including)
+    /// `2^(i + 1)` bytes, except that the first bucket also counts empty
+    /// allocations, and the last counts everything larger. Sizes are those
+    /// of the whole block, including the allocator's per-allocation header,
+    /// but before rounding to [`HeapBuilder::min_alloc_granularity`].
+    ///
+    /// Like [`AHeap::alloc_count`], this doesn't lock the heap, and the
+    /// counts wrap around on overflow. This is useful for choosing a size
+    /// class for [`HeapBuilder::with_node_cache`].
+    ///
+    /// Only available with the `size-histogram` feature.
+    #[cfg(feature = "size-histogram")]
+    pub fn size_histogram(&self) -> [u32; AHeap::HISTOGRAM_BUCKETS] {
+        core::array::from_fn(|i| self.size_histogram[i].load(Ordering::Relaxed))
+    }
 
-            let heap = Heap::new(cursor, end - (cursor as usize));
+    /// The number of bytes currently allocated from this heap.
+    ///
+    /// This counts the whole block of every live allocation, including the
+    /// allocator's per-allocation header and any rounding (to
+    /// [`HeapBuilder::min_alloc_granularity`], the node cache's size class,
+    /// or by the underlying allocator itself), so that it adds up with
+    /// [`AHeap::free_bytes`] to the total size of the heap (across all
+    /// regions).
+    ///
+    /// Like [`AHeap::alloc_count`], this doesn't lock the heap. The counters
+    /// are atomic, and only updated while the heap is locked, but they are
+    /// loaded separately, so a snapshot may be slightly out of date. Frees
+    /// are counted when the memory is actually returned to the heap, see
+    /// [`AHeap::free_count`].
+    ///
+    /// Only available with the `heap-accounting` feature (on by default).
+    #[cfg(feature = "heap-accounting")]
+    pub fn used_bytes(&self) -> usize {
+        self.used_bytes.load(Ordering::Relaxed)
+    }
 
-            aheap_ptr.write(Self {
-                freelist: MpscQueue::new_with_static_stub(&*stub_ptr),
-                state: AtomicU8::new(Self::BUSY_LOCKED),
-                heap: UnsafeCell::new(heap),
-                heap_wait: WaitQueue::new(),
-                inhibit_alloc: AtomicBool::new(false),
-                any_frees: AtomicBool::new(false),
-            });
+    /// The number of bytes of this heap not currently allocated.
+    ///
+    /// This is the total size of the heap minus [`AHeap::used_bytes`]. The
+    /// free bytes may be fragmented, so an allocation of this size will not
+    /// necessarily succeed.
+    ///
+    /// Only available with the `heap-accounting` feature (on by default).
+    #[cfg(feature = "heap-accounting")]
+    pub fn free_bytes(&self) -> usize {
+        // A region may be added, and allocated from, between the two loads
+        self.total_bytes
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.used_bytes())
+    }
+
+    /// The largest value [`AHeap::used_bytes`] has reached so far.
+    ///
+    /// Only available with the `heap-accounting` feature (on by default).
+    #[cfg(feature = "heap-accounting")]
+    pub fn high_water_mark(&self) -> usize {
+        self.high_water_mark.load(Ordering::Relaxed)
+    }
+
+    /// Reset all of the counts in [`AHeap::size_histogram`] to zero.
+    ///
+    /// Only available with the `size-histogram` feature.
+    #[cfg(feature = "size-histogram")]
+    pub fn reset_histogram(&self) {
+        for bucket in &self.size_histogram {
+            bucket.store(0, Ordering::Relaxed);
         }
+    }
 
-        // Everything else is now our allocation space.
-        let aheap = NonNull::new_unchecked(aheap_ptr);
-        let aheap_ref: &'static AHeap = aheap.as_ref();
+    /// If `layout` (already rounded) falls in the node cache's size class,
+    /// returns the layout of the cache's blocks.
+    fn cache_class(&self, layout: Layout) -> Option<Layout> {
+        let class = self.cache_layout?;
+        (layout.size() <= class.size() && layout.align() <= class.align()).then_some(class)
+    }
 
-        // Creating exclusive access to the inner heap is acceptable, as we
-        // have marked ourselves with "BUSY_LOCKED", acting as a mutex.
-        let guard = HeapGuard { aheap: aheap_ref };
+    /// The shard of the node cache the caller should try first.
+    ///
+    /// Threads run on separate stacks, so hashing the address of a local
+    /// spreads them out over the shards. Deterministic heaps always start
+    /// from the first shard, so that placement doesn't depend on where the
+    /// stack happens to be.
+    fn cache_home(&self) -> usize {
+        if self.strategy == AllocStrategy::Deterministic {
+            return 0;
+        }
+        let probe = 0u8;
+        let page = core::ptr::addr_of!(probe).addr() >> 12;
+        let hash = page.wrapping_mul(0x9E37_79B9_7F4A_7C15_u64 as usize);
+        hash >> (usize::BITS - CACHE_SHARDS.trailing_zeros())
+    }
 
-        // Well that went great, I think!
-        Ok((aheap, guard))
+    /// Take a block from the node cache, skipping any shards that are busy.
+    fn cache_pop(&self) -> Option<NonNull<u8>> {
+        let home = self.cache_home();
+        (0..CACHE_SHARDS).find_map(|i| self.cache[(home + i) % CACHE_SHARDS].pop())
+    }
+
+    /// Return a block to the node cache, returning `false` if every shard
+    /// was full or busy.
+    ///
+    /// # Safety
+    ///
+    /// `block` must be a free block of the node cache's layout.
+    unsafe fn cache_push(&self, block: NonNull<u8>) -> bool {
+        let home = self.cache_home();
+        (0..CACHE_SHARDS).any(|i| {
+            let shard = (home + i) % CACHE_SHARDS;
+            // Split the capacity between the shards, so they add up to it
+            let capacity = self.cache_capacity / CACHE_SHARDS
+                + usize::from(shard < self.cache_capacity % CACHE_SHARDS);
+            self.cache[shard].push(block, capacity)
+        })
+    }
+
+    /// Whether nodes of the node cache's size class may be allocated from,
+    /// and freed to, the cache without locking the heap.
+    ///
+    /// The trace hook may only be called while the heap is locked, and
+    /// deterministic heaps must not depend on which shards happen to be
+    /// busy, so both always go through the lock.
+    fn cache_bypasses_lock(&self) -> bool {
+        !cfg!(feature = "trace-alloc") && self.strategy == AllocStrategy::FirstFit
+    }
+
+    /// Allocate a node of `layout` from the node cache, without locking the
+    /// heap.
+    ///
+    /// Returns `None` if the node isn't of the cached size class, or the
+    /// cache had no block to spare, in which case the heap must be locked.
+    fn alloc_cached(&self, layout: Layout) -> Option<NonNull<u8>> {
+        if !self.cache_bypasses_lock() {
+            return None;
+        }
+        self.cache_class(self.round_layout(layout)?)?;
+        let block = self.cache_pop()?;
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+        // Taking a block from the cache doesn't change the free space in the
+        // heap, so the low watermark can't have been crossed.
+        self.count_alloc(layout);
+        Some(block)
+    }
+
+    /// Free a node of `layout` to the node cache, without locking the heap.
+    ///
+    /// Returns `false` if it isn't of the cached size class, or the cache
+    /// had no room for it, in which case the heap must be locked.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a node allocated from this heap with `layout`.
+    unsafe fn free_cached(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        if !self.cache_bypasses_lock() {
+            return false;
+        }
+        match self.round_layout(layout) {
+            Some(rounded) if self.cache_class(rounded).is_some() => {}
+            _ => return false,
+        }
+        if !self.cache_push(ptr) {
+            return false;
+        }
+        self.count_free(layout);
+        true
+    }
+
+    /// Initialize a freshly allocated node for a `T` as a box holding `data`.
+    ///
+    /// # Safety
+    ///
+    /// `block` must have been allocated from this heap for a `Node<T>`.
+    unsafe fn init_box<T>(&'static self, block: NonNull<u8>, data: T) -> HeapBox<T> {
+        let nn = block.cast::<Active<T>>();
+        Active::<T>::write_heap(nn, self);
+        Active::<T>::data(nn).as_ptr().write(data);
+        HeapBox {
+            ptr: nn,
+            pd: PhantomData,
+        }
+    }
+
+    /// Initialize a freshly allocated node as an arc holding `data`,
+    /// recording `location` as its creation site when the `debug-arc`
+    /// feature is enabled.
+    ///
+    /// # Safety
+    ///
+    /// `block` must have been allocated from this heap for a
+    /// `Node<ArcInner<T>>`.
+    #[cfg_attr(not(feature = "debug-arc"), allow(unused_variables))]
+    unsafe fn init_arc<T>(
+        &'static self,
+        block: NonNull<u8>,
+        data: T,
+        location: &'static Location<'static>,
+    ) -> HeapArc<T> {
+        let nn = block.cast::<Active<ArcInner<T>>>();
+        Active::<ArcInner<T>>::write_heap(nn, self);
+        Active::<ArcInner<T>>::data(nn).as_ptr().write(ArcInner {
+            refcnt: RefCount::new(1),
+            weak: RefCount::new(1),
+            on_last_drop: None,
+            #[cfg(feature = "debug-arc")]
+            location,
+            data,
+        });
+        HeapArc {
+            ptr: nn,
+            pd: PhantomData,
+        }
+    }
+
+    /// Update the counters for an allocation of `layout`, before rounding.
+    fn count_alloc(&self, layout: Layout) {
+        self.alloc_count.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "heap-accounting")]
+        {
+            // The block has already been allocated, so this can't fail
+            let size = backend_size(self.block_layout(layout).unwrap());
+            let used = self.used_bytes.fetch_add(size, Ordering::Relaxed) + size;
+            self.high_water_mark.fetch_max(used, Ordering::Relaxed);
+        }
+        #[cfg(feature = "size-histogram")]
+        {
+            let bucket = layout.size().checked_ilog2().unwrap_or(0) as usize;
+            let bucket = core::cmp::min(bucket, AHeap::HISTOGRAM_BUCKETS - 1);
+            self.size_histogram[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Update the counters for a free of `layout`, before rounding.
+    fn count_free(&self, layout: Layout) {
+        self.free_count.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "heap-accounting")]
+        {
+            // This was already successfully computed when the block was
+            // allocated.
+            let block = self.block_layout(layout).unwrap();
+            self.used_bytes
+                .fetch_sub(backend_size(block), Ordering::Relaxed);
+        }
+    }
+
+    /// The layout of the block actually carved out of the heap for a node
+    /// of the given `layout`, after rounding and the node cache are taken
+    /// into account.
+    pub(crate) fn block_layout(&self, layout: Layout) -> Option<Layout> {
+        let layout = self.round_layout(layout)?;
+        Some(self.cache_class(layout).unwrap_or(layout))
+    }
+
+    /// Attempt to resize a raw allocation, WITHOUT moving it.
+    ///
+    /// This only succeeds if an allocation of the `new` layout would occupy
+    /// exactly the same block of memory as the existing allocation of the
+    /// `old` layout (e.g. thanks to the heap's allocation granularity), in
+    /// which case nothing needs to be done, other than using `new` from now
+    /// on, including when calling [`deallocate_raw`]. Otherwise, the
+    /// allocation is left untouched, and it is up to the caller to allocate
+    /// a new block and move the contents themselves.
+    ///
+    /// NOTE: None of the allocation methods of this crate ever move a live
+    /// allocation, so pointers to the contents of a raw allocation remain
+    /// valid until it is freed.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` *must* have been returned by [`AHeap::allocate_raw`] or
+    /// [`HeapGuard::alloc_raw`] on this heap, with the `old` layout (or a
+    /// layout it was successfully resized to).
+    pub unsafe fn reallocate_raw_in_place_only(
+        &self,
+        ptr: NonNull<()>,
+        old: Layout,
+        new: Layout,
+    ) -> Result<(), ReallocMoved> {
+        debug_assert!(self.owns(ptr.cast()));
+
+        let (old_node, old_offset) = ActiveUnsized::layout(old).ok_or(ReallocMoved)?;
+        let (new_node, new_offset) = ActiveUnsized::layout(new).ok_or(ReallocMoved)?;
+        // The contents must stay at the same offset from the start of the
+        // block, so the header can still be found from `ptr`.
+        if old_offset != new_offset {
+            return Err(ReallocMoved);
+        }
+        match (self.block_layout(old_node), self.block_layout(new_node)) {
+            (Some(old_block), Some(new_block)) if old_block == new_block => Ok(()),
+            _ => Err(ReallocMoved),
+        }
     }
 
     pub(crate) unsafe fn release_node(&'static self, node: NonNull<Recycle>) {
-        // Can we immediately lock the allocator, avoiding the free list?
-        if let Ok(mut guard) = self.lock() {
-            let layout: Layout = (*node.as_ptr()).node_layout;
-            guard.get_heap().deallocate(node.cast::<u8>(), layout);
+        debug_assert!(
+            self.owns(node.cast()),
+            "freed a pointer ({:p}) that was not allocated by this heap",
+            node
+        );
+
+        // Blocks of the node cache's size class can usually go straight back
+        // to the cache, without locking the allocator at all.
+        let layout: Layout = (*node.as_ptr()).node_layout;
+        if self.free_cached(node.cast::<u8>(), layout) {
+            self.notify_free();
             return;
         }
 
-        // Nope! Stick it in the free list
-        let node_ref = NodeRef { node };
-        self.freelist.enqueue(node_ref);
+        // Can we immediately lock the allocator, avoiding the free list?
+        //
+        // Deterministic heaps always use the free list, so that whether or
+        // not the lock happens to be held doesn't affect placement.
+        let guard = match self.strategy {
+            AllocStrategy::FirstFit => self.lock().ok(),
+            AllocStrategy::Deterministic => None,
+        };
+        if let Some(mut guard) = guard {
+            guard.dealloc_layout(node.cast::<u8>(), layout);
+            drop(guard);
+        } else {
+            // Nope! Stick it in the free list. Waiting allocations will clean
+            // it up when they retry.
+            let node_ref = NodeRef { node };
+            self.freelist.enqueue(node_ref);
+        }
+
+        self.notify_free();
+    }
+
+    /// Let any waiting allocations know that memory has been released.
+    fn notify_free(&self) {
+        self.free_epoch.fetch_add(1, Ordering::SeqCst);
+        self.heap_wait.wake_all();
+    }
+
+    fn waiter(&'static self, priority: u8) -> Waiter {
+        Waiter {
+            heap: self,
+            key: None,
+            priority,
+        }
+    }
+
+    /// Wait until memory has been released, after a failed allocation attempt.
+    ///
+    /// `epoch` is the value of `free_epoch` observed BEFORE the failed
+    /// attempt. We register for a wakeup first, and only then check whether
+    /// anything has been released since, so a free that races with the
+    /// failed attempt can't be missed (which would leave us waiting forever).
+    async fn wait_for_free(&'static self, epoch: usize) {
+        let mut wait = pin!(self.heap_wait.wait());
+
+        // Poll once to register our waker
+        let registered = poll_fn(|cx| match wait.as_mut().poll(cx) {
+            Poll::Ready(res) => {
+                res.unwrap();
+                Poll::Ready(false)
+            }
+            Poll::Pending => Poll::Ready(true),
+        })
+        .await;
+
+        if registered && self.free_epoch.load(Ordering::SeqCst) == epoch {
+            wait.await.unwrap();
+        }
+    }
+
+    /// Returns an error if an allocation of a node of `layout` could never
+    /// succeed, however much memory is freed: if it is aligned to more than
+    /// [`AHeap::max_align`], or larger than any of the heap's regions.
+    fn check_fits(&self, layout: Layout) -> Result<(), AllocError> {
+        let block = self
+            .block_layout(layout)
+            .ok_or_else(|| AllocError::new(layout, AllocErrorKind::Overflow))?;
+        if block.align() > self.max_align() {
+            return Err(AllocError::new(block, AllocErrorKind::UnsupportedAlign));
+        }
+        if block.size() > self.largest_region() {
+            return Err(AllocError::new(block, AllocErrorKind::TooLarge));
+        }
+        Ok(())
+    }
+
+    /// `check_fits` for an array of `count` `T`s.
+    fn check_fits_arr<T>(&self, count: usize, align: usize) -> Result<(), AllocError> {
+        let (layout, _) =
+            ActiveArr::<T>::layout_for_arr(count, align).ok_or_else(|| layout_error::<T>(align))?;
+        self.check_fits(layout)
+    }
+
+    /// `check_fits` for a raw allocation of `layout`.
+    fn check_fits_raw(&self, layout: Layout) -> Result<(), AllocError> {
+        let (node, _) = ActiveUnsized::layout(layout)
+            .ok_or_else(|| AllocError::new(layout, AllocErrorKind::Overflow))?;
+        self.check_fits(node)
+    }
+
+    /// The size of the largest of the heap's regions.
+    ///
+    /// This includes any headers at the start of the region, so it is an
+    /// upper bound on the size of a single allocation, not necessarily an
+    /// achievable one.
+    fn largest_region(&self) -> usize {
+        self.regions()
+            .map(|region| region.end - region.start)
+            .fold(self.region_end - self.region_start, usize::max)
     }
 
     pub fn poll(&'static self) {
@@ -142,30 +725,138 @@ impl AHeap {
 
         // Did we perform any deallocations?
         if self.any_frees.swap(false, Ordering::SeqCst) {
-            // Clear the inhibit flag
-            self.inhibit_alloc.store(false, Ordering::SeqCst);
-
             // Wake any tasks waiting on alloc
             self.heap_wait.wake_all();
         }
     }
 
     pub fn lock(&'static self) -> Result<HeapGuard, u8> {
-        self.state.compare_exchange(
-            Self::INIT_IDLE,
-            Self::BUSY_LOCKED,
-            Ordering::SeqCst,
-            Ordering::SeqCst,
-        )?;
+        let acquire = || {
+            self.state.compare_exchange(
+                Self::INIT_IDLE,
+                Self::BUSY_LOCKED,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+        };
+        if acquire().is_err() {
+            // The heap being busy doesn't mean it's out of memory, so ask
+            // the holder to wake up any allocations that go on to wait for a
+            // free. Trying again after asking means that the holder can't
+            // release the lock in between without noticing.
+            self.lock_contended.store(true, Ordering::SeqCst);
+            acquire()?;
+        }
 
         // SAFETY: We are already in the BUSY_LOCKED state, we have exclusive access.
         Ok(HeapGuard { aheap: self })
     }
 
-    pub async fn allocate<T>(&'static self, mut item: T) -> HeapBox<T> {
+    /// Lock the heap for a non-blocking allocation, if it is idle and no
+    /// async allocation is waiting its turn (which would go first).
+    ///
+    /// Unlike [`AHeap::lock`], failing leaves no trace: the holder of the
+    /// lock isn't asked to wake anyone up when it is released.
+    fn try_lock_now(&'static self) -> Option<HeapGuard> {
+        if self.queue_head.load(Ordering::Acquire) != AHeap::NO_WAITER {
+            return None;
+        }
+        self.state
+            .compare_exchange(
+                Self::INIT_IDLE,
+                Self::BUSY_LOCKED,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .ok()?;
+
+        // SAFETY: We are now in the BUSY_LOCKED state, we have exclusive access.
+        Some(HeapGuard { aheap: self })
+    }
+
+    /// Make this the "current" heap, used by convenience constructors such
+    /// as [`HeapBox::new`], [`HeapArc::new`], and [`HeapArray::new`].
+    ///
+    /// NOTE: There is a single, global current heap (rather than one per task
+    /// or thread). This is intended for systems with one dominant heap; use
+    /// the explicit `AHeap` methods when working with multiple heaps.
+    pub fn set_current(&'static self) {
+        CURRENT_HEAP.store(core::ptr::from_ref(self).cast_mut(), Ordering::Release);
+    }
+
+    /// Run `f` with this as the current heap, restoring the previous current
+    /// heap (if any) afterwards.
+    ///
+    /// NOTE: This swaps the single, global current heap, so it is NOT scoped
+    /// to the calling task or thread. While `f` runs, every other thread also
+    /// sees this heap as current, and two threads calling this at once will
+    /// clobber each other's heap (and may restore the wrong one afterwards).
+    /// Only use it where nothing else is using the current heap concurrently.
+    pub fn with_current<R>(&'static self, f: impl FnOnce() -> R) -> R {
+        struct Restore(*mut AHeap);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                CURRENT_HEAP.store(self.0, Ordering::Release);
+            }
+        }
+
+        let prev = CURRENT_HEAP.swap(core::ptr::from_ref(self).cast_mut(), Ordering::AcqRel);
+        let _restore = Restore(prev);
+        f()
+    }
+
+    /// Obtain the current heap, as set by [`AHeap::set_current`] or
+    /// [`AHeap::with_current`].
+    pub fn current() -> Option<&'static AHeap> {
+        unsafe { CURRENT_HEAP.load(Ordering::Acquire).as_ref() }
+    }
+
+    /// Like [`AHeap::current`], but panics if there is no current heap.
+    #[track_caller]
+    pub(crate) fn expect_current() -> &'static AHeap {
+        match Self::current() {
+            Some(heap) => heap,
+            None => panic!("no current heap is set! Call `AHeap::set_current` first"),
+        }
+    }
+
+    pub async fn allocate<T>(&'static self, item: T) -> HeapBox<T> {
+        self.allocate_prioritized(item, AHeap::DEFAULT_PRIORITY)
+            .await
+    }
+
+    /// Like [`AHeap::allocate`], but with a priority for when the allocation
+    /// has to wait for space.
+    ///
+    /// Allocations that have to wait are served in order: the highest
+    /// priority first, and the oldest first among equal priorities. While
+    /// one is waiting its turn, newer async allocations wait behind it, even
+    /// if they would fit, so that a large allocation can't be starved by a
+    /// stream of small ones. Note that this means a task that holds on to
+    /// memory while waiting behind a request that needs that memory will
+    /// never make progress.
+    ///
+    /// ## Panics
+    ///
+    /// If the allocation could never succeed, no matter how much memory is
+    /// freed, because it is larger than any of the heap's regions. This
+    /// applies to all of the async allocation methods, which would
+    /// otherwise wait (and hold up every other allocation) forever. See
+    /// [`AHeap::allocate_checked`] and friends for variants that return an
+    /// error instead.
+    pub async fn allocate_prioritized<T>(&'static self, mut item: T, priority: u8) -> HeapBox<T> {
+        assert_fits(self.check_fits(Layout::new::<Node<T>>()));
+        let mut waiter = self.waiter(priority);
         loop {
-            // Is the heap inhibited?
-            if !self.inhibit_alloc.load(Ordering::Acquire) {
+            let epoch = self.free_epoch.load(Ordering::SeqCst);
+
+            // Is it our turn?
+            if waiter.is_turn() {
+                // Small allocations can often skip the heap's lock entirely
+                if let Some(block) = self.alloc_cached(Layout::new::<Node<T>>()) {
+                    return unsafe { self.init_box(block, item) };
+                }
                 // Can we get an exclusive heap handle?
                 if let Ok(mut hg) = self.lock() {
                     // Can we allocate our item?
@@ -180,51 +871,205 @@ impl AHeap {
                         }
                     }
                 }
-                // We weren't inhibited before, but something failed. Inhibit
-                // further allocations to prevent starving waiting allocations
-                self.inhibit_alloc.store(true, Ordering::Release);
             }
+            // Either something failed, or an older allocation goes first.
+            // Get in line, so that newer allocations can't starve us.
+            waiter.enqueue();
 
             // Didn't succeed, wait until we've done some de-allocations
-            self.heap_wait.wait().await.unwrap();
+            self.wait_for_free(epoch).await;
         }
     }
 
-    pub async fn allocate_arc<T>(&'static self, mut item: T) -> HeapArc<T> {
-        loop {
-            // Is the heap inhibited?
-            if !self.inhibit_alloc.load(Ordering::Acquire) {
-                // Can we get an exclusive heap handle?
-                if let Ok(mut hg) = self.lock() {
-                    // Can we allocate our item?
-                    match hg.alloc_arc(item) {
-                        Ok(hb) => {
-                            // Yes! Return our allocated item
-                            return hb;
-                        }
-                        Err(it) => {
-                            // Nope, the allocation failed.
-                            item = it;
+    /// Like [`AHeap::allocate`], but if the allocation could never succeed,
+    /// however much memory is freed, returns an error (handing `item` back)
+    /// rather than panicking.
+    ///
+    /// The error is [`AllocErrorKind::TooLarge`], or
+    /// [`AllocErrorKind::UnsupportedAlign`] for over-aligned types. An
+    /// allocation that could fit still waits for space, unlike with the
+    /// `try_` methods, such as [`AHeap::try_alloc_box`], which never wait.
+    pub async fn allocate_checked<T>(
+        &'static self,
+        item: T,
+    ) -> Result<HeapBox<T>, (T, AllocError)> {
+        match self.check_fits(Layout::new::<Node<T>>()) {
+            Ok(()) => Ok(self.allocate(item).await),
+            Err(err) => Err((item, err)),
+        }
+    }
+
+    /// Make a single, non-blocking attempt to allocate a [`HeapBox`].
+    ///
+    /// This is usable from contexts that can't `.await`, such as interrupts.
+    /// It fails, handing `item` back, if the heap is currently locked, if
+    /// an async allocation is waiting its turn (which goes first), or if
+    /// there isn't enough space. A failed attempt doesn't wait in line, or
+    /// register for a wakeup.
+    ///
+    /// If the heap is busy, the error is [`AllocErrorKind::Busy`].
+    pub fn try_alloc_box<T>(&'static self, item: T) -> Result<HeapBox<T>, (T, AllocError)> {
+        match self.try_lock_now() {
+            Some(mut hg) => hg.alloc_box_or_err(item),
+            None => Err((
+                item,
+                AllocError::new(Layout::new::<Node<T>>(), AllocErrorKind::Busy),
+            )),
+        }
+    }
+
+    #[cfg_attr(feature = "debug-arc", track_caller)]
+    pub fn allocate_arc<T>(&'static self, mut item: T) -> impl Future<Output = HeapArc<T>> {
+        // `async fn`s can't be `#[track_caller]`, so grab the caller before
+        // handing off to the future.
+        let location = Location::caller();
+        assert_fits(self.check_fits(Layout::new::<Node<ArcInner<T>>>()));
+        async move {
+            let mut waiter = self.waiter(AHeap::DEFAULT_PRIORITY);
+            loop {
+                let epoch = self.free_epoch.load(Ordering::SeqCst);
+
+                // Is it our turn?
+                if waiter.is_turn() {
+                    // Small allocations can often skip the heap's lock entirely
+                    if let Some(block) = self.alloc_cached(Layout::new::<Node<ArcInner<T>>>()) {
+                        return unsafe { self.init_arc(block, item, location) };
+                    }
+                    // Can we get an exclusive heap handle?
+                    if let Ok(mut hg) = self.lock() {
+                        // Can we allocate our item?
+                        match hg.alloc_arc_at(item, location) {
+                            Ok(hb) => {
+                                // Yes! Return our allocated item
+                                return hb;
+                            }
+                            Err((it, _)) => {
+                                // Nope, the allocation failed.
+                                item = it;
+                            }
                         }
                     }
                 }
-                // We weren't inhibited before, but something failed. Inhibit
-                // further allocations to prevent starving waiting allocations
-                self.inhibit_alloc.store(true, Ordering::Release);
+                // Either something failed, or an older allocation goes first.
+                // Get in line, so that newer allocations can't starve us.
+                waiter.enqueue();
+
+                // Didn't succeed, wait until we've done some de-allocations
+                self.wait_for_free(epoch).await;
             }
+        }
+    }
 
-            // Didn't succeed, wait until we've done some de-allocations
-            self.heap_wait.wait().await.unwrap();
+    /// Like [`AHeap::allocate_arc`], but `on_last_drop` is called on the
+    /// contents right before they are dropped, once the last arc to them is
+    /// dropped. See [`HeapArc::new_with_drop`].
+    #[cfg_attr(feature = "debug-arc", track_caller)]
+    pub fn allocate_arc_with_drop<T>(
+        &'static self,
+        item: T,
+        on_last_drop: fn(&mut T),
+    ) -> impl Future<Output = HeapArc<T>> {
+        let arc = self.allocate_arc(item);
+        async move {
+            let mut arc = arc.await;
+            // SAFETY: Nobody else has seen the arc yet
+            unsafe { HeapArc::set_on_last_drop(&mut arc, on_last_drop) };
+            arc
         }
     }
 
-    pub async fn allocate_array_with<F, T>(&'static self, f: F, count: usize) -> HeapArray<T>
-    where
-        F: Fn() -> T,
-    {
+    /// Like [`AHeap::allocate_arc`], but returns an error (handing `item`
+    /// back) rather than panicking if the allocation could never succeed,
+    /// see [`AHeap::allocate_checked`].
+    #[cfg_attr(feature = "debug-arc", track_caller)]
+    pub fn allocate_arc_checked<T>(
+        &'static self,
+        item: T,
+    ) -> impl Future<Output = Result<HeapArc<T>, (T, AllocError)>> {
+        let res = match self.check_fits(Layout::new::<Node<ArcInner<T>>>()) {
+            Ok(()) => Ok(self.allocate_arc(item)),
+            Err(err) => Err((item, err)),
+        };
+        async move {
+            match res {
+                Ok(fut) => Ok(fut.await),
+                Err(err) => Err(err),
+            }
+        }
+    }
+
+    /// Move the contents of `boxed` into a new [`HeapArc`], waiting for space
+    /// to become available if necessary.
+    ///
+    /// See [`HeapGuard::alloc_arc_from_box`] for details.
+    #[cfg_attr(feature = "debug-arc", track_caller)]
+    pub fn arc_from_box<T>(
+        &'static self,
+        mut boxed: HeapBox<T>,
+    ) -> impl Future<Output = HeapArc<T>> {
+        // `async fn`s can't be `#[track_caller]`, so grab the caller before
+        // handing off to the future.
+        let location = Location::caller();
+        assert_fits(self.check_fits(Layout::new::<Node<ArcInner<T>>>()));
+        async move {
+            let mut waiter = self.waiter(AHeap::DEFAULT_PRIORITY);
+            loop {
+                let epoch = self.free_epoch.load(Ordering::SeqCst);
+
+                // Is it our turn?
+                if waiter.is_turn() {
+                    // Can we get an exclusive heap handle?
+                    if let Ok(mut hg) = self.lock() {
+                        // Can we allocate our item?
+                        match hg.alloc_arc_from_box_at(boxed, location) {
+                            Ok(arc) => {
+                                // Yes! Return our allocated item
+                                return arc;
+                            }
+                            Err(b) => {
+                                // Nope, the allocation failed.
+                                boxed = b;
+                            }
+                        }
+                    }
+                }
+                // Either something failed, or an older allocation goes first.
+                // Get in line, so that newer allocations can't starve us.
+                waiter.enqueue();
+
+                // Didn't succeed, wait until we've done some de-allocations
+                self.wait_for_free(epoch).await;
+            }
+        }
+    }
+
+    /// Make a single, non-blocking attempt to allocate a [`HeapArc`].
+    ///
+    /// This is usable from contexts that can't `.await`, such as interrupts.
+    /// Like [`AHeap::try_alloc_box`], it fails, handing `item` back along
+    /// with the reason, if the heap is busy or there isn't enough space.
+    #[cfg_attr(feature = "debug-arc", track_caller)]
+    pub fn try_alloc_arc<T>(&'static self, item: T) -> Result<HeapArc<T>, (T, AllocError)> {
+        match self.try_lock_now() {
+            Some(mut hg) => hg.alloc_arc_at(item, Location::caller()),
+            None => Err((
+                item,
+                AllocError::new(Layout::new::<Node<ArcInner<T>>>(), AllocErrorKind::Busy),
+            )),
+        }
+    }
+
+    pub async fn allocate_array_with<F, T>(&'static self, f: F, count: usize) -> HeapArray<T>
+    where
+        F: Fn() -> T,
+    {
+        assert_fits(self.check_fits_arr::<T>(count, 1));
+        let mut waiter = self.waiter(AHeap::DEFAULT_PRIORITY);
         loop {
-            // Is the heap inhibited?
-            if !self.inhibit_alloc.load(Ordering::Acquire) {
+            let epoch = self.free_epoch.load(Ordering::SeqCst);
+
+            // Is it our turn?
+            if waiter.is_turn() {
                 // Can we get an exclusive heap handle?
                 if let Ok(mut hg) = self.lock() {
                     // Can we allocate our item?
@@ -241,20 +1086,307 @@ impl AHeap {
                         }
                     }
                 }
-                // We weren't inhibited before, but something failed. Inhibit
-                // further allocations to prevent starving waiting allocations
-                self.inhibit_alloc.store(true, Ordering::Release);
             }
+            // Either something failed, or an older allocation goes first.
+            // Get in line, so that newer allocations can't starve us.
+            waiter.enqueue();
+
+            // Didn't succeed, wait until we've done some de-allocations
+            self.wait_for_free(epoch).await;
+        }
+    }
+
+    /// Like [`AHeap::allocate_array_with`], but returns an error rather than
+    /// panicking if the allocation could never succeed, see
+    /// [`AHeap::allocate_checked`].
+    pub async fn allocate_array_with_checked<F, T>(
+        &'static self,
+        f: F,
+        count: usize,
+    ) -> Result<HeapArray<T>, AllocError>
+    where
+        F: Fn() -> T,
+    {
+        self.check_fits_arr::<T>(count, 1)?;
+        Ok(self.allocate_array_with(f, count).await)
+    }
+
+    /// Make a single, non-blocking attempt to allocate an array.
+    ///
+    /// Like [`AHeap::try_alloc_box`], this never waits. If the heap is busy,
+    /// the error is [`AllocErrorKind::Busy`], and `f` is never called.
+    pub fn try_alloc_array_with<F, T>(
+        &'static self,
+        f: F,
+        count: usize,
+    ) -> Result<HeapArray<T>, AllocError>
+    where
+        F: Fn() -> T,
+    {
+        match self.try_lock_now() {
+            Some(mut hg) => hg.alloc_box_array_with(f, count),
+            None => {
+                let layout = ActiveArr::<T>::layout_for_arr(count, 1)
+                    .map_or(Layout::new::<T>(), |(layout, _)| layout);
+                Err(AllocError::new(layout, AllocErrorKind::Busy))
+            }
+        }
+    }
+
+    /// Allocate an array whose storage is aligned to at least `align` bytes,
+    /// waiting for space to become available if necessary.
+    ///
+    /// ## Panics
+    ///
+    /// If `align` is not a power of two, or is larger than
+    /// [`AHeap::max_align`], or if the allocation is larger than any of the
+    /// heap's regions.
+    #[track_caller]
+    pub fn allocate_array_aligned_with<F, T>(
+        &'static self,
+        f: F,
+        count: usize,
+        align: usize,
+    ) -> impl Future<Output = HeapArray<T>>
+    where
+        F: Fn() -> T,
+    {
+        // Check before handing off to the future, so that an impossible
+        // request panics at the call site.
+        assert_fits(self.check_fits_arr::<T>(count, align));
+        async move {
+            let mut waiter = self.waiter(AHeap::DEFAULT_PRIORITY);
+            loop {
+                let epoch = self.free_epoch.load(Ordering::SeqCst);
+
+                // Is it our turn?
+                if waiter.is_turn() {
+                    // Can we get an exclusive heap handle?
+                    if let Ok(mut hg) = self.lock() {
+                        // Can we allocate our item?
+                        match hg.alloc_box_array_aligned_with(&f, count, align) {
+                            Ok(hb) => {
+                                // Yes! Return our allocated item
+                                return hb;
+                            }
+                            Err(_) => {
+                                // Nope, the allocation failed.
+                            }
+                        }
+                    }
+                }
+                // Either something failed, or an older allocation goes first.
+                // Get in line, so that newer allocations can't starve us.
+                waiter.enqueue();
+
+                // Didn't succeed, wait until we've done some de-allocations
+                self.wait_for_free(epoch).await;
+            }
+        }
+    }
+
+    /// Allocate a `HeapBox` containing an uninitialized `[T; N]`, waiting
+    /// for space to become available if necessary.
+    ///
+    /// Use [`HeapBox::init_with`] to fill it in place.
+    pub async fn allocate_array_const<T, const N: usize>(
+        &'static self,
+    ) -> HeapBox<[MaybeUninit<T>; N]> {
+        assert_fits(self.check_fits_arr::<T>(N, 1));
+        let mut waiter = self.waiter(AHeap::DEFAULT_PRIORITY);
+        loop {
+            let epoch = self.free_epoch.load(Ordering::SeqCst);
+
+            // Is it our turn?
+            if waiter.is_turn() {
+                // Can we get an exclusive heap handle?
+                if let Ok(mut hg) = self.lock() {
+                    match hg.alloc_array_const() {
+                        Ok(hb) => {
+                            // Yes! Return our allocated item
+                            return hb;
+                        }
+                        Err(_) => {
+                            // Nope, the allocation failed.
+                        }
+                    }
+                }
+            }
+            // Either something failed, or an older allocation goes first.
+            // Get in line, so that newer allocations can't starve us.
+            waiter.enqueue();
+
+            // Didn't succeed, wait until we've done some de-allocations
+            self.wait_for_free(epoch).await;
+        }
+    }
+
+    /// Allocate a `HeapBox` containing an uninitialized `T`, waiting for
+    /// space to become available if necessary.
+    ///
+    /// Write the value in place, then use [`HeapBox::assume_init`].
+    pub async fn allocate_uninit<T>(&'static self) -> HeapBox<MaybeUninit<T>> {
+        assert_fits(self.check_fits(Layout::new::<Node<MaybeUninit<T>>>()));
+        let mut waiter = self.waiter(AHeap::DEFAULT_PRIORITY);
+        loop {
+            let epoch = self.free_epoch.load(Ordering::SeqCst);
+
+            // Is it our turn?
+            if waiter.is_turn() {
+                // Can we get an exclusive heap handle?
+                if let Ok(mut hg) = self.lock() {
+                    match hg.alloc_uninit() {
+                        Ok(hb) => {
+                            // Yes! Return our allocated item
+                            return hb;
+                        }
+                        Err(_) => {
+                            // Nope, the allocation failed.
+                        }
+                    }
+                }
+            }
+            // Either something failed, or an older allocation goes first.
+            // Get in line, so that newer allocations can't starve us.
+            waiter.enqueue();
+
+            // Didn't succeed, wait until we've done some de-allocations
+            self.wait_for_free(epoch).await;
+        }
+    }
+
+    /// Allocate an array of exactly `capacity` elements, holding the items
+    /// of `iter`, waiting for space to become available if necessary.
+    ///
+    /// See [`HeapGuard::alloc_array_from_iter`] for details.
+    pub async fn allocate_array_from_iter<T, I>(
+        &'static self,
+        iter: I,
+        capacity: usize,
+    ) -> Result<HeapArray<T>, IterLengthError>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut iter = iter.into_iter();
+        assert_fits(self.check_fits_arr::<T>(capacity, 1));
+        let mut waiter = self.waiter(AHeap::DEFAULT_PRIORITY);
+        loop {
+            let epoch = self.free_epoch.load(Ordering::SeqCst);
+
+            // Is it our turn?
+            if waiter.is_turn() {
+                // Can we get an exclusive heap handle?
+                if let Ok(mut hg) = self.lock() {
+                    // NOTE: The iterator is only advanced once the allocation
+                    // has succeeded, so we can retry with it later.
+                    match hg.alloc_array_from_iter(&mut iter, capacity) {
+                        Ok(res) => {
+                            // Yes! Return our allocated item (or the mismatch)
+                            return res;
+                        }
+                        Err(_) => {
+                            // Nope, the allocation failed.
+                        }
+                    }
+                }
+            }
+            // Either something failed, or an older allocation goes first.
+            // Get in line, so that newer allocations can't starve us.
+            waiter.enqueue();
+
+            // Didn't succeed, wait until we've done some de-allocations
+            self.wait_for_free(epoch).await;
+        }
+    }
+
+    /// Allocate an array holding the items of `iter`, waiting for space to
+    /// become available if necessary.
+    ///
+    /// See [`HeapGuard::alloc_array_from_exact`] for details.
+    pub async fn allocate_array_from_exact<T, I>(&'static self, iter: I) -> HeapArray<T>
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let mut iter = iter.into_iter();
+        assert_fits(self.check_fits_arr::<T>(iter.len(), 1));
+        let mut waiter = self.waiter(AHeap::DEFAULT_PRIORITY);
+        loop {
+            let epoch = self.free_epoch.load(Ordering::SeqCst);
+
+            // Is it our turn?
+            if waiter.is_turn() {
+                // Can we get an exclusive heap handle?
+                if let Ok(mut hg) = self.lock() {
+                    // NOTE: The iterator is only advanced once the allocation
+                    // has succeeded, so we can retry with it later.
+                    match hg.alloc_array_from_exact(&mut iter) {
+                        Ok(hb) => {
+                            // Yes! Return our allocated item
+                            return hb;
+                        }
+                        Err(_) => {
+                            // Nope, the allocation failed.
+                        }
+                    }
+                }
+            }
+            // Either something failed, or an older allocation goes first.
+            // Get in line, so that newer allocations can't starve us.
+            waiter.enqueue();
 
             // Didn't succeed, wait until we've done some de-allocations
-            self.heap_wait.wait().await.unwrap();
+            self.wait_for_free(epoch).await;
+        }
+    }
+
+    /// Allocate an array holding the elements of `array`, waiting for space
+    /// to become available if necessary.
+    ///
+    /// The elements are moved into the array, not cloned.
+    pub async fn allocate_array_from<T, const N: usize>(
+        &'static self,
+        mut array: [T; N],
+    ) -> HeapArray<T> {
+        assert_fits(self.check_fits_arr::<T>(N, 1));
+        let mut waiter = self.waiter(AHeap::DEFAULT_PRIORITY);
+        loop {
+            let epoch = self.free_epoch.load(Ordering::SeqCst);
+
+            // Is it our turn?
+            if waiter.is_turn() {
+                // Can we get an exclusive heap handle?
+                if let Ok(mut hg) = self.lock() {
+                    // Can we allocate our array?
+                    match hg.alloc_array_from(array) {
+                        Ok(ha) => {
+                            // Yes! Return our allocated array
+                            return ha;
+                        }
+                        Err(arr) => {
+                            // Nope, the allocation failed.
+                            array = arr;
+                        }
+                    }
+                }
+            }
+            // Either something failed, or an older allocation goes first.
+            // Get in line, so that newer allocations can't starve us.
+            waiter.enqueue();
+
+            // Didn't succeed, wait until we've done some de-allocations
+            self.wait_for_free(epoch).await;
         }
     }
 
     pub async fn allocate_fixed_vec<T>(&'static self, capacity: usize) -> HeapFixedVec<T> {
+        assert_fits(self.check_fits_arr::<T>(capacity, 1));
+        let mut waiter = self.waiter(AHeap::DEFAULT_PRIORITY);
         loop {
-            // Is the heap inhibited?
-            if !self.inhibit_alloc.load(Ordering::Acquire) {
+            let epoch = self.free_epoch.load(Ordering::SeqCst);
+
+            // Is it our turn?
+            if waiter.is_turn() {
                 // Can we get an exclusive heap handle?
                 if let Ok(mut hg) = self.lock() {
                     match hg.alloc_fixed_vec(capacity) {
@@ -267,20 +1399,182 @@ impl AHeap {
                         }
                     }
                 }
-                // We weren't inhibited before, but something failed. Inhibit
-                // further allocations to prevent starving waiting allocations
-                self.inhibit_alloc.store(true, Ordering::Release);
             }
+            // Either something failed, or an older allocation goes first.
+            // Get in line, so that newer allocations can't starve us.
+            waiter.enqueue();
 
             // Didn't succeed, wait until we've done some de-allocations
-            self.heap_wait.wait().await.unwrap();
+            self.wait_for_free(epoch).await;
         }
     }
 
+    /// Allocate a jagged array of `rows` rows, waiting for space to become
+    /// available if necessary.
+    ///
+    /// See [`HeapGuard::alloc_jagged_with`]. If there isn't room for all of
+    /// the rows, the rows allocated so far are freed before waiting, and the
+    /// whole array is built again (calling `row_len` and `f` again) on the
+    /// next attempt. `row_len` is also called for every row up front, to
+    /// check that each of them could ever fit.
+    pub async fn allocate_jagged_with<T, L, F>(
+        &'static self,
+        rows: usize,
+        mut row_len: L,
+        mut f: F,
+    ) -> HeapArray<HeapArray<T>>
+    where
+        L: FnMut(usize) -> usize,
+        F: FnMut(usize, usize) -> T,
+    {
+        assert_fits(self.check_fits_arr::<HeapArray<T>>(rows, 1));
+        for r in 0..rows {
+            assert_fits(self.check_fits_arr::<T>(row_len(r), 1));
+        }
+        let mut waiter = self.waiter(AHeap::DEFAULT_PRIORITY);
+        loop {
+            let epoch = self.free_epoch.load(Ordering::SeqCst);
+
+            // Is it our turn?
+            if waiter.is_turn() {
+                // Can we get an exclusive heap handle?
+                if let Ok(mut hg) = self.lock() {
+                    // Can we allocate our item?
+                    match hg.alloc_jagged_with(rows, &mut row_len, &mut f) {
+                        Ok(hb) => {
+                            // Yes! Return our allocated item
+                            return hb;
+                        }
+                        Err(_) => {
+                            // Nope, the allocation failed.
+                        }
+                    }
+                }
+            }
+            // Either something failed, or an older allocation goes first.
+            // Get in line, so that newer allocations can't starve us.
+            waiter.enqueue();
+
+            // Didn't succeed, wait until we've done some de-allocations
+            self.wait_for_free(epoch).await;
+        }
+    }
+
+    /// Allocate a [`HeapArrayBuilder`] for an array of exactly `capacity`
+    /// elements, waiting for space to become available if necessary.
+    pub async fn allocate_array_builder<T>(&'static self, capacity: usize) -> HeapArrayBuilder<T> {
+        HeapArrayBuilder {
+            vec: self.allocate_fixed_vec(capacity).await,
+        }
+    }
+
+    /// Like [`AHeap::allocate_array_aligned_with`], but returns an error
+    /// rather than panicking if the allocation could never succeed, e.g. if
+    /// `align` is not a power of two, see [`AHeap::allocate_checked`].
+    pub async fn allocate_array_aligned_with_checked<F, T>(
+        &'static self,
+        f: F,
+        count: usize,
+        align: usize,
+    ) -> Result<HeapArray<T>, AllocError>
+    where
+        F: Fn() -> T,
+    {
+        self.check_fits_arr::<T>(count, align)?;
+        Ok(self.allocate_array_aligned_with(f, count, align).await)
+    }
+
+    /// Allocate a box whose contents are aligned to at least `align` bytes,
+    /// waiting for space to become available if necessary.
+    ///
+    /// ## Panics
+    ///
+    /// If `align` is not a power of two, or is larger than
+    /// [`AHeap::max_align`], or if the allocation is larger than any of the
+    /// heap's regions.
+    pub async fn allocate_aligned_box<T>(
+        &'static self,
+        mut value: T,
+        align: usize,
+    ) -> HeapAlignedBox<T> {
+        assert_fits(match Layout::new::<T>().align_to(align) {
+            Ok(layout) => self.check_fits_raw(layout),
+            Err(_) => Err(layout_error::<T>(align)),
+        });
+        let mut waiter = self.waiter(AHeap::DEFAULT_PRIORITY);
+        loop {
+            let epoch = self.free_epoch.load(Ordering::SeqCst);
+
+            // Is it our turn?
+            if waiter.is_turn() {
+                // Can we get an exclusive heap handle?
+                if let Ok(mut hg) = self.lock() {
+                    // Can we allocate our item?
+                    match hg.alloc_aligned_box(value, align) {
+                        Ok(hb) => {
+                            // Yes! Return our allocated item
+                            return hb;
+                        }
+                        Err(it) => {
+                            // Nope, the allocation failed.
+                            value = it;
+                        }
+                    }
+                }
+            }
+            // Either something failed, or an older allocation goes first.
+            // Get in line, so that newer allocations can't starve us.
+            waiter.enqueue();
+
+            // Didn't succeed, wait until we've done some de-allocations
+            self.wait_for_free(epoch).await;
+        }
+    }
+
+    /// Allocate a raw block of memory, waiting for space to become available
+    /// if necessary.
+    ///
+    /// The allocation must be freed with [`deallocate_raw`].
+    ///
+    /// ## Panics
+    ///
+    /// If `layout` is aligned to more than [`AHeap::max_align`], or is
+    /// larger than any of the heap's regions.
     pub async fn allocate_raw(&'static self, layout: Layout) -> NonNull<()> {
+        self.allocate_raw_prioritized(layout, AHeap::DEFAULT_PRIORITY)
+            .await
+    }
+
+    /// Like [`AHeap::allocate_raw`], but returns an error rather than
+    /// panicking if the allocation could never succeed, see
+    /// [`AHeap::allocate_checked`].
+    pub async fn allocate_raw_checked(
+        &'static self,
+        layout: Layout,
+    ) -> Result<NonNull<()>, AllocError> {
+        self.check_fits_raw(layout)?;
+        Ok(self.allocate_raw(layout).await)
+    }
+
+    /// Like [`AHeap::allocate_raw`], but with a priority for when the
+    /// allocation has to wait for space, see [`AHeap::allocate_prioritized`].
+    ///
+    /// ## Panics
+    ///
+    /// If `layout` is aligned to more than [`AHeap::max_align`], or is
+    /// larger than any of the heap's regions.
+    pub async fn allocate_raw_prioritized(
+        &'static self,
+        layout: Layout,
+        priority: u8,
+    ) -> NonNull<()> {
+        assert_fits(self.check_fits_raw(layout));
+        let mut waiter = self.waiter(priority);
         loop {
-            // Is the heap inhibited?
-            if !self.inhibit_alloc.load(Ordering::Acquire) {
+            let epoch = self.free_epoch.load(Ordering::SeqCst);
+
+            // Is it our turn?
+            if waiter.is_turn() {
                 // Can we get an exclusive heap handle?
                 if let Ok(mut hg) = self.lock() {
                     match hg.alloc_raw(layout) {
@@ -293,14 +1587,497 @@ impl AHeap {
                         }
                     }
                 }
-                // We weren't inhibited before, but something failed. Inhibit
-                // further allocations to prevent starving waiting allocations
-                self.inhibit_alloc.store(true, Ordering::Release);
             }
+            // Either something failed, or an older allocation goes first.
+            // Get in line, so that newer allocations can't starve us.
+            waiter.enqueue();
 
             // Didn't succeed, wait until we've done some de-allocations
-            self.heap_wait.wait().await.unwrap();
+            self.wait_for_free(epoch).await;
+        }
+    }
+}
+
+/// Panics with the error if an allocation can never succeed.
+///
+/// Waiting for an allocation that can never succeed would also hold up every
+/// other allocation, forever, so refuse to start one.
+#[track_caller]
+fn assert_fits(res: Result<(), AllocError>) {
+    if let Err(err) = res {
+        panic!("{err}");
+    }
+}
+
+/// The error for a `T` (or an array of them) aligned to `align`, whose
+/// layout couldn't be computed.
+fn layout_error<T>(align: usize) -> AllocError {
+    let kind = if align.is_power_of_two() {
+        AllocErrorKind::Overflow
+    } else {
+        AllocErrorKind::InvalidAlign
+    };
+    AllocError::new(Layout::new::<T>(), kind)
+}
+
+/// The number of bytes the underlying allocator takes up for a `block`.
+///
+/// This is the same rounding `Heap::allocate_first_fit` applies: every block
+/// must have room for the allocator's free-list entry (two words) once it is
+/// freed, and is a multiple of that entry's alignment.
+#[cfg(feature = "heap-accounting")]
+fn backend_size(block: Layout) -> usize {
+    let align = core::mem::align_of::<usize>();
+    let size = core::cmp::max(block.size(), 2 * core::mem::size_of::<usize>());
+    (size + align - 1) & !(align - 1)
+}
+
+/// The largest power of two with a multiple in `start..end`.
+fn max_align_in(start: usize, end: usize) -> usize {
+    if start == 0 || start >= end {
+        return 1;
+    }
+    // The highest bit that differs between `start - 1` and `end - 1` is the
+    // highest bit that gets set on the way from one to the other, when we
+    // pass a multiple of it.
+    let differ = (start - 1) ^ (end - 1);
+    1 << (usize::BITS - 1 - differ.leading_zeros())
+}
+
+/// A builder for an [`AHeap`] with non-default options.
+#[derive(Debug, Clone)]
+pub struct HeapBuilder {
+    min_alloc_granularity: usize,
+    node_cache: Option<(usize, usize)>,
+    strategy: AllocStrategy,
+    low_watermark: Option<(usize, fn())>,
+    /// See `HeapBuilder::region`, the first is the bootstrap region
+    regions: [Option<(NonNull<u8>, usize)>; HeapBuilder::MAX_REGIONS],
+    /// Set if `region` was called more than `MAX_REGIONS` times
+    too_many_regions: bool,
+}
+
+/// The options given to a [`HeapBuilder`] were inconsistent, or the heap
+/// could not be placed in the given regions.
+///
+/// See [`HeapBuilder::build`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HeapBuildError {
+    /// No regions were given.
+    NoRegions,
+    /// More than [`HeapBuilder::MAX_REGIONS`] regions were given.
+    TooManyRegions,
+    /// Two of the regions overlap.
+    OverlappingRegions,
+    /// A region was too small to hold the heap's own bookkeeping, or the
+    /// first region was too small to also hold the node cache.
+    RegionTooSmall,
+    /// The allocation granularity is not a power of two.
+    InvalidGranularity,
+    /// The node cache's size class is too large to be allocated.
+    InvalidNodeCache,
+}
+
+/// How an [`AHeap`] places allocations.
+///
+/// In both cases, each allocation is placed in the lowest addressed free
+/// block that fits it (first fit). The difference is in when freed memory
+/// becomes available again.
+///
+/// See [`HeapBuilder::strategy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AllocStrategy {
+    /// Memory is freed immediately, unless the heap happens to be locked at
+    /// the time, in which case it is freed later.
+    ///
+    /// This is the default.
+    #[default]
+    FirstFit,
+    /// Memory is always freed by the next allocation (or call to
+    /// [`AHeap::poll`]).
+    ///
+    /// This means the same sequence of allocations and frees always places
+    /// allocations at the same offsets in the heap, regardless of timing,
+    /// which is useful for reproducing bugs that depend on addresses.
+    Deterministic,
+}
+
+// The regions are only ever accessed by the (unsafe) `build`, whose caller
+// guarantees they are valid, wherever it is called from.
+unsafe impl Send for HeapBuilder {}
+unsafe impl Sync for HeapBuilder {}
+
+impl Default for HeapBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeapBuilder {
+    /// Create a new builder, with the default options
+    pub const fn new() -> Self {
+        Self {
+            min_alloc_granularity: 1,
+            node_cache: None,
+            strategy: AllocStrategy::FirstFit,
+            low_watermark: None,
+            regions: [None; Self::MAX_REGIONS],
+            too_many_regions: false,
+        }
+    }
+
+    /// The maximum number of regions that can be given to a builder.
+    ///
+    /// More can be added to the heap after it is built, with
+    /// [`HeapGuard::add_region`].
+    pub const MAX_REGIONS: usize = 4;
+
+    /// Add a region of `len` bytes at `base` to the heap built by
+    /// [`HeapBuilder::build`].
+    ///
+    /// The heap is bootstrapped in the first region, and the others are
+    /// added to it in order, as with [`HeapGuard::add_region`]. Regions are
+    /// only used by `build`, NOT by [`HeapBuilder::bootstrap`].
+    pub const fn region(mut self, base: NonNull<u8>, len: usize) -> Self {
+        let mut i = 0;
+        while i < Self::MAX_REGIONS {
+            if self.regions[i].is_none() {
+                self.regions[i] = Some((base, len));
+                return self;
+            }
+            i += 1;
+        }
+        self.too_many_regions = true;
+        self
+    }
+
+    /// Call `callback` when the amount of free memory drops below
+    /// `threshold_bytes`, see [`AHeap::set_low_watermark`].
+    ///
+    /// Defaults to no callback.
+    pub const fn low_watermark(mut self, threshold_bytes: usize, callback: fn()) -> Self {
+        self.low_watermark = Some((threshold_bytes, callback));
+        self
+    }
+
+    /// Choose when freed memory becomes available again, see
+    /// [`AllocStrategy`].
+    ///
+    /// Defaults to [`AllocStrategy::FirstFit`].
+    pub const fn strategy(mut self, strategy: AllocStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Round the size of every allocation up to a multiple of `granularity`
+    /// bytes.
+    ///
+    /// Many small allocations of slightly different sizes tend to fragment
+    /// the heap with many small, differently sized free blocks. Rounding
+    /// sizes up means freed blocks are more likely to be reusable by later
+    /// allocations, at the cost of wasting up to `granularity - 1` bytes
+    /// per allocation.
+    ///
+    /// Defaults to `1` (no rounding). It must be a power of two (so not
+    /// zero), or [`HeapBuilder::build`] fails with
+    /// [`HeapBuildError::InvalidGranularity`].
+    pub const fn min_alloc_granularity(mut self, granularity: usize) -> Self {
+        self.min_alloc_granularity = granularity;
+        self
+    }
+
+    /// Keep a cache of up to `count` free blocks of `size_class` bytes,
+    /// separate from the main heap.
+    ///
+    /// All allocations of up to `size_class` bytes (including the
+    /// allocator's per-allocation header) take up a full `size_class` block,
+    /// and are served from the cache when it is not empty, which is much
+    /// faster than searching the main heap. Freed blocks go back to the
+    /// cache until it is full, and to the main heap after that.
+    ///
+    /// This is useful when one kind of allocation, such as a task's control
+    /// block, is allocated and freed very frequently. The cache is filled
+    /// with `count` blocks when the heap is bootstrapped.
+    ///
+    /// The cache is split into shards, each with its own lock, and async
+    /// allocations (and all frees) of the cached size class use it without
+    /// locking the heap. This keeps many tasks allocating small nodes at once
+    /// from contending on the heap's lock. With the `trace-alloc` feature,
+    /// or [`AllocStrategy::Deterministic`], the cache is only used while the
+    /// heap is locked.
+    ///
+    /// See [`AHeap::node_cache_stats`] for checking how well it is working.
+    pub const fn with_node_cache(mut self, size_class: usize, count: usize) -> Self {
+        self.node_cache = Some((size_class, count));
+        self
+    }
+
+    /// Construct a thread safe async allocator from the regions given with
+    /// [`HeapBuilder::region`], using the configured options.
+    ///
+    /// Unlike [`HeapBuilder::bootstrap`], this checks that the options are
+    /// consistent before using any of the regions.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as [`AHeap::bootstrap`] apply to every region.
+    pub unsafe fn build(self) -> Result<(NonNull<AHeap>, HeapGuard), HeapBuildError> {
+        if self.too_many_regions {
+            return Err(HeapBuildError::TooManyRegions);
+        }
+        if !self.min_alloc_granularity.is_power_of_two() {
+            return Err(HeapBuildError::InvalidGranularity);
+        }
+
+        let mut bounds = [(0, 0); Self::MAX_REGIONS];
+        for (i, (base, len)) in self.regions.iter().flatten().enumerate() {
+            let start = base.as_ptr().addr();
+            let end = start
+                .checked_add(*len)
+                .ok_or(HeapBuildError::RegionTooSmall)?;
+            if bounds[..i].iter().any(|&(s, e)| start < e && s < end) {
+                return Err(HeapBuildError::OverlappingRegions);
+            }
+            bounds[i] = (start, end);
+        }
+
+        let mut regions = self.regions.into_iter().flatten();
+        let (base, len) = regions.next().ok_or(HeapBuildError::NoRegions)?;
+        let (heap, mut guard) = self.bootstrap_region(base.as_ptr(), len)?;
+        for (base, len) in regions {
+            guard
+                .add_region(base, len)
+                .map_err(|RegionTooSmall| HeapBuildError::RegionTooSmall)?;
+        }
+        Ok((heap, guard))
+    }
+
+    /// Construct a thread safe async allocator from a pool of memory, using
+    /// the configured options.
+    ///
+    /// Any regions given with [`HeapBuilder::region`] are ignored.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as [`AHeap::bootstrap`] apply.
+    pub unsafe fn bootstrap(
+        self,
+        addr: *mut u8,
+        size: usize,
+    ) -> Result<(NonNull<AHeap>, HeapGuard), ()> {
+        self.bootstrap_region(addr, size).map_err(drop)
+    }
+
+    unsafe fn bootstrap_region(
+        &self,
+        addr: *mut u8,
+        size: usize,
+    ) -> Result<(NonNull<AHeap>, HeapGuard), HeapBuildError> {
+        // First, we go all bump-allocator to emplace ourselves within this region
+        let mut cursor = addr;
+        let end = addr
+            .addr()
+            .checked_add(size)
+            .ok_or(HeapBuildError::RegionTooSmall)?;
+        let mut used = 0;
+
+        let stub_ptr;
+        let aheap_ptr;
+
+        // We start with the stub node required for our mpsc queue.
+        {
+            let stub_layout = Layout::new::<Recycle>();
+            let stub_offset = cursor.align_offset(stub_layout.align());
+            let stub_size = stub_layout.size();
+            used += stub_offset;
+            used += stub_size;
+
+            if used > size {
+                return Err(HeapBuildError::RegionTooSmall);
+            }
+
+            cursor = cursor.wrapping_add(stub_offset);
+            stub_ptr = cursor.cast::<Recycle>();
+            stub_ptr.write(Recycle {
+                links: Links::new_stub(),
+                node_layout: stub_layout,
+            });
+            cursor = cursor.add(stub_size);
+        }
+
+        // Next we allocate ourselves
+        {
+            let aheap_layout = Layout::new::<AHeap>();
+            let aheap_offset = cursor.align_offset(aheap_layout.align());
+            let aheap_size = aheap_layout.size();
+            used += aheap_offset;
+            used += aheap_size;
+
+            if used > size {
+                return Err(HeapBuildError::RegionTooSmall);
+            }
+
+            cursor = cursor.wrapping_add(aheap_offset);
+            aheap_ptr = cursor.cast::<AHeap>();
+
+            // Increment the cursor, as we use it for the heap initialization
+            cursor = cursor.add(aheap_size);
+
+            let heap = Heap::new(cursor, end - cursor.addr());
+            #[cfg(feature = "heap-accounting")]
+            let total_bytes = heap.size();
+
+            aheap_ptr.write(AHeap {
+                freelist: MpscQueue::new_with_static_stub(&*stub_ptr),
+                state: AtomicU8::new(AHeap::BUSY_LOCKED),
+                heap: UnsafeCell::new(heap),
+                heap_wait: WaitQueue::new(),
+                queue_head: AtomicUsize::new(AHeap::NO_WAITER),
+                next_ticket: AtomicUsize::new(0),
+                any_frees: AtomicBool::new(false),
+                free_epoch: AtomicUsize::new(0),
+                lock_contended: AtomicBool::new(false),
+                granularity: self.min_alloc_granularity,
+                strategy: self.strategy,
+                region_start: cursor.addr(),
+                region_end: end,
+                cache_layout: None,
+                cache_capacity: 0,
+                cache: [const { CacheShard::new() }; CACHE_SHARDS],
+                cache_hits: AtomicUsize::new(0),
+                cache_misses: AtomicUsize::new(0),
+                alloc_count: AtomicUsize::new(0),
+                free_count: AtomicUsize::new(0),
+                low_watermark: AtomicUsize::new(0),
+                low_watermark_cb: AtomicPtr::new(null_mut()),
+                below_watermark: AtomicBool::new(false),
+                #[cfg(feature = "size-histogram")]
+                size_histogram: [const { core::sync::atomic::AtomicU32::new(0) };
+                    AHeap::HISTOGRAM_BUCKETS],
+                #[cfg(feature = "heap-accounting")]
+                total_bytes: AtomicUsize::new(total_bytes),
+                #[cfg(feature = "heap-accounting")]
+                used_bytes: AtomicUsize::new(0),
+                #[cfg(feature = "heap-accounting")]
+                high_water_mark: AtomicUsize::new(0),
+                #[cfg(feature = "trace-alloc")]
+                trace_hook: UnsafeCell::new(None),
+                regions: AtomicPtr::new(null_mut()),
+            });
+        }
+
+        // Set up the node cache, if we have one
+        let cache_layout = match self.node_cache {
+            Some((size_class, _count)) => {
+                // Every block must be able to hold the cache's free-list header
+                let size = core::cmp::max(size_class, core::mem::size_of::<CacheSlot>());
+                let align = core::cmp::max(
+                    core::mem::align_of::<Recycle>(),
+                    core::mem::align_of::<CacheSlot>(),
+                );
+                let layout = Layout::from_size_align(size, align)
+                    .map_err(|_| HeapBuildError::InvalidNodeCache)?;
+                let layout = (*aheap_ptr).round_layout(layout);
+                Some(layout.ok_or(HeapBuildError::InvalidNodeCache)?)
+            }
+            None => None,
+        };
+        (*aheap_ptr).cache_layout = cache_layout;
+        (*aheap_ptr).cache_capacity = self.node_cache.map_or(0, |(_size_class, count)| count);
+
+        // Everything else is now our allocation space.
+        let aheap = NonNull::new_unchecked(aheap_ptr);
+        let aheap_ref: &'static AHeap = aheap.as_ref();
+
+        // Creating exclusive access to the inner heap is acceptable, as we
+        // have marked ourselves with "BUSY_LOCKED", acting as a mutex.
+        let mut guard = HeapGuard { aheap: aheap_ref };
+
+        // Pre-fill the node cache
+        if let (Some(layout), Some((_size_class, count))) = (cache_layout, self.node_cache) {
+            for _ in 0..count {
+                let block = guard
+                    .get_heap()
+                    .allocate_first_fit(layout)
+                    .map_err(|()| HeapBuildError::RegionTooSmall)?;
+                let pushed = aheap_ref.cache_push(block);
+                debug_assert!(pushed);
+            }
         }
+
+        if let Some((threshold_bytes, callback)) = self.low_watermark {
+            aheap_ref.set_low_watermark(threshold_bytes, callback);
+        }
+
+        // Well that went great, I think!
+        Ok((aheap, guard))
+    }
+}
+
+// === impl Region ===
+
+impl Region {
+    fn contains(&self, addr: usize) -> bool {
+        (self.start..self.end).contains(&addr)
+    }
+}
+
+// === impl CacheShard ===
+
+impl CacheShard {
+    /// Never a valid block, as every block is aligned to at least a word.
+    const LOCKED: *mut CacheSlot = core::ptr::without_provenance_mut(1);
+
+    const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(null_mut()),
+            len: UnsafeCell::new(0),
+        }
+    }
+
+    /// Lock the shard, returning its head, unless another thread holds it.
+    ///
+    /// The shard must be unlocked again by storing the new head.
+    fn lock(&self) -> Option<*mut CacheSlot> {
+        let head = self.head.swap(Self::LOCKED, Ordering::Acquire);
+        (head != Self::LOCKED).then_some(head)
+    }
+
+    /// Add a free block to the shard, returning `false` if it is busy, or
+    /// already holds `capacity` blocks.
+    ///
+    /// `block` must be valid for the cache's layout.
+    unsafe fn push(&self, block: NonNull<u8>, capacity: usize) -> bool {
+        let Some(head) = self.lock() else {
+            return false;
+        };
+        let len = &mut *self.len.get();
+        if *len == capacity {
+            self.head.store(head, Ordering::Release);
+            return false;
+        }
+        let slot = block.cast::<CacheSlot>().as_ptr();
+        slot.write(CacheSlot { next: head });
+        *len += 1;
+        self.head.store(slot, Ordering::Release);
+        true
+    }
+
+    /// Take a block from the shard, unless it is busy or empty.
+    fn pop(&self) -> Option<NonNull<u8>> {
+        let head = self.lock()?;
+        let Some(slot) = NonNull::new(head) else {
+            self.head.store(head, Ordering::Release);
+            return None;
+        };
+        // SAFETY: We hold the shard's lock, and every block in the list was
+        // initialized by `push`
+        unsafe {
+            *self.len.get() -= 1;
+            self.head.store(slot.as_ref().next, Ordering::Release);
+        }
+        Some(slot.cast())
     }
 }
 
@@ -313,68 +2090,386 @@ impl AHeap {
 /// - `layout` *must* be the same `Layout` that was provided to the original
 ///   call to [`Heap::allocate_raw`] or[`HeapGuard::alloc_raw`]!
 pub unsafe fn deallocate_raw(ptr: NonNull<()>, layout: Layout) {
+    #[cfg(feature = "defmt")]
+    trace_raw("free", layout, ptr);
     let ptr = ActiveUnsized::from_raw(ptr, layout);
     ActiveUnsized::yeet(ptr, layout);
 }
 
-/// A guard type that provides mutually exclusive access to the allocator as
-/// long as the guard is held.
-pub struct HeapGuard {
-    aheap: &'static AHeap,
-}
+/// Emit a `defmt` trace event for a raw allocation or free.
+///
+/// The sequence number is shared by all heaps, so events can be put back
+/// in order.
+#[cfg(feature = "defmt")]
+fn trace_raw(op: &str, layout: Layout, ptr: NonNull<()>) {
+    static SEQ: AtomicUsize = AtomicUsize::new(0);
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+    defmt::trace!(
+        "{=str} seq={=usize} size={=usize} align={=usize} ptr={=usize:#x}",
+        op,
+        seq,
+        layout.size(),
+        layout.align(),
+        ptr.as_ptr().addr(),
+    );
+}
+
+/// A guard type that provides mutually exclusive access to the allocator as
+/// long as the guard is held.
+pub struct HeapGuard {
+    aheap: &'static AHeap,
+}
+
+// Public HeapGuard methods
+impl HeapGuard {
+    /// The heap that this guard has locked.
+    pub fn heap(&self) -> &'static AHeap {
+        self.aheap
+    }
+
+    fn get_heap(&mut self) -> &mut Heap {
+        unsafe { &mut *self.aheap.heap.get() }
+    }
+
+    fn clean_allocs(&mut self) {
+        let mut any = false;
+        // Then, free all pending memory in order to maximize space available.
+        let free_list = &self.aheap.freelist;
+
+        while let Some(node_ref) = free_list.dequeue() {
+            // defmt::println!("[ALLOC] FREE: {=usize}", layout.size());
+            // SAFETY: We have mutually exclusive access to the allocator, and
+            // the pointer and layout are correctly calculated by the relevant
+            // FreeBox types.
+
+            let layout = unsafe { node_ref.node.as_ref().node_layout };
+            let ptr = node_ref.node.cast::<u8>();
+
+            unsafe {
+                self.dealloc_layout(ptr, layout);
+                any = true;
+            }
+        }
+
+        if any {
+            self.aheap.any_frees.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Return a block of memory to the underlying allocator.
+    ///
+    /// This is the counterpart to `alloc_layout`, which all frees go
+    /// through. `layout` is the layout originally requested from
+    /// `alloc_layout`, BEFORE rounding.
+    unsafe fn dealloc_layout(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.aheap.count_free(layout);
+        #[cfg(feature = "trace-alloc")]
+        if let Some(hook) = *self.aheap.trace_hook.get() {
+            hook.on_free(ptr, layout);
+        }
+
+        if !self.release_block(ptr, layout) {
+            return;
+        }
+
+        // Re-arm the low watermark callback once we have recovered
+        if self.aheap.below_watermark.load(Ordering::Acquire)
+            && self.free_bytes() >= self.aheap.low_watermark.load(Ordering::Acquire)
+        {
+            self.aheap.below_watermark.store(false, Ordering::Release);
+        }
+    }
+
+    /// Return a block allocated by `alloc_block` to the node cache or the
+    /// region it came from, WITHOUT counting it as a free.
+    ///
+    /// Returns `false` if the block went into the node cache, and so didn't
+    /// change the amount of free memory.
+    unsafe fn release_block(&mut self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        // This was already successfully rounded when the block was allocated.
+        let mut layout = self.aheap.round_layout(layout).unwrap();
+        if let Some(class) = self.aheap.cache_class(layout) {
+            if self.aheap.cache_push(ptr) {
+                return false;
+            }
+            // The cache is full, but the block was allocated with the size
+            // of the cache's blocks.
+            layout = class;
+        }
+        let addr = ptr.as_ptr().addr();
+        if (self.aheap.region_start..self.aheap.region_end).contains(&addr) {
+            self.get_heap().deallocate(ptr, layout);
+        } else {
+            match self.aheap.regions().find(|region| region.contains(addr)) {
+                Some(region) => (*region.heap.get()).deallocate(ptr, layout),
+                None => debug_assert!(false, "freed a pointer ({ptr:p}) outside of all regions"),
+            }
+        }
+        true
+    }
+
+    /// Attempt to allocate a block of memory with the given `Layout`.
+    ///
+    /// This is the core allocation routine that all of the typed allocation
+    /// methods go through. Any pending frees are processed before the
+    /// allocation is attempted.
+    fn alloc_layout(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        let block = self.alloc_block(layout)?;
+        self.aheap.count_alloc(layout);
+
+        let threshold = self.aheap.low_watermark.load(Ordering::Acquire);
+        if threshold != 0
+            && !self.aheap.below_watermark.load(Ordering::Acquire)
+            && self.free_bytes() < threshold
+        {
+            self.aheap.below_watermark.store(true, Ordering::Release);
+            let cb = self.aheap.low_watermark_cb.load(Ordering::Acquire);
+            // SAFETY: this was stored from a `fn()` by `set_low_watermark`,
+            // which also set the (non-zero) threshold.
+            let cb = unsafe { core::mem::transmute::<*mut (), fn()>(cb) };
+            cb();
+        }
+
+        Ok(block)
+    }
+
+    /// Allocate a block for a `T` (or the node containing one), reporting it
+    /// to the trace hook, if there is one.
+    // `T` is only used by the trace hook
+    #[cfg_attr(
+        not(feature = "trace-alloc"),
+        allow(clippy::extra_unused_type_parameters)
+    )]
+    fn alloc_typed<T: ?Sized>(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        let block = self.alloc_layout(layout)?;
+        #[cfg(feature = "trace-alloc")]
+        if let Some(hook) = unsafe { *self.aheap.trace_hook.get() } {
+            hook.on_alloc(block, layout, core::any::type_name::<T>());
+        }
+        Ok(block)
+    }
 
-// Public HeapGuard methods
-impl HeapGuard {
-    fn get_heap(&mut self) -> &mut Heap {
-        unsafe { &mut *self.aheap.heap.get() }
+    /// The total number of free bytes, across all regions.
+    fn free_bytes(&mut self) -> usize {
+        let mut free = self.get_heap().free();
+        for region in self.aheap.regions() {
+            // SAFETY: We hold the lock
+            free += unsafe { (*region.heap.get()).free() };
+        }
+        free
     }
 
-    fn clean_allocs(&mut self) {
-        let mut any = false;
-        // Then, free all pending memory in order to maximize space available.
-        let free_list = &self.aheap.freelist;
+    /// Allocate a block, WITHOUT checking the low watermark.
+    fn alloc_block(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        // Clean up any pending allocs
+        self.clean_allocs();
+
+        let mut layout = self
+            .aheap
+            .round_layout(layout)
+            .ok_or_else(|| AllocError::new(layout, AllocErrorKind::Overflow))?;
+
+        // The underlying allocator would never find room for this, so don't
+        // report it as an allocation that might succeed later
+        if layout.align() > self.aheap.max_align() {
+            return Err(AllocError::new(layout, AllocErrorKind::UnsupportedAlign));
+        }
+
+        if let Some(class) = self.aheap.cache_class(layout) {
+            if let Some(block) = self.aheap.cache_pop() {
+                self.aheap.cache_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(block);
+            }
+            self.aheap.cache_misses.fetch_add(1, Ordering::Relaxed);
+            // Allocate a full sized block, so it can go into the cache when
+            // it is freed.
+            layout = class;
+        }
+
         let heap = self.get_heap();
+        if let Ok(block) = heap.allocate_first_fit(layout) {
+            return Ok(block);
+        }
+        let mut largest = heap.size();
+        let mut free = heap.free();
+
+        // Then try the additional regions, in order
+        for region in self.aheap.regions() {
+            // SAFETY: We hold the lock
+            let heap = unsafe { &mut *region.heap.get() };
+            if let Ok(block) = heap.allocate_first_fit(layout) {
+                return Ok(block);
+            }
+            largest = core::cmp::max(largest, heap.size());
+            free += heap.free();
+        }
 
-        while let Some(node_ref) = free_list.dequeue() {
-            // defmt::println!("[ALLOC] FREE: {=usize}", layout.size());
-            // SAFETY: We have mutually exclusive access to the allocator, and
-            // the pointer and layout are correctly calculated by the relevant
-            // FreeBox types.
+        let kind = if layout.size() > largest {
+            AllocErrorKind::TooLarge
+        } else {
+            AllocErrorKind::OutOfMemory
+        };
+        Err(AllocError {
+            layout,
+            kind,
+            total_free: Some(free),
+        })
+    }
 
-            let layout = unsafe { node_ref.node.as_ref().node_layout };
-            let ptr = node_ref.node.cast::<u8>();
+    /// Returns whether an array (or raw allocation) of `layout` would
+    /// currently fit in the heap, without allocating it.
+    ///
+    /// See [`HeapGuard::can_allocate_all`].
+    pub fn can_allocate(&mut self, layout: Layout) -> bool {
+        self.can_allocate_all(&[layout])
+    }
+
+    /// Returns whether arrays (or raw allocations) of all of `layouts` would
+    /// currently fit in the heap at once, without allocating them.
+    ///
+    /// This is checked by actually placing each allocation in turn, so that
+    /// earlier ones take up space (and fragment the free space) for later
+    /// ones, exactly as allocating them in that order would. They are all
+    /// released again before returning, and are not counted by
+    /// [`AHeap::alloc_count`] or reported to a trace hook.
+    ///
+    /// This is only advisory: it holds while this guard is held, but once
+    /// the heap is unlocked, other allocations may take up the space. The
+    /// size of the header is that of an array's, so allocations of other
+    /// containers, such as [`HeapArc`]s with the `debug-arc` feature, may
+    /// need slightly more.
+    pub fn can_allocate_all(&mut self, layouts: &[Layout]) -> bool {
+        fn block_layout(layout: &Layout) -> Option<Layout> {
+            ActiveArr::<u8>::layout_for_arr(layout.size(), layout.align()).map(|(l, _)| l)
+        }
+
+        // The blocks placed so far are held in a list threaded through the
+        // blocks themselves: each starts with a pointer to the one placed
+        // before it. Every block is at least as large, and as aligned, as a
+        // `Node`, so there is always room for the pointer.
+        let mut held: Option<NonNull<u8>> = None;
+        let mut placed = 0;
+        let mut fits = true;
+        for layout in layouts {
+            let Some(block) = block_layout(layout).and_then(|l| self.alloc_block(l).ok()) else {
+                fits = false;
+                break;
+            };
+            // SAFETY: The block was just allocated, and is large and aligned
+            // enough for the pointer.
+            unsafe { block.cast::<Option<NonNull<u8>>>().as_ptr().write(held) };
+            held = Some(block);
+            placed += 1;
+        }
 
+        // Release them again in reverse, so the last one placed comes first.
+        for layout in layouts[..placed].iter().rev() {
+            // SAFETY: One block was held for each layout placed, and its
+            // layout was computed successfully when it was allocated.
             unsafe {
-                heap.deallocate(ptr, layout);
-                any = true;
+                let block = held.unwrap_unchecked();
+                held = block.cast::<Option<NonNull<u8>>>().as_ptr().read();
+                self.release_block(block, block_layout(layout).unwrap_unchecked());
             }
         }
+        fits
+    }
 
-        if any {
-            self.aheap.any_frees.store(true, Ordering::Relaxed);
+    /// Set (or clear) the hook that is notified of every allocation and
+    /// free made by this heap.
+    ///
+    /// Only available with the `trace-alloc` feature.
+    #[cfg(feature = "trace-alloc")]
+    pub fn set_trace_hook(&mut self, hook: Option<&'static dyn AllocTraceHook>) {
+        // SAFETY: We hold the lock
+        unsafe { *self.aheap.trace_hook.get() = hook };
+    }
+
+    /// Add another region of memory to the heap.
+    ///
+    /// Allocations are made from the region the heap was bootstrapped with
+    /// if possible, and then from additional regions in the order they were
+    /// added. This allows a single heap to span several non-contiguous banks
+    /// of memory, preferring faster ones: bootstrap the heap with the
+    /// fastest bank, and add the others in order of preference.
+    ///
+    /// A small header is placed at the start of the region. Fails if the
+    /// region is too small to hold it.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as for the region passed to
+    /// [`AHeap::bootstrap`] apply. The region must not overlap with any
+    /// region already managed by this (or any other) heap.
+    pub unsafe fn add_region(
+        &mut self,
+        base: NonNull<u8>,
+        len: usize,
+    ) -> Result<(), RegionTooSmall> {
+        let addr = base.as_ptr();
+        let end = addr.addr().checked_add(len).ok_or(RegionTooSmall)?;
+
+        let layout = Layout::new::<Region>();
+        let offset = addr.align_offset(layout.align());
+        let used = offset.checked_add(layout.size()).ok_or(RegionTooSmall)?;
+        if used >= len {
+            return Err(RegionTooSmall);
+        }
+
+        let region_ptr = addr.add(offset).cast::<Region>();
+        let cursor = addr.add(used);
+        region_ptr.write(Region {
+            next: AtomicPtr::new(null_mut()),
+            start: cursor.addr(),
+            end,
+            heap: UnsafeCell::new(Heap::new(cursor, len - used)),
+        });
+        #[cfg(feature = "heap-accounting")]
+        self.aheap
+            .total_bytes
+            .fetch_add((*(*region_ptr).heap.get()).size(), Ordering::Relaxed);
+
+        // Append it to the end of the list
+        match self.aheap.regions().last() {
+            Some(last) => last.next.store(region_ptr, Ordering::Release),
+            None => self.aheap.regions.store(region_ptr, Ordering::Release),
         }
+        Ok(())
     }
 
     /// Attempt to allocate a HeapBox using the allocator
     ///
-    /// If space was available, the allocation will be returned. If not, an
-    /// error will be returned
+    /// If space was available, the allocation will be returned. If not,
+    /// `data` is handed back as the error. The reason isn't reported, see
+    /// [`HeapGuard::alloc_box_or_err`].
     pub fn alloc_box<T>(&mut self, data: T) -> Result<HeapBox<T>, T> {
-        // Clean up any pending allocs
-        self.clean_allocs();
+        self.alloc_box_or_err(data).map_err(|(data, _)| data)
+    }
 
-        // Then, attempt to allocate the requested T.
-        let nnu8 = match self.get_heap().allocate_first_fit(Layout::new::<Node<T>>()) {
+    /// Like [`HeapGuard::alloc_box`], but also returns the reason the
+    /// allocation failed.
+    pub fn alloc_box_or_err<T>(&mut self, data: T) -> Result<HeapBox<T>, (T, AllocError)> {
+        // Attempt to allocate the requested T.
+        let nnu8 = match self.alloc_typed::<T>(Layout::new::<Node<T>>()) {
             Ok(t) => t,
-            Err(_) => return Err(data),
+            Err(err) => return Err((data, err)),
         };
-        let nn = nnu8.cast::<Active<T>>();
 
         // And initialize it with the contents given to us
-        unsafe {
-            Active::<T>::write_heap(nn, self.aheap);
-            Active::<T>::data(nn).as_ptr().write(data);
-        }
+        Ok(unsafe { self.aheap.init_box(nnu8, data) })
+    }
+
+    /// Allocate a `HeapBox<T>` WITHOUT initializing its contents.
+    ///
+    /// # Safety
+    ///
+    /// The contents of the box MUST be initialized before they are accessed
+    /// or dropped, unless `T` is valid when uninitialized (e.g. an array of
+    /// `MaybeUninit`).
+    pub(crate) unsafe fn alloc_box_uninit<T>(&mut self) -> Result<HeapBox<T>, AllocError> {
+        let nnu8 = self.alloc_typed::<T>(Layout::new::<Node<T>>())?;
+        let nn = nnu8.cast::<Active<T>>();
+        Active::<T>::write_heap(nn, self.aheap);
 
         Ok(HeapBox {
             ptr: nn,
@@ -382,31 +2477,85 @@ impl HeapGuard {
         })
     }
 
+    /// Attempt to allocate a `HeapBox` containing an uninitialized `T`.
+    ///
+    /// Unlike `alloc_box(value)`, this never places a `T` on the stack.
+    /// Write the value in place, then use [`HeapBox::assume_init`].
+    pub fn alloc_uninit<T>(&mut self) -> Result<HeapBox<MaybeUninit<T>>, AllocError> {
+        // SAFETY: A `MaybeUninit` is valid uninitialized.
+        unsafe { self.alloc_box_uninit() }
+    }
+
+    /// Attempt to allocate a `HeapBox` containing an uninitialized `[T; N]`.
+    ///
+    /// Unlike `alloc_box([value; N])`, this never places the array on the
+    /// stack. Use [`HeapBox::init_with`] to fill it in place.
+    pub fn alloc_array_const<T, const N: usize>(
+        &mut self,
+    ) -> Result<HeapBox<[MaybeUninit<T>; N]>, AllocError> {
+        // SAFETY: An array of `MaybeUninit`s is valid uninitialized.
+        unsafe { self.alloc_box_uninit() }
+    }
+
     /// Attempt to allocate a HeapArc using the allocator
     ///
-    /// If space was available, the allocation will be returned. If not, an
-    /// error will be returned
+    /// If space was available, the allocation will be returned. If not,
+    /// `data` is handed back as the error, as with [`HeapGuard::alloc_box`].
+    #[cfg_attr(feature = "debug-arc", track_caller)]
     pub fn alloc_arc<T>(&mut self, data: T) -> Result<HeapArc<T>, T> {
-        // Clean up any pending allocs
-        self.clean_allocs();
+        self.alloc_arc_at(data, Location::caller())
+            .map_err(|(data, _)| data)
+    }
 
-        // Then, attempt to allocate the requested T.
-        let nnu8 = match self
-            .get_heap()
-            .allocate_first_fit(Layout::new::<Node<ArcInner<T>>>())
-        {
+    /// Like [`HeapGuard::alloc_arc`], but also returns the reason the
+    /// allocation failed.
+    #[cfg_attr(feature = "debug-arc", track_caller)]
+    pub fn alloc_arc_or_err<T>(&mut self, data: T) -> Result<HeapArc<T>, (T, AllocError)> {
+        self.alloc_arc_at(data, Location::caller())
+    }
+
+    /// Attempt to move the contents of `boxed` into a new [`HeapArc`].
+    ///
+    /// The arc needs room for its reference counts ahead of the contents, so
+    /// it can't reuse the box's allocation: the contents are copied directly
+    /// from one allocation to the other (never onto the stack), and the box's
+    /// allocation is then freed, without dropping them. If the allocation
+    /// fails, the box is handed back. Use a [`HeapSharableBox`] to avoid the
+    /// copy, if it is known up front that the value may be shared.
+    #[cfg_attr(feature = "debug-arc", track_caller)]
+    pub fn alloc_arc_from_box<T>(&mut self, boxed: HeapBox<T>) -> Result<HeapArc<T>, HeapBox<T>> {
+        self.alloc_arc_from_box_at(boxed, Location::caller())
+    }
+
+    #[cfg_attr(not(feature = "debug-arc"), allow(unused_variables))]
+    fn alloc_arc_from_box_at<T>(
+        &mut self,
+        boxed: HeapBox<T>,
+        location: &'static Location<'static>,
+    ) -> Result<HeapArc<T>, HeapBox<T>> {
+        let nnu8 = match self.alloc_typed::<T>(Layout::new::<Node<ArcInner<T>>>()) {
             Ok(t) => t,
-            Err(_) => return Err(data),
+            Err(_) => return Err(boxed),
         };
         let nn = nnu8.cast::<Active<ArcInner<T>>>();
 
-        // And initialize it with the contents given to us
+        // The contents are moved to the arc, so must not be dropped with the box
+        let src = boxed.ptr;
+        forget(boxed);
         unsafe {
             Active::<ArcInner<T>>::write_heap(nn, self.aheap);
-            Active::<ArcInner<T>>::data(nn).as_ptr().write(ArcInner {
-                data,
-                refcnt: AtomicUsize::new(1),
-            });
+            let inner = Active::<ArcInner<T>>::data(nn).as_ptr();
+            addr_of_mut!((*inner).refcnt).write(RefCount::new(1));
+            addr_of_mut!((*inner).weak).write(RefCount::new(1));
+            addr_of_mut!((*inner).on_last_drop).write(None);
+            #[cfg(feature = "debug-arc")]
+            addr_of_mut!((*inner).location).write(location);
+            core::ptr::copy_nonoverlapping(
+                Active::<T>::data(src).as_ptr(),
+                addr_of_mut!((*inner).data),
+                1,
+            );
+            Active::<T>::yeet(src);
         }
 
         Ok(HeapArc {
@@ -415,91 +2564,656 @@ impl HeapGuard {
         })
     }
 
-    pub fn alloc_box_array_with<T, F>(&mut self, f: F, count: usize) -> Result<HeapArray<T>, ()>
+    /// Attempt to allocate a [`HeapSharableBox`], which can later be turned
+    /// into a [`HeapArc`] without moving its contents.
+    #[cfg_attr(feature = "debug-arc", track_caller)]
+    pub fn alloc_box_sharable<T>(&mut self, data: T) -> Result<HeapSharableBox<T>, T> {
+        let arc = self
+            .alloc_arc_at(data, Location::caller())
+            .map_err(|(data, _)| data)?;
+        Ok(HeapSharableBox { arc })
+    }
+
+    /// Like [`HeapGuard::alloc_box_sharable`], but also returns the reason
+    /// the allocation failed.
+    #[cfg_attr(feature = "debug-arc", track_caller)]
+    pub fn alloc_box_sharable_or_err<T>(
+        &mut self,
+        data: T,
+    ) -> Result<HeapSharableBox<T>, (T, AllocError)> {
+        let arc = self.alloc_arc_at(data, Location::caller())?;
+        Ok(HeapSharableBox { arc })
+    }
+
+    /// Allocates an arc, recording `location` as its creation site when the
+    /// `debug-arc` feature is enabled.
+    #[cfg_attr(not(feature = "debug-arc"), allow(unused_variables))]
+    fn alloc_arc_at<T>(
+        &mut self,
+        data: T,
+        location: &'static Location<'static>,
+    ) -> Result<HeapArc<T>, (T, AllocError)> {
+        // Attempt to allocate the requested T.
+        let nnu8 = match self.alloc_typed::<T>(Layout::new::<Node<ArcInner<T>>>()) {
+            Ok(t) => t,
+            Err(err) => return Err((data, err)),
+        };
+
+        // And initialize it with the contents given to us
+        Ok(unsafe { self.aheap.init_arc(nnu8, data, location) })
+    }
+
+    pub fn alloc_box_array_with<T, F>(
+        &mut self,
+        f: F,
+        count: usize,
+    ) -> Result<HeapArray<T>, AllocError>
     where
         F: Fn() -> T,
     {
-        // Clean up any pending allocs
-        self.clean_allocs();
+        self.alloc_box_array_aligned_with(f, count, 1)
+    }
 
-        // Then figure out the layout of the requested array. This call fails
-        // if the total size exceeds ISIZE_MAX, which is exceedingly unlikely
-        // (unless the caller calculated something wrong)
-        let layout = unsafe { ActiveArr::<T>::layout_for_arr(count) };
+    /// Allocate an array whose storage is aligned to at least `align` bytes,
+    /// e.g. for use as a DMA buffer.
+    ///
+    /// `align` must be a power of two, otherwise the error is
+    /// [`AllocErrorKind::InvalidAlign`].
+    ///
+    /// If `f` panics, the elements created so far are dropped, and the array
+    /// is freed.
+    pub fn alloc_box_array_aligned_with<T, F>(
+        &mut self,
+        f: F,
+        count: usize,
+        align: usize,
+    ) -> Result<HeapArray<T>, AllocError>
+    where
+        F: Fn() -> T,
+    {
+        // First figure out the layout of the requested array. This fails if
+        // the total size exceeds ISIZE_MAX, which is exceedingly unlikely
+        // (unless the caller calculated something wrong), or if `align` is
+        // bogus.
+        let (layout, header_offset) =
+            ActiveArr::<T>::layout_for_arr(count, align).ok_or_else(|| layout_error::<T>(align))?;
 
         // Then, attempt to allocate the requested T.
-        let nnu8 = self.get_heap().allocate_first_fit(layout)?;
-        let aa_ptr = nnu8.cast::<ActiveArr<T>>();
+        let nnu8 = self.alloc_typed::<[T]>(layout)?;
 
-        // And initialize it with the contents given to us
-        unsafe {
-            ActiveArr::<T>::write_heap(aa_ptr, self.aheap);
-            ActiveArr::<T>::write_capacity(aa_ptr, count);
-            let (start, count) = ActiveArr::<T>::data(aa_ptr);
-            let start = start.as_ptr();
-            for i in 0..count {
-                start.add(i).write((f)());
-            }
+        let aa_ptr =
+            unsafe { ActiveArr::<T>::init_header(nnu8, header_offset, self.aheap, count, align) };
+
+        // And initialize it with the contents given to us. Like
+        // `alloc_array_from_exact`, only the elements written so far are
+        // live, so if `f` panics, just those are dropped, and the array is
+        // freed.
+        let mut arr = HeapArray {
+            ptr: aa_ptr,
+            start: 0,
+            len: 0,
+            pd: PhantomData,
+        };
+        let (start, _count) = unsafe { ActiveArr::<T>::data(aa_ptr) };
+        for i in 0..count {
+            unsafe { start.as_ptr().add(i).write((f)()) };
+            arr.len += 1;
+        }
+        Ok(arr)
+    }
+
+    /// Allocate an array holding the items of `iter`, with a single
+    /// allocation sized by the iterator's reported length.
+    ///
+    /// The iterator is not advanced if the allocation fails. If it yields
+    /// fewer items than it reported, the array holds only the yielded items.
+    /// Either kind of mismatch is a debug assertion failure. If the iterator
+    /// panics, the items yielded so far are dropped, and the array is freed.
+    pub fn alloc_array_from_exact<T, I>(&mut self, iter: I) -> Result<HeapArray<T>, AllocError>
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let mut iter = iter.into_iter();
+        let count = iter.len();
+
+        let (layout, header_offset) = ActiveArr::<T>::layout_for_arr(count, 1)
+            .ok_or_else(|| AllocError::new(Layout::new::<T>(), AllocErrorKind::Overflow))?;
+        let nnu8 = self.alloc_typed::<[T]>(layout)?;
+        let aa_ptr =
+            unsafe { ActiveArr::<T>::init_header(nnu8, header_offset, self.aheap, count, 1) };
+
+        // Start out empty, and grow the live range as items are written, so
+        // that dropping the array (e.g. if the iterator panics) only drops
+        // the items written so far.
+        let mut arr = HeapArray {
+            ptr: aa_ptr,
+            start: 0,
+            len: 0,
+            pd: PhantomData,
+        };
+        let (start, _count) = unsafe { ActiveArr::<T>::data(aa_ptr) };
+        for item in iter.by_ref().take(count) {
+            unsafe { start.as_ptr().add(arr.len).write(item) };
+            arr.len += 1;
+        }
+        debug_assert_eq!(
+            arr.len, count,
+            "iterator yielded fewer items than its length"
+        );
+        debug_assert!(
+            iter.next().is_none(),
+            "iterator yielded more items than its length"
+        );
+
+        Ok(arr)
+    }
+
+    /// Allocate an array of exactly `capacity` elements, holding the items
+    /// of `iter`.
+    ///
+    /// The iterator is not advanced if the allocation fails. If it yields
+    /// fewer than `capacity` items, or has any left over (in which case one
+    /// more item is taken from it, and dropped), the inner result is an
+    /// error, the items yielded so far are dropped, and the array is freed.
+    /// The same happens if the iterator panics.
+    pub fn alloc_array_from_iter<T, I>(
+        &mut self,
+        iter: I,
+        capacity: usize,
+    ) -> Result<Result<HeapArray<T>, IterLengthError>, AllocError>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let (layout, header_offset) = ActiveArr::<T>::layout_for_arr(capacity, 1)
+            .ok_or_else(|| AllocError::new(Layout::new::<T>(), AllocErrorKind::Overflow))?;
+        let nnu8 = self.alloc_typed::<[T]>(layout)?;
+        let aa_ptr =
+            unsafe { ActiveArr::<T>::init_header(nnu8, header_offset, self.aheap, capacity, 1) };
+
+        // Start out empty, and grow the live range as items are written, so
+        // that dropping the array (on a mismatch, or if the iterator panics)
+        // only drops the items written so far.
+        let mut arr = HeapArray {
+            ptr: aa_ptr,
+            start: 0,
+            len: 0,
+            pd: PhantomData,
+        };
+        let (start, _capacity) = unsafe { ActiveArr::<T>::data(aa_ptr) };
+        let mut iter = iter.into_iter();
+        while arr.len < capacity {
+            let Some(item) = iter.next() else {
+                return Ok(Err(IterLengthError::TooShort {
+                    yielded: arr.len,
+                    capacity,
+                }));
+            };
+            unsafe { start.as_ptr().add(arr.len).write(item) };
+            arr.len += 1;
+        }
+        if iter.next().is_some() {
+            return Ok(Err(IterLengthError::TooLong { capacity }));
         }
 
+        Ok(Ok(arr))
+    }
+
+    /// Allocate an array holding the elements of `array`.
+    ///
+    /// The elements are moved into the array, not cloned. If the allocation
+    /// fails, `array` is handed back.
+    pub fn alloc_array_from<T, const N: usize>(
+        &mut self,
+        array: [T; N],
+    ) -> Result<HeapArray<T>, [T; N]> {
+        let Some((layout, header_offset)) = ActiveArr::<T>::layout_for_arr(N, 1) else {
+            return Err(array);
+        };
+        let nnu8 = match self.alloc_typed::<[T]>(layout) {
+            Ok(nnu8) => nnu8,
+            Err(_) => return Err(array),
+        };
+
+        let aa_ptr = unsafe {
+            let aa_ptr = ActiveArr::<T>::init_header(nnu8, header_offset, self.aheap, N, 1);
+            let (start, _count) = ActiveArr::<T>::data(aa_ptr);
+            // `[T; N]` has the same alignment as `T`
+            start.as_ptr().cast::<[T; N]>().write(array);
+            aa_ptr
+        };
+
         Ok(HeapArray {
             ptr: aa_ptr,
+            start: 0,
+            len: N,
             pd: PhantomData,
         })
     }
 
-    pub fn alloc_fixed_vec<T>(&mut self, capacity: usize) -> Result<HeapFixedVec<T>, ()> {
-        // Clean up any pending allocs
-        self.clean_allocs();
+    /// Allocate an array of `len` elements, initializing the element at each
+    /// index `i` with `f(i)`, and stopping at the first error.
+    ///
+    /// If `f` returns an error, the elements initialized so far are dropped,
+    /// and the allocation is freed, before returning the error. A failure
+    /// to allocate the array is returned as an error too, converted from an
+    /// [`AllocError`].
+    pub fn try_alloc_array_from_fn<T, E, F>(
+        &mut self,
+        len: usize,
+        mut f: F,
+    ) -> Result<HeapArray<T>, E>
+    where
+        E: From<AllocError>,
+        F: FnMut(usize) -> Result<T, E>,
+    {
+        let (layout, header_offset) = ActiveArr::<T>::layout_for_arr(len, 1)
+            .ok_or_else(|| AllocError::new(Layout::new::<T>(), AllocErrorKind::Overflow))?;
+        let nnu8 = self.alloc_typed::<[T]>(layout)?;
+        let aa_ptr =
+            unsafe { ActiveArr::<T>::init_header(nnu8, header_offset, self.aheap, len, 1) };
+
+        // Like `alloc_array_from_exact`, only the elements written so far
+        // are live, so returning early (or panicking) drops just those.
+        let mut arr = HeapArray {
+            ptr: aa_ptr,
+            start: 0,
+            len: 0,
+            pd: PhantomData,
+        };
+        let (start, _count) = unsafe { ActiveArr::<T>::data(aa_ptr) };
+        for i in 0..len {
+            let item = f(i)?;
+            unsafe { start.as_ptr().add(i).write(item) };
+            arr.len += 1;
+        }
+        Ok(arr)
+    }
+
+    /// Allocate a shared array of `count` elements, each initialized by
+    /// calling `f`.
+    pub fn alloc_arc_array_with<T, F>(
+        &mut self,
+        f: F,
+        count: usize,
+    ) -> Result<HeapArcArray<T>, AllocError>
+    where
+        F: Fn() -> T,
+    {
+        let arr = self.alloc_box_array_with(f, count)?;
+        // A fresh array has a reference count of one, and covers the whole
+        // storage, so it can be handed over as-is.
+        let ptr = arr.ptr;
+        forget(arr);
+        Ok(HeapArcArray {
+            ptr,
+            pd: PhantomData,
+        })
+    }
+
+    /// Allocate a shared array of `count` uninitialized elements.
+    ///
+    /// The elements can be filled in with [`HeapArcArray::get_mut`] while
+    /// there is only one handle to the array, and then
+    /// [`HeapArcArray::assume_init`] converts it into an array of `T`s.
+    pub fn alloc_arc_array_uninit<T>(
+        &mut self,
+        count: usize,
+    ) -> Result<HeapArcArray<MaybeUninit<T>>, AllocError> {
+        self.alloc_arc_array_with(MaybeUninit::uninit, count)
+    }
 
-        // Then figure out the layout of the requested array. This call fails
-        // if the total size exceeds ISIZE_MAX, which is exceedingly unlikely
+    pub fn alloc_fixed_vec<T>(&mut self, capacity: usize) -> Result<HeapFixedVec<T>, AllocError> {
+        // First figure out the layout of the requested array. This fails if
+        // the total size exceeds ISIZE_MAX, which is exceedingly unlikely
         // (unless the caller calculated something wrong)
-        let layout = unsafe { ActiveArr::<MaybeUninit<T>>::layout_for_arr(capacity) };
+        let (layout, header_offset) = ActiveArr::<MaybeUninit<T>>::layout_for_arr(capacity, 1)
+            .ok_or_else(|| AllocError::new(Layout::new::<T>(), AllocErrorKind::Overflow))?;
 
         // Then, attempt to allocate the requested T.
-        let nnu8 = self.get_heap().allocate_first_fit(layout)?;
-        let aa_ptr = nnu8.cast::<ActiveArr<MaybeUninit<T>>>();
+        let nnu8 = self.alloc_typed::<[T]>(layout)?;
 
         // And initialize it with the contents given to us
-        unsafe {
-            ActiveArr::<MaybeUninit<T>>::write_heap(aa_ptr, self.aheap);
-            ActiveArr::<MaybeUninit<T>>::write_capacity(aa_ptr, capacity);
+        let aa_ptr = unsafe {
+            let aa_ptr = ActiveArr::<MaybeUninit<T>>::init_header(
+                nnu8,
+                header_offset,
+                self.aheap,
+                capacity,
+                1,
+            );
             let (start, count) = ActiveArr::<MaybeUninit<T>>::data(aa_ptr);
             let start = start.as_ptr();
             for i in 0..count {
                 start.add(i).write(MaybeUninit::uninit());
             }
-        }
+            aa_ptr
+        };
 
         Ok(HeapFixedVec {
             ptr: aa_ptr,
             pd: PhantomData,
             len: 0,
+            lazy_capacity: 0,
         })
     }
 
-    pub fn alloc_raw(&mut self, layout: Layout) -> Result<NonNull<()>, ()> {
-        // Clean up any pending allocs
-        self.clean_allocs();
+    /// Allocate a jagged array: an array of `rows` rows, where row `r` is an
+    /// array of `row_len(r)` elements, and the element in column `c` of row
+    /// `r` is initialized with `f(r, c)`.
+    ///
+    /// Each row is a separate allocation. If allocating any of them fails,
+    /// the rows allocated so far are dropped, and the whole array is freed,
+    /// before returning the error.
+    pub fn alloc_jagged_with<T, L, F>(
+        &mut self,
+        rows: usize,
+        mut row_len: L,
+        mut f: F,
+    ) -> Result<HeapArray<HeapArray<T>>, AllocError>
+    where
+        L: FnMut(usize) -> usize,
+        F: FnMut(usize, usize) -> T,
+    {
+        let mut outer = self.alloc_fixed_vec::<HeapArray<T>>(rows)?;
+        for r in 0..rows {
+            let row = self.try_alloc_array_from_fn(row_len(r), |c| Ok::<_, AllocError>(f(r, c)))?;
+            // Can't fail, there's room for exactly all of the rows
+            let _ = outer.push(row);
+        }
+        Ok(outer.into_initialized_array())
+    }
+
+    /// Attempt to allocate a [`HeapArrayBuilder`] for an array of exactly
+    /// `capacity` elements.
+    pub fn alloc_array_builder<T>(
+        &mut self,
+        capacity: usize,
+    ) -> Result<HeapArrayBuilder<T>, AllocError> {
+        let vec = self.alloc_fixed_vec(capacity)?;
+        Ok(HeapArrayBuilder { vec })
+    }
+
+    /// Allocate a box whose contents are aligned to at least `align` bytes.
+    ///
+    /// If `align` is not a power of two, or the allocation fails, `value` is
+    /// handed back. The reason isn't reported, see
+    /// [`HeapGuard::alloc_aligned_box_or_err`].
+    pub fn alloc_aligned_box<T>(&mut self, value: T, align: usize) -> Result<HeapAlignedBox<T>, T> {
+        self.alloc_aligned_box_or_err(value, align)
+            .map_err(|(value, _)| value)
+    }
+
+    /// Like [`HeapGuard::alloc_aligned_box`], but also returns the reason
+    /// the allocation failed.
+    pub fn alloc_aligned_box_or_err<T>(
+        &mut self,
+        value: T,
+        align: usize,
+    ) -> Result<HeapAlignedBox<T>, (T, AllocError)> {
+        let Ok(layout) = Layout::new::<T>().align_to(align) else {
+            return Err((value, layout_error::<T>(align)));
+        };
+        let ptr = match self.alloc_raw(layout) {
+            Ok(ptr) => ptr.cast::<T>(),
+            Err(err) => return Err((value, err)),
+        };
+        unsafe { ptr.as_ptr().write(value) };
+        Ok(HeapAlignedBox { ptr, layout })
+    }
+
+    pub fn alloc_raw(&mut self, layout: Layout) -> Result<NonNull<()>, AllocError> {
+        #[cfg(feature = "defmt")]
+        let requested = layout;
 
         // calculate the layout of the requested allocation
-        let (layout, offset) = ActiveUnsized::layout(layout);
+        let (layout, offset) = ActiveUnsized::layout(layout)
+            .ok_or_else(|| AllocError::new(layout, AllocErrorKind::Overflow))?;
 
         // Then, attempt to allocate the requested T.
-        let nnu8 = self.get_heap().allocate_first_fit(layout)?;
+        let nnu8 = self.alloc_typed::<[u8]>(layout)?;
         let ptr = nnu8.cast::<ActiveUnsized>();
 
-        unsafe {
+        let data_ptr = unsafe {
             ActiveUnsized::write_heap(ptr, self.aheap);
             let data_ptr = ptr.as_ptr().cast::<u8>().add(offset).cast::<()>();
-            Ok(NonNull::new_unchecked(data_ptr))
-        }
+            NonNull::new_unchecked(data_ptr)
+        };
+        #[cfg(feature = "defmt")]
+        trace_raw("alloc", requested, data_ptr);
+        Ok(data_ptr)
+    }
+
+    /// Like [`HeapGuard::alloc_raw`], but with every byte of the allocation
+    /// set to `fill`.
+    ///
+    /// The allocation must be freed with [`deallocate_raw`], as with
+    /// `alloc_raw`.
+    pub fn alloc_raw_filled(
+        &mut self,
+        layout: Layout,
+        fill: u8,
+    ) -> Result<NonNull<()>, AllocError> {
+        let ptr = self.alloc_raw(layout)?;
+        unsafe { ptr.as_ptr().cast::<u8>().write_bytes(fill, layout.size()) };
+        Ok(ptr)
     }
 }
 
 impl Drop for HeapGuard {
     fn drop(&mut self) {
-        self.aheap.state.store(AHeap::INIT_IDLE, Ordering::SeqCst)
+        self.aheap.state.store(AHeap::INIT_IDLE, Ordering::SeqCst);
+        if self.aheap.lock_contended.swap(false, Ordering::SeqCst) {
+            // Someone failed to lock the heap while we held it. If that was
+            // an async allocation, it is now waiting for a free (and
+            // holding up other allocations), so let it retry.
+            self.aheap.notify_free();
+        }
+    }
+}
+
+/// An async allocation's place in line for the heap.
+///
+/// Waiting allocations are ordered by a key, lowest first, and `queue_head`
+/// holds the lowest key of the allocations that have checked in since the
+/// head last changed. Only the head (or anyone, if there is no head) may try
+/// to allocate.
+struct Waiter {
+    heap: &'static AHeap,
+    /// Taken the first time the allocation has to wait
+    key: Option<usize>,
+    priority: u8,
+}
+
+impl Waiter {
+    const TICKET_BITS: u32 = usize::BITS - u8::BITS;
+
+    /// Returns whether the allocation may try to allocate now.
+    fn is_turn(&self) -> bool {
+        match self.key {
+            // Check in, in case the head has gone away, or has been
+            // overtaken by a higher priority allocation
+            Some(key) => {
+                let head = self.heap.queue_head.fetch_min(key, Ordering::AcqRel);
+                head >= key
+            }
+            None => self.heap.queue_head.load(Ordering::Acquire) == AHeap::NO_WAITER,
+        }
+    }
+
+    /// Get in line, after failing to allocate (or not getting the chance).
+    fn enqueue(&mut self) {
+        let key = *self.key.get_or_insert_with(|| {
+            // Higher priorities sort first, then older tickets. The ticket
+            // wraps within its bits, but never reaches `NO_WAITER`.
+            let ticket = self.heap.next_ticket.fetch_add(1, Ordering::Relaxed)
+                % ((1 << Self::TICKET_BITS) - 1);
+            (usize::from(u8::MAX - self.priority) << Self::TICKET_BITS) | ticket
+        });
+        self.heap.queue_head.fetch_min(key, Ordering::AcqRel);
+    }
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        let Some(key) = self.key else {
+            return;
+        };
+        // If we were at the head of the line (whether we allocated or were
+        // cancelled), let the rest of the line check in again.
+        let left = self
+            .heap
+            .queue_head
+            .compare_exchange(key, AHeap::NO_WAITER, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok();
+        if left {
+            self.heap.notify_free();
+        }
+    }
+}
+
+/// An error returned when an allocation could not be completed.
+///
+/// In addition to the reason the allocation failed, this records the
+/// [`Layout`] that was requested, and (where known) how much free space the
+/// heap had in total at the time of the failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError {
+    layout: Layout,
+    kind: AllocErrorKind,
+    total_free: Option<usize>,
+}
+
+/// An allocation could not be resized without moving it.
+///
+/// See [`AHeap::reallocate_raw_in_place_only`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReallocMoved;
+
+/// The reason an allocation failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AllocErrorKind {
+    /// There is not currently a large enough free region in the heap.
+    ///
+    /// The allocation may succeed later, once other allocations are freed.
+    /// NOTE: [`AllocError::free_bytes`] is the total free in the heap, NOT
+    /// the largest free region, so it may well exceed the size requested.
+    OutOfMemory,
+    /// The allocation is larger than the entire heap, and can never succeed.
+    ///
+    /// The async allocation methods panic, rather than wait for these,
+    /// except for [`AHeap::allocate_checked`] and friends, which return them.
+    TooLarge,
+    /// Computing the size of the allocation overflowed.
+    Overflow,
+    /// The requested alignment was not a power of two.
+    InvalidAlign,
+    /// The requested alignment is larger than the heap can provide, and
+    /// can never succeed.
+    ///
+    /// See [`AHeap::max_align`].
+    UnsupportedAlign,
+    /// The allocation did not complete in time.
+    ///
+    /// This is never returned by the allocator itself, but is provided for
+    /// callers that race an allocation against a deadline.
+    TimedOut,
+    /// The heap was locked, or an async allocation was waiting its turn.
+    ///
+    /// This is only returned by the non-blocking allocation methods, such
+    /// as [`AHeap::try_alloc_array_with`], and the allocation may succeed
+    /// if retried.
+    Busy,
+}
+
+impl AllocError {
+    /// Create a new `AllocError` for a failed allocation of `layout`.
+    pub const fn new(layout: Layout, kind: AllocErrorKind) -> Self {
+        Self {
+            layout,
+            kind,
+            total_free: None,
+        }
+    }
+
+    /// The layout of the failed allocation.
+    ///
+    /// This is the layout of the ENTIRE allocation, including the allocator's
+    /// per-allocation header, rather than just the requested payload. If
+    /// computing the layout overflowed, this is the layout of a single
+    /// element of the requested array.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// The reason the allocation failed.
+    pub fn kind(&self) -> AllocErrorKind {
+        self.kind
+    }
+
+    /// The total number of free bytes in the heap at the time of the
+    /// failure, if known.
+    ///
+    /// This is the sum over all of the heap's free space, which may be split
+    /// up into many smaller holes: an `OutOfMemory` allocation may well be
+    /// smaller than this, if no single hole was large enough for it.
+    pub fn free_bytes(&self) -> Option<usize> {
+        self.total_free
+    }
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tried to allocate {} bytes align {}: {}",
+            self.layout.size(),
+            self.layout.align(),
+            self.kind,
+        )?;
+        if let Some(free) = self.total_free {
+            write!(f, " ({free} bytes free in total)")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for HeapBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HeapBuildError::NoRegions => "no regions were given",
+            HeapBuildError::TooManyRegions => "too many regions were given",
+            HeapBuildError::OverlappingRegions => "regions overlap",
+            HeapBuildError::RegionTooSmall => "region is too small",
+            HeapBuildError::InvalidGranularity => "allocation granularity is not a power of two",
+            HeapBuildError::InvalidNodeCache => "node cache size class is too large",
+        })
+    }
+}
+
+impl fmt::Display for RegionTooSmall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("region is too small to be added to the heap")
     }
-}
\ No newline at end of file
+}
+
+impl fmt::Display for ReallocMoved {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("allocation cannot be resized without moving it")
+    }
+}
+
+impl fmt::Display for AllocErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AllocErrorKind::OutOfMemory => "out of memory",
+            AllocErrorKind::TooLarge => "allocation is larger than the heap",
+            AllocErrorKind::Overflow => "allocation size overflowed",
+            AllocErrorKind::InvalidAlign => "alignment is not a power of two",
+            AllocErrorKind::UnsupportedAlign => "alignment is larger than the heap can provide",
+            AllocErrorKind::TimedOut => "allocation timed out",
+            AllocErrorKind::Busy => "heap was busy",
+        })
+    }
+}
@@ -1,5 +1,11 @@
 #![no_std]
 
+pub mod channel;
 pub mod containers;
 pub mod heap;
 pub mod node;
+pub mod once;
+pub mod pool;
+pub mod registry;
+pub mod ring;
+pub mod slab;
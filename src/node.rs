@@ -12,8 +12,9 @@
 //! within this module as much as is reasonably possible.
 
 use cordyceps::{mpsc_queue::Links, Linked};
-use core::mem::{ManuallyDrop, MaybeUninit};
-use core::ptr::{addr_of, null};
+use core::mem::{offset_of, ManuallyDrop};
+use core::ptr::addr_of;
+use core::sync::atomic::AtomicUsize;
 use core::{alloc::Layout, ptr::NonNull};
 
 use crate::heap::AHeap;
@@ -47,6 +48,11 @@ pub(crate) union Node<T> {
 /// It contains a pointer to the allocator, as well as storage for the item.
 ///
 /// The contained data MUST be valid for the lifetime of the `Active<T>`.
+///
+/// As this is `repr(C)`, the compiler pads the `heap` header up to the
+/// alignment of `T`, and the allocation itself uses the alignment of the
+/// whole `Node<T>`, so `data` is always suitably aligned, even for
+/// `#[repr(align(N))]` types.
 #[repr(C)]
 pub(crate) struct Active<T> {
     heap: *const AHeap,
@@ -82,10 +88,23 @@ pub(crate) struct Active<T> {
 /// The `ActiveArr::data` function handles this by using the `addr_of!` macro
 /// to obtain the pointer of the underlying array storage, WITHOUT narrowing
 /// the provenance of the outer "supersized" allocation.
+///
+/// Arrays may be allocated with a larger alignment than `T` requires (e.g.
+/// for DMA buffers). In that case the `ActiveArr` header is placed directly
+/// BEFORE the (over-aligned) data, which may leave some padding between the
+/// start of the allocation and the header. The `align` field records the
+/// alignment of the whole allocation, which is always larger than that
+/// padding, so the start of the allocation can be recovered by rounding
+/// the address of the header down to `align`.
+///
+/// The `refcnt` field is only meaningful for shared arrays (`HeapArcArray`),
+/// and is always one for exclusively owned arrays.
 #[repr(C)]
 pub(crate) struct ActiveArr<T> {
     heap: *const AHeap,
+    refcnt: AtomicUsize,
     capacity: usize,
+    align: usize,
     data: [T; 0],
 }
 
@@ -120,35 +139,44 @@ impl<T> Active<T> {
     /// This function does NOT handle dropping of the contained T, which
     /// must be done BEFORE calling this function.
     #[inline]
-    pub(crate) unsafe fn yeet(mut ptr: NonNull<Active<T>>) {
-        let heap = ptr.as_mut().heap;
-        let ptr: NonNull<Recycle> = ptr.cast();
-
-        ptr.as_ptr().write(Recycle {
-            links: Links::new(),
-            node_layout: Layout::new::<Node<T>>(),
-        });
-
-        (*heap).release_node(ptr);
+    pub(crate) unsafe fn yeet(ptr: NonNull<Active<T>>) {
+        Active::<()>::yeet_erased(ptr.cast(), Layout::new::<Node<T>>());
     }
 
-    #[inline(always)]
-    fn data_offset() -> isize {
-        let dummy: Active<MaybeUninit<T>> = Active {
-            heap: null(),
-            data: MaybeUninit::uninit(),
-        };
-        let data_ptr = addr_of!(dummy.data);
-        let dummy_ptr: *const Active<MaybeUninit<T>> = &dummy;
-        unsafe { dummy_ptr.cast::<u8>().offset_from(data_ptr.cast::<u8>()) }
+    /// The layout of the block the heap allocated for this node, including
+    /// any rounding applied by the heap.
+    pub(crate) unsafe fn alloc_layout(this: NonNull<Active<T>>) -> Layout {
+        let heap = addr_of!((*this.as_ptr()).heap).read();
+        // This was already successfully computed when the node was allocated.
+        (*heap).block_layout(Layout::new::<Node<T>>()).unwrap()
     }
 
+    /// The offset of the data from the start of the node.
+    pub(crate) const DATA_OFFSET: usize = offset_of!(Active<T>, data);
+
+    /// The bytes a node takes up beyond the `T` itself: the header, and any
+    /// padding needed for alignment or to fit a `Recycle`. This doesn't
+    /// include any rounding up to the heap's allocation granularity.
+    pub(crate) const OVERHEAD_BYTES: usize =
+        core::mem::size_of::<Node<T>>() - core::mem::size_of::<T>();
+
+    /// Recover the `Active<T>` from a pointer to its data, as previously
+    /// returned by `Active::data`.
+    ///
+    /// The data pointer must have been derived from the pointer to the whole
+    /// node, so that it still has the provenance of the entire allocation.
     pub(crate) unsafe fn from_leaked_ptr(data: NonNull<T>) -> NonNull<Active<T>> {
+        debug_assert!(data.as_ptr().is_aligned(), "leaked pointer is misaligned");
         let ptr = data
             .cast::<u8>()
             .as_ptr()
-            .offset(Self::data_offset())
+            .sub(Self::DATA_OFFSET)
             .cast::<Active<T>>();
+        // Every node is allocated with the alignment of a `Node<T>`
+        debug_assert!(
+            ptr.cast::<Node<T>>().is_aligned(),
+            "leaked pointer does not belong to a node of this type"
+        );
         NonNull::new_unchecked(ptr)
     }
 
@@ -175,44 +203,108 @@ impl<T> Active<T> {
     }
 }
 
+impl Active<()> {
+    /// Like [`Active::yeet`], for a node whose `T` is no longer known.
+    ///
+    /// The heap pointer comes first in every `Active<T>`, so it can be read
+    /// without knowing `T`.
+    ///
+    /// # Safety
+    ///
+    /// `node_layout` *must* be the `Layout` of the `Node<T>` the node was
+    /// allocated as.
+    #[inline]
+    pub(crate) unsafe fn yeet_erased(ptr: NonNull<Active<()>>, node_layout: Layout) {
+        // Don't create a reference, the data has already been dropped
+        let heap = addr_of!((*ptr.as_ptr()).heap).read();
+        let ptr: NonNull<Recycle> = ptr.cast();
+
+        ptr.as_ptr().write(Recycle {
+            links: Links::new(),
+            node_layout,
+        });
+
+        (*heap).release_node(ptr);
+    }
+}
+
 impl<T> ActiveArr<T> {
     /// Obtain a valid layout for an ActiveArr
     ///
     /// As we can't directly create a `Layout` type for our Node<T>/ActiveArr<T>
     /// because of the `!Sized` nature of `[T]`, we instead do manual layout
     /// surgery here instead. This function takes the alignment necessary for
-    /// a `Node<T>`, but also increases the size to accomodate a `[T]` with
-    /// a size of the given `ct` parameter.
+    /// a `Node<T>` (or the requested `align`, if that is larger), but also
+    /// increases the size to accomodate a `[T]` with a size of the given `ct`
+    /// parameter.
     ///
     /// The given layout will always have a size >= the size of a `Node<T>`, even
     /// if the `ActiveArr<T> + [T]` would be smaller than a `Node<T>`.
+    ///
+    /// Also returns the offset of the `ActiveArr<T>` header from the start of
+    /// the allocation, which is only non-zero for over-aligned arrays.
+    ///
+    /// Returns `None` if the total size of the allocation would overflow, or
+    /// if `align` is not a power of two.
     #[inline]
-    pub(crate) unsafe fn layout_for_arr(ct: usize) -> Layout {
+    pub(crate) fn layout_for_arr(ct: usize, align: usize) -> Option<(Layout, usize)> {
         let layout_node = Layout::new::<Node<T>>();
         let layout_acta = Layout::new::<ActiveArr<T>>();
-        let arr_size = core::mem::size_of::<T>() * ct;
-        let size = layout_acta.size() + arr_size;
+        if !align.is_power_of_two() {
+            return None;
+        }
+        let align = core::cmp::max(layout_node.align(), align);
+
+        // The data must start at a multiple of `align`, and the header sits
+        // directly in front of it.
+        let data_offset = layout_acta.size().checked_add(align - 1)? & !(align - 1);
+        let header_offset = data_offset - layout_acta.size();
+
+        let arr_size = core::mem::size_of::<T>().checked_mul(ct)?;
+        let size = data_offset.checked_add(arr_size)?;
         let size = core::cmp::max(layout_node.size(), size);
 
         // We take the ALIGNMENT from the `Node`, which is a superset
         // type, and the SIZE from either the (ActiveArr + Array) OR
         // Node, whichever is larger
-        Layout::from_size_align_unchecked(size, layout_node.align())
+        let layout = Layout::from_size_align(size, align).ok()?;
+        Some((layout, header_offset))
     }
 
-    /// Set the heap pointer contained within the given ActiveArr<T>.
+    /// Initialize the header of a freshly allocated ActiveArr<T>.
     ///
-    /// This should ONLY be used to initialize the ActiveArr<T> at time of allocation.
+    /// `block` is the start of the allocation, and `align` and `header_offset`
+    /// are the values used to compute its layout with `layout_for_arr`.
+    ///
+    /// This should ONLY be used at time of allocation.
     #[inline(always)]
-    pub(crate) unsafe fn write_heap(this: NonNull<ActiveArr<T>>, heap: *const AHeap) {
+    pub(crate) unsafe fn init_header(
+        block: NonNull<u8>,
+        header_offset: usize,
+        heap: *const AHeap,
+        capacity: usize,
+        align: usize,
+    ) -> NonNull<ActiveArr<T>> {
+        let this = NonNull::new_unchecked(block.as_ptr().add(header_offset)).cast::<ActiveArr<T>>();
         let ptr = this.as_ptr();
+        let align = core::cmp::max(Layout::new::<Node<T>>().align(), align);
         core::ptr::addr_of_mut!((*ptr).heap).write(heap);
+        core::ptr::addr_of_mut!((*ptr).refcnt).write(AtomicUsize::new(1));
+        core::ptr::addr_of_mut!((*ptr).capacity).write(capacity);
+        core::ptr::addr_of_mut!((*ptr).align).write(align);
+        this
     }
 
-    #[inline(always)]
-    pub(crate) unsafe fn write_capacity(this: NonNull<ActiveArr<T>>, capacity: usize) {
-        let ptr = this.as_ptr();
-        core::ptr::addr_of_mut!((*ptr).capacity).write(capacity);
+    /// Recover the `ActiveArr<T>` from a pointer to the start of its array
+    /// storage, as previously returned by `ActiveArr::data`.
+    pub(crate) unsafe fn from_leaked_ptr(data: NonNull<T>) -> NonNull<ActiveArr<T>> {
+        debug_assert!(data.as_ptr().is_aligned(), "leaked pointer is misaligned");
+        let ptr = data
+            .cast::<u8>()
+            .as_ptr()
+            .sub(offset_of!(ActiveArr<T>, data))
+            .cast::<ActiveArr<T>>();
+        NonNull::new_unchecked(ptr)
     }
 
     /// Obtain a pointer to the start of the array storage, as well as the length of the array
@@ -222,24 +314,48 @@ impl<T> ActiveArr<T> {
     /// a more detailed discussion of these particularities.
     #[inline(always)]
     pub(crate) unsafe fn data(this: NonNull<ActiveArr<T>>) -> (NonNull<T>, usize) {
-        let size = this.as_ref().capacity;
         let tptr = this.as_ptr();
+        let size = addr_of!((*tptr).capacity).read();
         let daddr = core::ptr::addr_of_mut!((*tptr).data);
         let nn = NonNull::new_unchecked(daddr.cast::<T>());
         (nn, size)
     }
 
+    /// Obtain a reference to the reference count of a shared array.
+    #[inline(always)]
+    pub(crate) unsafe fn refcnt<'a>(this: NonNull<ActiveArr<T>>) -> &'a AtomicUsize {
+        let ptr = this.as_ptr();
+        &*core::ptr::addr_of!((*ptr).refcnt)
+    }
+
+    /// The layout of the block the heap allocated for this node, including
+    /// any rounding applied by the heap.
+    pub(crate) unsafe fn alloc_layout(this: NonNull<ActiveArr<T>>) -> Layout {
+        let ptr = this.as_ptr();
+        let heap = addr_of!((*ptr).heap).read();
+        let capacity = addr_of!((*ptr).capacity).read();
+        let align = addr_of!((*ptr).align).read();
+        // This was already successfully computed when the node was allocated.
+        let (layout, _) = Self::layout_for_arr(capacity, align).unwrap();
+        (*heap).block_layout(layout).unwrap()
+    }
+
     /// Convert an Active<T> into a Recycle, and release it to be freed
     ///
     /// This function does NOT handle dropping of the contained `[T]`, which
     /// must be done BEFORE calling this function.
     #[inline]
-    pub(crate) unsafe fn yeet(mut ptr: NonNull<ActiveArr<T>>) {
-        let heap = ptr.as_mut().heap;
-        let capacity = ptr.as_mut().capacity;
-
-        let ptr: NonNull<Recycle> = ptr.cast();
-        let layout = Self::layout_for_arr(capacity);
+    pub(crate) unsafe fn yeet(ptr: NonNull<ActiveArr<T>>) {
+        // As in `data`, don't narrow the provenance with a reference
+        let heap = addr_of!((*ptr.as_ptr()).heap).read();
+        let capacity = addr_of!((*ptr.as_ptr()).capacity).read();
+        let align = addr_of!((*ptr.as_ptr()).align).read();
+
+        // This was already successfully computed when the array was allocated.
+        let (layout, header_offset) = Self::layout_for_arr(capacity, align).unwrap();
+        let block = ptr.as_ptr().cast::<u8>().sub(header_offset);
+        debug_assert_eq!(block.addr() % align, 0);
+        let ptr: NonNull<Recycle> = NonNull::new_unchecked(block).cast();
 
         ptr.as_ptr().write(Recycle {
             links: Links::new(),
@@ -253,15 +369,18 @@ impl<T> ActiveArr<T> {
 impl ActiveUnsized {
     /// Obtain a valid layout for an ActiveUnsized with an inner allocation of
     /// the requested `Layout`.
+    ///
+    /// Returns `None` if the total size of the allocation would overflow.
     #[inline]
-    pub(crate) fn layout(layout_inner: Layout) -> (Layout, usize) {
+    pub(crate) fn layout(layout_inner: Layout) -> Option<(Layout, usize)> {
         let layout_node = Layout::new::<Node<()>>();
-        let (mut layout, offset) = Layout::new::<*const AHeap>().extend(layout_inner).unwrap();
-        // round up to ensure we can fit a `Node`
-        if layout_node.size() > layout.size() {
-            layout = layout_node;
-        }
-        (layout, offset)
+        let (layout, offset) = Layout::new::<*const AHeap>().extend(layout_inner).ok()?;
+        // round up to ensure we can fit a `Node`, without losing any extra
+        // alignment requested for the inner allocation
+        let size = core::cmp::max(layout.size(), layout_node.size());
+        let align = core::cmp::max(layout.align(), layout_node.align());
+        let layout = Layout::from_size_align(size, align).ok()?;
+        Some((layout, offset))
     }
 
     /// Set the heap pointer contained within the given `ActiveUnsized`.
@@ -275,7 +394,7 @@ impl ActiveUnsized {
 
     #[inline(always)]
     pub(crate) unsafe fn from_raw(data: NonNull<()>, layout_inner: Layout) -> NonNull<Self> {
-        let (_layout, offset) = Self::layout(layout_inner);
+        let (_layout, offset) = Self::layout(layout_inner).unwrap();
         let ptr = data.cast::<u8>().as_ptr().sub(offset).cast::<Self>();
         NonNull::new_unchecked(ptr)
     }
@@ -287,11 +406,11 @@ impl ActiveUnsized {
     /// The provided `Layout` *must* be the same as the `ActiveUnsized`'s
     /// original allocated `Layout`!
     #[inline]
-    pub(crate) unsafe fn yeet(mut ptr: NonNull<Self>, layout: Layout) {
-        let heap = ptr.as_mut().heap;
+    pub(crate) unsafe fn yeet(ptr: NonNull<Self>, layout: Layout) {
+        let heap = addr_of!((*ptr.as_ptr()).heap).read();
 
         let ptr: NonNull<Recycle> = ptr.cast();
-        let (layout, _) = Self::layout(layout);
+        let (layout, _) = Self::layout(layout).unwrap();
 
         ptr.as_ptr().write(Recycle {
             links: Links::new(),
@@ -0,0 +1,150 @@
+//! # `mnemos-alloc` One-shot Cells
+//!
+//! A [`HeapOnce`] is a shared slot that a value can be put into once, and
+//! taken out of once, without any locking. This is useful for handing a
+//! single result from one task to another.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::{
+    containers::HeapArc,
+    heap::{AHeap, AllocError, HeapGuard},
+};
+
+/// A shared, heap allocated, one-shot slot for a `T`.
+///
+/// Clones of a `HeapOnce` refer to the same slot. The value can be [set]
+/// once, and [taken] once, from any of the clones.
+///
+/// [set]: HeapOnce::set
+/// [taken]: HeapOnce::take
+pub struct HeapOnce<T> {
+    inner: HeapArc<OnceInner<T>>,
+}
+
+struct OnceInner<T> {
+    state: AtomicU8,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// The value is only ever accessed by one clone at a time, guarded by `state`.
+unsafe impl<T: Send> Send for HeapOnce<T> {}
+unsafe impl<T: Send> Sync for HeapOnce<T> {}
+
+// === impl HeapOnce ===
+
+impl<T> HeapOnce<T> {
+    /// The value has not been set yet.
+    const EMPTY: u8 = 0;
+    /// The value is being written by `set`.
+    const WRITING: u8 = 1;
+    /// The value has been set, and not taken yet.
+    const FULL: u8 = 2;
+    /// The value has been taken.
+    const TAKEN: u8 = 3;
+
+    /// Allocate a new, empty slot, waiting for space to become available if
+    /// necessary.
+    pub async fn new(heap: &'static AHeap) -> Self {
+        Self {
+            inner: heap.allocate_arc(OnceInner::new()).await,
+        }
+    }
+
+    /// Attempt to allocate a new, empty slot.
+    pub fn try_new(guard: &mut HeapGuard) -> Result<Self, AllocError> {
+        let inner = guard
+            .alloc_arc_or_err(OnceInner::new())
+            .map_err(|(_, err)| err)?;
+        Ok(Self { inner })
+    }
+
+    /// Put `value` into the slot.
+    ///
+    /// This only succeeds the first time it is called: if the slot has
+    /// already been set (even if the value has since been taken), `value`
+    /// is handed back.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let inner = &*self.inner;
+        if inner
+            .state
+            .compare_exchange(
+                Self::EMPTY,
+                Self::WRITING,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            return Err(value);
+        }
+        // SAFETY: Moving out of `EMPTY` gives us exclusive access to the value
+        unsafe { (*inner.value.get()).write(value) };
+        inner.state.store(Self::FULL, Ordering::Release);
+        Ok(())
+    }
+
+    /// Take the value out of the slot, if it has been set and not already
+    /// taken.
+    pub fn take(&self) -> Option<T> {
+        let inner = &*self.inner;
+        inner
+            .state
+            .compare_exchange(
+                Self::FULL,
+                Self::TAKEN,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .ok()?;
+        // SAFETY: Moving out of `FULL` gives us exclusive access to the
+        // (initialized) value, and nobody will access it again.
+        Some(unsafe { (*inner.value.get()).assume_init_read() })
+    }
+
+    /// Returns whether a value has been set, and not yet taken.
+    pub fn is_full(&self) -> bool {
+        self.inner.state.load(Ordering::Acquire) == Self::FULL
+    }
+}
+
+impl<T> Clone for HeapOnce<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for HeapOnce<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeapOnce")
+            .field("is_full", &self.is_full())
+            .finish()
+    }
+}
+
+// === impl OnceInner ===
+
+impl<T> OnceInner<T> {
+    fn new() -> Self {
+        Self {
+            state: AtomicU8::new(HeapOnce::<T>::EMPTY),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+}
+
+impl<T> Drop for OnceInner<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == HeapOnce::<T>::FULL {
+            // SAFETY: The value was set, and never taken
+            unsafe { self.value.get_mut().assume_init_drop() }
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! # `mnemos-alloc` Box Pools
+//!
+//! A [`BoxPool`] recycles the allocations of boxes of one type. Dropping a
+//! [`PoolBox`] returns its allocation to the pool, rather than to the heap,
+//! and the pool hands it out again for the next box. Once the pool has
+//! filled up, allocating and dropping boxes in a steady state doesn't touch
+//! the heap at all.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    marker::PhantomData,
+    mem::forget,
+    ops::{Deref, DerefMut},
+    ptr::{drop_in_place, null_mut, NonNull},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use crate::{
+    containers::HeapBox,
+    heap::{AHeap, HeapGuard},
+    node::Active,
+};
+
+/// A pool of recycled allocations for `HeapBox<T>`s.
+///
+/// Up to `capacity` allocations are kept in the pool. Any more than that are
+/// returned to the heap when their box is dropped.
+pub struct BoxPool<T> {
+    heap: &'static AHeap,
+    /// Protects `head`. This is only held to push or pop a single node, and
+    /// is never waited for.
+    lock: AtomicBool,
+    head: UnsafeCell<*mut FreeNode>,
+    len: AtomicUsize,
+    capacity: usize,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    pd: PhantomData<T>,
+}
+
+/// A box allocated from a [`BoxPool`].
+///
+/// Dropping it returns the allocation to the pool.
+pub struct PoolBox<'a, T> {
+    pool: &'a BoxPool<T>,
+    ptr: NonNull<Active<T>>,
+}
+
+/// Hit and miss counts for a [`BoxPool`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Allocations that reused an allocation from the pool.
+    pub hits: usize,
+    /// Allocations that found the pool empty, and fell back to the heap.
+    pub misses: usize,
+}
+
+/// The header written into a node while it sits in the pool.
+///
+/// Every node is at least as large and as aligned as a `Recycle`, so this
+/// always fits.
+struct FreeNode {
+    next: *mut FreeNode,
+}
+
+// The pool hands out the `T`s' allocations to other tasks, but never the
+// `T`s themselves.
+unsafe impl<T: Send> Send for BoxPool<T> {}
+unsafe impl<T: Send> Sync for BoxPool<T> {}
+
+unsafe impl<T: Send> Send for PoolBox<'_, T> {}
+unsafe impl<T: Sync> Sync for PoolBox<'_, T> {}
+
+// === impl BoxPool ===
+
+impl<T> BoxPool<T> {
+    /// Create an empty pool, which keeps up to `capacity` allocations from
+    /// `heap`.
+    ///
+    /// The pool starts out empty, and is filled as its boxes are dropped.
+    pub const fn new(heap: &'static AHeap, capacity: usize) -> Self {
+        Self {
+            heap,
+            lock: AtomicBool::new(false),
+            head: UnsafeCell::new(null_mut()),
+            len: AtomicUsize::new(0),
+            capacity,
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+            pd: PhantomData,
+        }
+    }
+
+    /// Allocate a box holding `value`, reusing an allocation from the pool if
+    /// there is one, and otherwise waiting for space to become available on
+    /// the heap if necessary.
+    pub async fn alloc(&self, value: T) -> PoolBox<'_, T> {
+        match self.pop_or_miss() {
+            Some(ptr) => self.reuse(ptr, value),
+            None => self.adopt(self.heap.allocate(value).await),
+        }
+    }
+
+    /// Attempt to allocate a box holding `value`, reusing an allocation from
+    /// the pool if there is one.
+    ///
+    /// If the pool is empty, and allocating from the heap with `guard`
+    /// fails, `value` is handed back.
+    ///
+    /// ## Panics
+    ///
+    /// If `guard` is not for the pool's heap, which every box in the pool
+    /// must be allocated from.
+    pub fn try_alloc(&self, guard: &mut HeapGuard, value: T) -> Result<PoolBox<'_, T>, T> {
+        assert!(
+            core::ptr::eq(guard.heap(), self.heap),
+            "guard is for a different heap than the pool's"
+        );
+        match self.pop_or_miss() {
+            Some(ptr) => Ok(self.reuse(ptr, value)),
+            None => guard.alloc_box(value).map(|hb| self.adopt(hb)),
+        }
+    }
+
+    /// The number of allocations currently in the pool.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether there are no allocations currently in the pool.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The maximum number of allocations kept in the pool.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Obtain the hit and miss counts of the pool.
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Pop a node from the pool, if there is one.
+    ///
+    /// If the pool is busy, this counts as a miss, rather than waiting.
+    fn pop_or_miss(&self) -> Option<NonNull<Active<T>>> {
+        let ptr = self
+            .try_with_lock(|head| {
+                let node = NonNull::new(*head)?;
+                // SAFETY: Nodes in the list are valid `FreeNode`s
+                *head = unsafe { node.as_ref().next };
+                Some(node)
+            })
+            .flatten();
+        match ptr {
+            Some(node) => {
+                self.len.fetch_sub(1, Ordering::Relaxed);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(node.cast())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Put a node, whose contents have already been dropped, back into the
+    /// pool, or free it if the pool is full (or busy).
+    unsafe fn push_or_free(&self, ptr: NonNull<Active<T>>) {
+        // Reserve our spot first, so the pool never grows beyond capacity
+        let reserved = self
+            .len
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |len| {
+                (len < self.capacity).then_some(len + 1)
+            })
+            .is_ok();
+        if !reserved {
+            Active::<T>::yeet(ptr);
+            return;
+        }
+
+        let node = ptr.cast::<FreeNode>();
+        let pushed = self.try_with_lock(|head| {
+            node.as_ptr().write(FreeNode { next: *head });
+            *head = node.as_ptr();
+        });
+        if pushed.is_none() {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+            Active::<T>::yeet(ptr);
+        }
+    }
+
+    /// Write `value` into a node from the pool.
+    fn reuse(&self, ptr: NonNull<Active<T>>, value: T) -> PoolBox<'_, T> {
+        unsafe {
+            // The header was overwritten while it was in the pool
+            Active::<T>::write_heap(ptr, self.heap);
+            Active::<T>::data(ptr).as_ptr().write(value);
+        }
+        PoolBox { pool: self, ptr }
+    }
+
+    fn adopt(&self, hb: HeapBox<T>) -> PoolBox<'_, T> {
+        let ptr = hb.ptr;
+        forget(hb);
+        PoolBox { pool: self, ptr }
+    }
+
+    /// Call `f` with the list, unless someone else is using it.
+    ///
+    /// This never waits for the lock: whoever holds it may have been
+    /// interrupted by us, and would never get to release it.
+    fn try_with_lock<R>(&self, f: impl FnOnce(&mut *mut FreeNode) -> R) -> Option<R> {
+        self.lock
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        // SAFETY: The lock gives us exclusive access to the list
+        let res = f(unsafe { &mut *self.head.get() });
+        self.lock.store(false, Ordering::Release);
+        Some(res)
+    }
+}
+
+impl<T> Drop for BoxPool<T> {
+    fn drop(&mut self) {
+        let mut node = *self.head.get_mut();
+        while let Some(nn) = NonNull::new(node) {
+            unsafe {
+                node = nn.as_ref().next;
+                let ptr = nn.cast::<Active<T>>();
+                // Restore the header that `yeet` needs
+                Active::<T>::write_heap(ptr, self.heap);
+                Active::<T>::yeet(ptr);
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for BoxPool<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoxPool")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+// === impl PoolBox ===
+
+impl<T> PoolBox<'_, T> {
+    /// Detach the box from its pool, so that its allocation is returned to
+    /// the heap (rather than the pool) when it is dropped.
+    pub fn into_box(this: Self) -> HeapBox<T> {
+        let ptr = this.ptr;
+        forget(this);
+        HeapBox {
+            ptr,
+            pd: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for PoolBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*Active::<T>::data(self.ptr).as_ptr() }
+    }
+}
+
+impl<T> DerefMut for PoolBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *Active::<T>::data(self.ptr).as_ptr() }
+    }
+}
+
+impl<T> Drop for PoolBox<'_, T> {
+    fn drop(&mut self) {
+        unsafe {
+            drop_in_place(Active::<T>::data(self.ptr).as_ptr());
+            self.pool.push_or_free(self.ptr);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for PoolBox<'_, T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
@@ -0,0 +1,215 @@
+//! # `mnemos-alloc` Arc Registries
+//!
+//! Collections of [`HeapWeak`]s, which go away on their own once the last
+//! [`HeapArc`] for them is dropped.
+//!
+//! A [`HeapArcRegistry`] interns shared values: looking up a key returns the
+//! existing [`HeapArc`] for it, if there still is one, or makes a new one.
+//!
+//! A [`WeakList`] holds observers, such as the subscribers of a pub/sub
+//! system, each of which is visited while it is still alive.
+
+use crate::{
+    containers::{HeapArc, HeapArray, HeapFixedVec, HeapWeak},
+    heap::{AHeap, AllocError, HeapGuard},
+};
+
+/// A fixed capacity, heap allocated cache of [`HeapArc`]s, keyed by `K`.
+///
+/// NOTE: A [`HeapWeak`] keeps the allocation of its arc alive (though not
+/// its contents), so the allocations of dead entries are only released when
+/// their slot is reused, or by [`HeapArcRegistry::prune`].
+pub struct HeapArcRegistry<K, V> {
+    entries: HeapArray<Option<(K, HeapWeak<V>)>>,
+}
+
+/// A fixed capacity, heap allocated list of [`HeapWeak`]s.
+///
+/// Entries whose arcs have all been dropped are removed as they are found,
+/// by [`WeakList::for_each_live`], or to make room in [`WeakList::push`].
+pub struct WeakList<T> {
+    weaks: HeapFixedVec<HeapWeak<T>>,
+}
+
+// === impl HeapArcRegistry ===
+
+impl<K: Eq, V> HeapArcRegistry<K, V> {
+    /// Allocate a registry with room for `capacity` entries, waiting for
+    /// space to become available if necessary.
+    pub async fn new(heap: &'static AHeap, capacity: usize) -> Self {
+        Self {
+            entries: heap.allocate_array_with(|| None, capacity).await,
+        }
+    }
+
+    /// Attempt to allocate a registry with room for `capacity` entries.
+    pub fn try_new(guard: &mut HeapGuard, capacity: usize) -> Result<Self, AllocError> {
+        guard
+            .alloc_box_array_with(|| None, capacity)
+            .map(|entries| Self { entries })
+    }
+
+    /// The number of entries the registry can hold.
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Obtain the live `HeapArc` for `key`, if there is one.
+    pub fn get(&self, key: &K) -> Option<HeapArc<V>> {
+        self.entries
+            .iter()
+            .flatten()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, weak)| weak.upgrade())
+    }
+
+    /// Obtain the live `HeapArc` for `key`, or allocate a new one holding
+    /// `make()` (waiting for space to become available if necessary), and
+    /// register it.
+    ///
+    /// If the registry is full of live entries, `key` is handed back, and
+    /// `make` is not called.
+    pub async fn get_or_insert(
+        &mut self,
+        heap: &'static AHeap,
+        key: K,
+        make: impl FnOnce() -> V,
+    ) -> Result<HeapArc<V>, K> {
+        let slot = match self.lookup(&key) {
+            Ok(arc) => return Ok(arc),
+            Err(Some(slot)) => slot,
+            Err(None) => return Err(key),
+        };
+        let arc = heap.allocate_arc(make()).await;
+        self.entries[slot] = Some((key, HeapArc::downgrade(&arc)));
+        Ok(arc)
+    }
+
+    /// Like [`HeapArcRegistry::get_or_insert`], but allocating with `guard`.
+    ///
+    /// `key` is handed back if the registry is full of live entries (with
+    /// no error), or if there wasn't enough space to allocate the new arc
+    /// (with the reason, and the value returned by `make` is dropped).
+    pub fn try_get_or_insert(
+        &mut self,
+        guard: &mut HeapGuard,
+        key: K,
+        make: impl FnOnce() -> V,
+    ) -> Result<HeapArc<V>, (K, Option<AllocError>)> {
+        let slot = match self.lookup(&key) {
+            Ok(arc) => return Ok(arc),
+            Err(Some(slot)) => slot,
+            Err(None) => return Err((key, None)),
+        };
+        match guard.alloc_arc_or_err(make()) {
+            Ok(arc) => {
+                self.entries[slot] = Some((key, HeapArc::downgrade(&arc)));
+                Ok(arc)
+            }
+            Err((_, err)) => Err((key, Some(err))),
+        }
+    }
+
+    /// Remove the entries whose arcs have all been dropped, releasing their
+    /// allocations, and return how many were removed.
+    pub fn prune(&mut self) -> usize {
+        let mut pruned = 0;
+        for entry in self.entries.iter_mut() {
+            if entry
+                .as_ref()
+                .is_some_and(|(_, weak)| weak.strong_count() == 0)
+            {
+                *entry = None;
+                pruned += 1;
+            }
+        }
+        pruned
+    }
+
+    /// Find the live arc for `key`, or else the slot a new entry for it
+    /// should go in, if any.
+    ///
+    /// An entry for `key` whose arcs have all been dropped is replaced, so
+    /// that there is only ever one entry per key.
+    fn lookup(&self, key: &K) -> Result<HeapArc<V>, Option<usize>> {
+        let mut vacant = None;
+        for (i, entry) in self.entries.iter().enumerate() {
+            match entry {
+                Some((k, weak)) if k == key => match weak.upgrade() {
+                    Some(arc) => return Ok(arc),
+                    None => return Err(Some(i)),
+                },
+                Some((_, weak)) if weak.strong_count() != 0 => {}
+                _ => {
+                    vacant.get_or_insert(i);
+                }
+            }
+        }
+        Err(vacant)
+    }
+}
+
+// === impl WeakList ===
+
+impl<T> WeakList<T> {
+    /// Allocate a list with room for `capacity` entries, waiting for space
+    /// to become available if necessary.
+    pub async fn new(heap: &'static AHeap, capacity: usize) -> Self {
+        Self {
+            weaks: heap.allocate_fixed_vec(capacity).await,
+        }
+    }
+
+    /// Attempt to allocate a list with room for `capacity` entries.
+    pub fn try_new(guard: &mut HeapGuard, capacity: usize) -> Result<Self, AllocError> {
+        guard.alloc_fixed_vec(capacity).map(|weaks| Self { weaks })
+    }
+
+    /// The number of entries in the list, including any whose arcs have been
+    /// dropped but which haven't been removed yet.
+    pub fn len(&self) -> usize {
+        self.weaks.len()
+    }
+
+    /// Returns whether the list holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.weaks.is_empty()
+    }
+
+    /// The number of entries the list can hold.
+    pub fn capacity(&self) -> usize {
+        self.weaks.capacity()
+    }
+
+    /// Add an entry to the list, first removing dead entries if it is full.
+    ///
+    /// If the list is still full, the entry is handed back.
+    pub fn push(&mut self, weak: HeapWeak<T>) -> Result<(), HeapWeak<T>> {
+        if self.weaks.is_full() {
+            self.for_each_live(|_| {});
+        }
+        self.weaks.push(weak)
+    }
+
+    /// Call `f` with each entry whose arcs haven't all been dropped, and
+    /// remove the rest.
+    ///
+    /// Removing an entry moves the last entry into its place, so entries are
+    /// not necessarily visited in the order they were pushed.
+    pub fn for_each_live<F: FnMut(&HeapArc<T>)>(&mut self, mut f: F) {
+        let mut i = 0;
+        while i < self.weaks.len() {
+            match self.weaks[i].upgrade() {
+                Some(arc) => {
+                    f(&arc);
+                    i += 1;
+                }
+                None => {
+                    drop(self.weaks.swap_remove(i));
+                    // Don't move on: the entry swapped into `i` hasn't been
+                    // visited yet.
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! # `mnemos-alloc` Ring Buffers
+//!
+//! A [`HeapRingBuffer`] is a fixed capacity, heap allocated, first-in
+//! first-out queue. It can be used directly, or [split] into a
+//! [`RingProducer`] and a [`RingConsumer`], which can be used from two
+//! different tasks (or an interrupt and a task) without any locking.
+//!
+//! [split]: HeapRingBuffer::split
+
+use core::{
+    alloc::Layout,
+    cell::UnsafeCell,
+    fmt,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    containers::HeapArray,
+    heap::{AHeap, AllocError, AllocErrorKind, HeapGuard},
+};
+
+/// A fixed capacity, heap allocated, single-producer single-consumer ring
+/// buffer.
+///
+/// The capacity is always a power of two.
+pub struct HeapRingBuffer<T> {
+    slots: HeapArray<UnsafeCell<MaybeUninit<T>>>,
+    /// The total number of items ever popped, wrapping
+    head: AtomicUsize,
+    /// The total number of items ever pushed, wrapping
+    tail: AtomicUsize,
+}
+
+/// The pushing half of a [`HeapRingBuffer`], see [`HeapRingBuffer::split`].
+pub struct RingProducer<'a, T> {
+    ring: &'a HeapRingBuffer<T>,
+}
+
+/// The popping half of a [`HeapRingBuffer`], see [`HeapRingBuffer::split`].
+pub struct RingConsumer<'a, T> {
+    ring: &'a HeapRingBuffer<T>,
+}
+
+// Shared references only allow pushing or popping through the (single)
+// producer and consumer, which each only touch their own end of the buffer.
+unsafe impl<T: Send> Sync for HeapRingBuffer<T> {}
+
+// === impl HeapRingBuffer ===
+
+impl<T> HeapRingBuffer<T> {
+    /// Allocate a ring buffer with room for at least `capacity` items,
+    /// waiting for space to become available if necessary.
+    ///
+    /// The capacity is rounded up to the next power of two.
+    ///
+    /// # Panics
+    ///
+    /// If the rounded capacity overflows a `usize`, or could never fit in
+    /// the heap.
+    pub async fn new(heap: &'static AHeap, capacity: usize) -> Self {
+        let capacity = match Self::round_capacity(capacity) {
+            Ok(capacity) => capacity,
+            Err(err) => panic!("{err}"),
+        };
+        Self::from_slots(heap.allocate_array_with(Self::vacant, capacity).await)
+    }
+
+    /// Attempt to allocate a ring buffer with room for at least `capacity`
+    /// items.
+    ///
+    /// The capacity is rounded up to the next power of two.
+    pub fn try_new(guard: &mut HeapGuard, capacity: usize) -> Result<Self, AllocError> {
+        let capacity = Self::round_capacity(capacity)?;
+        guard
+            .alloc_box_array_with(Self::vacant, capacity)
+            .map(Self::from_slots)
+    }
+
+    fn round_capacity(capacity: usize) -> Result<usize, AllocError> {
+        capacity.checked_next_power_of_two().ok_or_else(|| {
+            AllocError::new(
+                Layout::new::<UnsafeCell<MaybeUninit<T>>>(),
+                AllocErrorKind::Overflow,
+            )
+        })
+    }
+
+    fn vacant() -> UnsafeCell<MaybeUninit<T>> {
+        UnsafeCell::new(MaybeUninit::uninit())
+    }
+
+    fn from_slots(slots: HeapArray<UnsafeCell<MaybeUninit<T>>>) -> Self {
+        debug_assert!(slots.len().is_power_of_two());
+        Self {
+            slots,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// The number of items in the buffer.
+    pub fn len(&self) -> usize {
+        // Load `head` first: it never passes `tail`, so a `tail` loaded
+        // after it is at least as far along, and the difference can't wrap.
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+
+    /// Returns whether the buffer holds no items.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns whether the buffer is full.
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    /// The number of items the buffer can hold.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Push an item onto the back of the buffer.
+    ///
+    /// If the buffer is full, the item is returned.
+    pub fn try_push(&mut self, item: T) -> Result<(), T> {
+        // SAFETY: `&mut self` means there are no other producers
+        unsafe { self.push(item) }
+    }
+
+    /// Pop the item at the front of the buffer, if there is one.
+    pub fn try_pop(&mut self) -> Option<T> {
+        // SAFETY: `&mut self` means there are no other consumers
+        unsafe { self.pop() }
+    }
+
+    /// Split the buffer into a producer and a consumer, which can be used
+    /// concurrently.
+    pub fn split(&mut self) -> (RingProducer<'_, T>, RingConsumer<'_, T>) {
+        (RingProducer { ring: self }, RingConsumer { ring: self })
+    }
+
+    fn slot(&self, pos: usize) -> *mut MaybeUninit<T> {
+        self.slots[pos & (self.capacity() - 1)].get()
+    }
+
+    /// ## Safety
+    ///
+    /// Must not be called concurrently with itself.
+    pub(crate) unsafe fn push(&self, item: T) -> Result<(), T> {
+        // Only we modify the tail
+        let tail = self.tail.load(Ordering::Relaxed);
+        // Acquire the consumer's reads of the slots it has freed
+        let head = self.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == self.capacity() {
+            return Err(item);
+        }
+        (*self.slot(tail)).write(item);
+        // Publish the written slot to the consumer
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// ## Safety
+    ///
+    /// Must not be called concurrently with itself.
+    pub(crate) unsafe fn pop(&self) -> Option<T> {
+        // Only we modify the head
+        let head = self.head.load(Ordering::Relaxed);
+        // Acquire the producer's writes of the slots it has filled
+        let tail = self.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let item = (*self.slot(head)).assume_init_read();
+        // Hand the now empty slot back to the producer
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(item)
+    }
+}
+
+impl<T> Drop for HeapRingBuffer<T> {
+    fn drop(&mut self) {
+        while self.try_pop().is_some() {}
+    }
+}
+
+impl<T> fmt::Debug for HeapRingBuffer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeapRingBuffer")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+// === impl RingProducer ===
+
+impl<T> RingProducer<'_, T> {
+    /// Push an item onto the back of the buffer.
+    ///
+    /// If the buffer is full, the item is returned.
+    pub fn try_push(&mut self, item: T) -> Result<(), T> {
+        // SAFETY: There is only one producer, and `&mut self` means it isn't
+        // pushing concurrently.
+        unsafe { self.ring.push(item) }
+    }
+
+    /// Returns whether the buffer is full.
+    pub fn is_full(&self) -> bool {
+        self.ring.is_full()
+    }
+
+    /// The number of items the buffer can hold.
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+}
+
+// === impl RingConsumer ===
+
+impl<T> RingConsumer<'_, T> {
+    /// Pop the item at the front of the buffer, if there is one.
+    pub fn try_pop(&mut self) -> Option<T> {
+        // SAFETY: There is only one consumer, and `&mut self` means it isn't
+        // popping concurrently.
+        unsafe { self.ring.pop() }
+    }
+
+    /// The number of items in the buffer.
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    /// Returns whether the buffer holds no items.
+    pub fn is_empty(&self) -> bool {
+        self.ring.is_empty()
+    }
+}
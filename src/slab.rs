@@ -0,0 +1,167 @@
+//! # `mnemos-alloc` Slab
+//!
+//! A fixed capacity collection of same-typed items, addressed by [`Key`]s
+//! rather than by pointers. Removing an item frees its slot for reuse, and
+//! each slot tracks a generation counter, so a stale `Key` for a removed
+//! item can never be used to access a newer item that reused its slot.
+
+use crate::{
+    containers::HeapArray,
+    heap::{AHeap, AllocError, HeapGuard},
+};
+
+/// A handle to an item stored in a [`Slab`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    index: usize,
+    generation: u32,
+}
+
+/// A fixed capacity, heap allocated collection of items, addressed by
+/// [`Key`].
+pub struct Slab<T> {
+    slots: HeapArray<Slot<T>>,
+    /// The first vacant slot, or `slots.len()` if the slab is full
+    free_head: usize,
+    len: usize,
+}
+
+struct Slot<T> {
+    /// Incremented every time the slot's item is removed
+    generation: u32,
+    value: Option<T>,
+    /// If this slot is vacant, the next vacant slot
+    next_free: usize,
+}
+
+// === impl Key ===
+
+impl Key {
+    /// The index of the slot this key refers to.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The generation of the slot this key refers to.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+// === impl Slab ===
+
+impl<T> Slab<T> {
+    /// Allocate a slab with room for `capacity` items, waiting for space to
+    /// become available if necessary.
+    pub async fn new(heap: &'static AHeap, capacity: usize) -> Self {
+        Self::from_slots(heap.allocate_array_with(Slot::vacant, capacity).await)
+    }
+
+    /// Attempt to allocate a slab with room for `capacity` items.
+    pub fn try_new(guard: &mut HeapGuard, capacity: usize) -> Result<Self, AllocError> {
+        guard
+            .alloc_box_array_with(Slot::vacant, capacity)
+            .map(Self::from_slots)
+    }
+
+    fn from_slots(mut slots: HeapArray<Slot<T>>) -> Self {
+        // Chain all of the slots into the free list, in order
+        for (i, slot) in slots.iter_mut().enumerate() {
+            slot.next_free = i + 1;
+        }
+        Self {
+            slots,
+            free_head: 0,
+            len: 0,
+        }
+    }
+
+    /// The number of items in the slab.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the slab holds no items.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of items the slab can hold.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Insert an item into a vacant slot, returning its key.
+    ///
+    /// If the slab is full, the item is returned.
+    pub fn insert(&mut self, value: T) -> Result<Key, T> {
+        let index = self.free_head;
+        let slot = match self.slots.get_mut(index) {
+            Some(slot) => slot,
+            None => return Err(value),
+        };
+        debug_assert!(slot.value.is_none());
+
+        self.free_head = slot.next_free;
+        slot.value = Some(value);
+        self.len += 1;
+        Ok(Key {
+            index,
+            generation: slot.generation,
+        })
+    }
+
+    /// Remove the item referred to by `key`, if it is still present.
+    pub fn remove(&mut self, key: Key) -> Option<T> {
+        let slot = self.slots.get_mut(key.index)?;
+        if slot.generation != key.generation {
+            return None;
+        }
+        let value = slot.value.take()?;
+
+        // Invalidate any other copies of the key, and put the slot at the
+        // front of the free list.
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.next_free = self.free_head;
+        self.free_head = key.index;
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// Obtain a reference to the item referred to by `key`, if it is still
+    /// present.
+    pub fn get(&self, key: Key) -> Option<&T> {
+        let slot = self.slots.get(key.index)?;
+        if slot.generation != key.generation {
+            return None;
+        }
+        slot.value.as_ref()
+    }
+
+    /// Obtain a mutable reference to the item referred to by `key`, if it is
+    /// still present.
+    pub fn get_mut(&mut self, key: Key) -> Option<&mut T> {
+        let slot = self.slots.get_mut(key.index)?;
+        if slot.generation != key.generation {
+            return None;
+        }
+        slot.value.as_mut()
+    }
+
+    /// Returns whether the item referred to by `key` is still present.
+    pub fn contains(&self, key: Key) -> bool {
+        self.get(key).is_some()
+    }
+}
+
+// === impl Slot ===
+
+impl<T> Slot<T> {
+    fn vacant() -> Self {
+        Self {
+            generation: 0,
+            value: None,
+            next_free: 0,
+        }
+    }
+}
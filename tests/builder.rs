@@ -0,0 +1,374 @@
+use mnemos_alloc::{
+    containers::HeapBox,
+    heap::{AHeap, HeapBuilder, HeapGuard},
+};
+
+const SIZE: usize = 16 * 1024;
+
+/// Allocate small boxes until the heap is full
+fn fill(guard: &mut HeapGuard) -> Vec<HeapBox<u8>> {
+    let mut boxes = Vec::new();
+    while let Ok(b) = guard.alloc_box(0u8) {
+        boxes.push(b);
+    }
+    boxes
+}
+
+#[test]
+fn min_alloc_granularity() {
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe {
+        HeapBuilder::new()
+            .min_alloc_granularity(256)
+            .bootstrap(bufptr.cast::<u8>(), SIZE)
+            .unwrap()
+    };
+
+    let boxes = fill(&mut guard);
+    // Every tiny allocation took up (at least) 256 bytes
+    assert!(boxes.len() <= SIZE / 256);
+    assert!(!boxes.is_empty());
+
+    // And freeing returns the whole rounded block
+    let count = boxes.len();
+    drop(boxes);
+    let boxes = fill(&mut guard);
+    assert_eq!(boxes.len(), count);
+}
+
+#[test]
+fn default_granularity() {
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let boxes = fill(&mut guard);
+    assert!(boxes.len() > SIZE / 256);
+}
+
+#[test]
+fn handle_layouts() {
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe {
+        HeapBuilder::new()
+            .min_alloc_granularity(64)
+            .bootstrap(bufptr.cast::<u8>(), SIZE)
+            .unwrap()
+    };
+
+    let bx = guard.alloc_box(0u16).map_err(drop).unwrap();
+    let layout = bx.layout();
+    assert_eq!(layout.size() % 64, 0);
+    assert!(layout.align() >= 2);
+
+    let arr = guard.alloc_box_array_aligned_with(|| 0u8, 100, 32).unwrap();
+    let layout = arr.layout();
+    assert_eq!(layout.size() % 64, 0);
+    assert!(layout.size() >= 100);
+    assert_eq!(layout.align(), 32);
+
+    let vec = guard.alloc_fixed_vec::<u64>(10).unwrap();
+    assert!(vec.layout().size() >= 80);
+
+    // The reported layout can be used to request an identical block
+    let raw = guard.alloc_raw(arr.layout()).unwrap();
+    assert_eq!(raw.as_ptr() as usize % 32, 0);
+    unsafe { mnemos_alloc::heap::deallocate_raw(raw, arr.layout()) };
+}
+
+#[test]
+fn node_cache() {
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, mut guard) = unsafe {
+        HeapBuilder::new()
+            .with_node_cache(64, 4)
+            .bootstrap(bufptr.cast::<u8>(), SIZE)
+            .unwrap()
+    };
+    let heap = unsafe { heap.as_ref() };
+
+    // The cache starts out full
+    let boxes: Vec<_> = (0..4u64)
+        .map(|i| guard.alloc_box(i).map_err(drop).unwrap())
+        .collect();
+    assert_eq!(heap.node_cache_stats().hits, 4);
+    assert_eq!(heap.node_cache_stats().misses, 0);
+
+    // Then falls back to the main heap
+    let extra = guard.alloc_box(4u64).map_err(drop).unwrap();
+    assert_eq!(heap.node_cache_stats().misses, 1);
+
+    // Larger allocations never touch the cache
+    let big = guard.alloc_box([0u8; 128]).map_err(drop).unwrap();
+    assert_eq!(heap.node_cache_stats().hits, 4);
+    assert_eq!(heap.node_cache_stats().misses, 1);
+
+    // Freed blocks refill the cache, with the extra going to the main heap
+    drop(guard);
+    drop(boxes);
+    drop(extra);
+    drop(big);
+    let mut guard = heap.lock().unwrap();
+    let boxes: Vec<_> = (0..5u64)
+        .map(|i| guard.alloc_box(i).map_err(drop).unwrap())
+        .collect();
+    assert_eq!(heap.node_cache_stats().hits, 8);
+    assert_eq!(heap.node_cache_stats().misses, 2);
+    assert_eq!(*boxes[4], 4);
+
+    // Nothing was lost: freeing everything lets us fill the heap as usual
+    drop(boxes);
+    let boxes = fill(&mut guard);
+    assert!(boxes.len() > 4);
+}
+
+#[test]
+fn reallocate_raw_in_place_only() {
+    use core::alloc::Layout;
+    use mnemos_alloc::heap::{deallocate_raw, ReallocMoved};
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, mut guard) = unsafe {
+        HeapBuilder::new()
+            .min_alloc_granularity(256)
+            .bootstrap(bufptr.cast::<u8>(), SIZE)
+            .unwrap()
+    };
+    let heap = unsafe { heap.as_ref() };
+
+    let small = Layout::from_size_align(16, 8).unwrap();
+    let bigger = Layout::from_size_align(64, 8).unwrap();
+    let huge = Layout::from_size_align(1024, 8).unwrap();
+    let aligned = Layout::from_size_align(64, 64).unwrap();
+
+    let ptr = guard.alloc_raw(small).unwrap();
+    unsafe {
+        // Fits in the rounding slack: the pointer stays valid
+        assert_eq!(
+            heap.reallocate_raw_in_place_only(ptr, small, bigger),
+            Ok(())
+        );
+        // These would need a different block
+        assert_eq!(
+            heap.reallocate_raw_in_place_only(ptr, bigger, huge),
+            Err(ReallocMoved)
+        );
+        assert_eq!(
+            heap.reallocate_raw_in_place_only(ptr, bigger, aligned),
+            Err(ReallocMoved)
+        );
+        deallocate_raw(ptr, bigger);
+    }
+
+    // Nothing leaked
+    drop(guard);
+    let mut guard = heap.lock().unwrap();
+    let _all = fill(&mut guard);
+}
+
+#[test]
+fn multiple_regions() {
+    use core::ptr::NonNull;
+    use mnemos_alloc::heap::RegionTooSmall;
+
+    const SMALL: usize = 2 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SMALL]));
+    let (heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SMALL).unwrap() };
+    let heap = unsafe { heap.as_ref() };
+
+    let tiny = Box::into_raw(Box::new([0u8; 8]));
+    let tiny = NonNull::new(tiny.cast::<u8>()).unwrap();
+    assert_eq!(unsafe { guard.add_region(tiny, 8) }, Err(RegionTooSmall));
+
+    let first = fill(&mut guard);
+    drop(first);
+
+    let second = Box::into_raw(Box::new([0u8; SIZE]));
+    let second = NonNull::new(second.cast::<u8>()).unwrap();
+    unsafe { guard.add_region(second, SIZE).unwrap() };
+
+    // Small allocations prefer the bootstrap region
+    let bx = guard.alloc_box(1u32).map_err(drop).unwrap();
+    let addr = &*bx as *const u32 as usize;
+    assert!((bufptr as usize..bufptr as usize + SMALL).contains(&addr));
+    assert!(heap.owns(NonNull::from(&*bx).cast()));
+
+    // Larger ones only fit in the second one
+    let arr = guard.alloc_box_array_with(|| 0u8, 4 * 1024).unwrap();
+    let addr = arr.as_ptr() as usize;
+    assert!((second.as_ptr() as usize..second.as_ptr() as usize + SIZE).contains(&addr));
+    assert!(heap.owns(NonNull::from(&arr[0])));
+
+    // And frees go back to the right region
+    drop(bx);
+    drop(arr);
+    let boxes = fill(&mut guard);
+    assert!(boxes.len() > SIZE / 64);
+}
+
+#[test]
+fn low_watermark() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static FIRED: AtomicUsize = AtomicUsize::new(0);
+    fn on_low() {
+        FIRED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    let heap = unsafe { heap.as_ref() };
+    heap.set_low_watermark(4 * 1024, on_low);
+
+    let a = guard.alloc_box_array_with(|| 0u8, 10 * 1024).unwrap();
+    assert_eq!(FIRED.load(Ordering::SeqCst), 0);
+    let b = guard.alloc_box_array_with(|| 0u8, 3 * 1024).unwrap();
+    assert_eq!(FIRED.load(Ordering::SeqCst), 1);
+    // Debounced: doesn't fire again while still low
+    let c = guard.alloc_box(0u8).map_err(drop).unwrap();
+    assert_eq!(FIRED.load(Ordering::SeqCst), 1);
+
+    // Recovering re-arms it
+    drop(b);
+    drop(c);
+    let b = guard.alloc_box_array_with(|| 0u8, 3 * 1024).unwrap();
+    assert_eq!(FIRED.load(Ordering::SeqCst), 2);
+    drop((a, b));
+}
+
+#[test]
+fn deterministic_strategy() {
+    use mnemos_alloc::heap::AllocStrategy;
+
+    /// Run the same sequence of allocations and frees, returning the offsets
+    /// of the allocations from the first one.
+    ///
+    /// (The buffer itself may be differently aligned each time, so the
+    /// offsets from its start can differ.)
+    fn offsets(relock: bool) -> Vec<usize> {
+        let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+        let (heap, guard) = unsafe {
+            HeapBuilder::new()
+                .strategy(AllocStrategy::Deterministic)
+                .bootstrap(bufptr.cast::<u8>(), SIZE)
+                .unwrap()
+        };
+        let heap = unsafe { heap.as_ref() };
+        assert_eq!(heap.strategy(), AllocStrategy::Deterministic);
+        drop(guard);
+
+        let mut offsets = Vec::new();
+        let mut live = Vec::new();
+        for i in 0..16usize {
+            // Sometimes free while the heap is locked
+            let guard = relock.then(|| heap.lock().unwrap());
+            if i % 3 == 0 && !live.is_empty() {
+                live.remove(0);
+            }
+            drop(guard);
+
+            let b = heap
+                .lock()
+                .unwrap()
+                .alloc_box_array_with(|| 0u8, 16 + 8 * (i % 4))
+                .unwrap();
+            offsets.push(b.as_ptr().addr());
+            live.push(b);
+        }
+        let base = offsets[0];
+        offsets.iter().map(|addr| addr - base).collect()
+    }
+
+    assert_eq!(offsets(false), offsets(true));
+}
+
+#[test]
+fn alloc_and_free_counts() {
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    let heap = unsafe { heap.as_ref() };
+    assert_eq!((heap.alloc_count(), heap.free_count()), (0, 0));
+
+    let a = guard.alloc_box(1u32).map_err(drop).unwrap();
+    let b = guard.alloc_box_array_with(|| 0u8, 32).unwrap();
+    assert_eq!((heap.alloc_count(), heap.free_count()), (2, 0));
+    drop(guard);
+
+    drop(a);
+    drop(b);
+    assert_eq!((heap.alloc_count(), heap.free_count()), (2, 2));
+}
+
+#[test]
+fn build_from_regions() {
+    use core::ptr::NonNull;
+    use mnemos_alloc::heap::HeapBuildError;
+
+    fn region(len: usize) -> NonNull<u8> {
+        let buf = Box::leak(vec![0u8; len].into_boxed_slice());
+        NonNull::new(buf.as_mut_ptr()).unwrap()
+    }
+
+    let build = |builder: HeapBuilder| unsafe { builder.build().map(drop) };
+
+    // The options are checked before any region is touched
+    assert_eq!(build(HeapBuilder::new()), Err(HeapBuildError::NoRegions));
+    let first = region(SIZE);
+    assert_eq!(
+        build(
+            HeapBuilder::new()
+                .region(first, SIZE)
+                .min_alloc_granularity(48)
+        ),
+        Err(HeapBuildError::InvalidGranularity)
+    );
+    assert_eq!(
+        build(
+            HeapBuilder::new()
+                .region(first, SIZE)
+                .min_alloc_granularity(0)
+        ),
+        Err(HeapBuildError::InvalidGranularity)
+    );
+    let overlapping = unsafe { NonNull::new_unchecked(first.as_ptr().add(SIZE / 2)) };
+    assert_eq!(
+        build(
+            HeapBuilder::new()
+                .region(first, SIZE)
+                .region(overlapping, SIZE / 2)
+        ),
+        Err(HeapBuildError::OverlappingRegions)
+    );
+    let mut too_many = HeapBuilder::new();
+    for _ in 0..=HeapBuilder::MAX_REGIONS {
+        too_many = too_many.region(region(SIZE), SIZE);
+    }
+    assert_eq!(build(too_many), Err(HeapBuildError::TooManyRegions));
+    assert_eq!(
+        build(HeapBuilder::new().region(region(8), 8)),
+        Err(HeapBuildError::RegionTooSmall)
+    );
+
+    static FIRED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+    fn on_low() {
+        FIRED.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    // Both regions are used, and the low watermark is armed
+    let second = region(SIZE);
+    let (heap, mut guard) = unsafe {
+        HeapBuilder::new()
+            .region(first, SIZE)
+            .region(second, SIZE)
+            .min_alloc_granularity(64)
+            .low_watermark(10 * 1024, on_low)
+            .build()
+            .unwrap()
+    };
+    let heap = unsafe { heap.as_ref() };
+    let a = guard.alloc_box_array_with(|| 0u8, 12 * 1024).unwrap();
+    let b = guard.alloc_box_array_with(|| 0u8, 12 * 1024).unwrap();
+    assert!(heap.owns(NonNull::from(&a[0])));
+    assert!(heap.owns(NonNull::from(&b[0])));
+    assert!(FIRED.load(std::sync::atomic::Ordering::SeqCst));
+}
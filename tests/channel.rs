@@ -0,0 +1,141 @@
+use std::future::Future;
+use std::pin::pin;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::task::{Context, Poll, Wake, Waker};
+
+use mnemos_alloc::{channel::try_channel, heap::AHeap};
+
+const SIZE: usize = 16 * 1024;
+
+#[derive(Default)]
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn send_waits_for_space() {
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    let (tx, mut rx) = try_channel::<u32>(&mut guard, 2).unwrap();
+
+    let flag = Arc::new(Flag::default());
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    tx.try_send(1).unwrap();
+    assert!(pin!(tx.send(2)).poll(&mut cx).is_ready());
+
+    // The channel is full, so the third send has to wait...
+    let mut send = pin!(tx.send(3));
+    assert!(send.as_mut().poll(&mut cx).is_pending());
+    assert!(!flag.0.load(Ordering::SeqCst));
+
+    // ...until an item is received.
+    assert_eq!(rx.try_recv(), Some(1));
+    assert!(flag.0.load(Ordering::SeqCst));
+    assert_eq!(send.as_mut().poll(&mut cx), Poll::Ready(Ok(())));
+
+    assert_eq!(rx.len(), 2);
+    assert_eq!(pin!(rx.recv()).poll(&mut cx), Poll::Ready(Some(2)));
+    assert_eq!(pin!(rx.recv()).poll(&mut cx), Poll::Ready(Some(3)));
+}
+
+#[test]
+fn recv_waits_for_items() {
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    let (tx, mut rx) = try_channel::<u32>(&mut guard, 4).unwrap();
+    let tx2 = tx.clone();
+
+    let flag = Arc::new(Flag::default());
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    {
+        let mut recv = pin!(rx.recv());
+        assert!(recv.as_mut().poll(&mut cx).is_pending());
+        tx2.try_send(7).unwrap();
+        assert!(flag.0.load(Ordering::SeqCst));
+        assert_eq!(recv.as_mut().poll(&mut cx), Poll::Ready(Some(7)));
+    }
+
+    // Items sent before the last sender is dropped are still received
+    tx.try_send(8).unwrap();
+    drop(tx);
+    assert!(!rx.is_closed());
+    drop(tx2);
+    assert!(rx.is_closed());
+    assert_eq!(pin!(rx.recv()).poll(&mut cx), Poll::Ready(Some(8)));
+    assert_eq!(pin!(rx.recv()).poll(&mut cx), Poll::Ready(None));
+}
+
+#[test]
+fn send_fails_once_receiver_dropped() {
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    let (tx, rx) = try_channel::<String>(&mut guard, 1).unwrap();
+
+    let flag = Arc::new(Flag::default());
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    tx.try_send(String::from("queued")).unwrap();
+    let mut send = pin!(tx.send(String::from("waiting")));
+    assert!(send.as_mut().poll(&mut cx).is_pending());
+
+    drop(rx);
+    assert!(tx.is_closed());
+    assert!(flag.0.load(Ordering::SeqCst));
+    assert_eq!(
+        send.as_mut().poll(&mut cx),
+        Poll::Ready(Err(String::from("waiting")))
+    );
+}
+
+/// `try_send` fails rather than waiting while another sender is pushing, so
+/// concurrent senders may have to retry, but every item still arrives once.
+#[test]
+fn concurrent_try_send() {
+    const ITEMS: u32 = if cfg!(miri) { 20 } else { 1000 };
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    let (tx, mut rx) = try_channel::<u32>(&mut guard, 4).unwrap();
+    drop(guard);
+
+    let handles = (0..2)
+        .map(|t| {
+            let tx = tx.clone();
+            std::thread::spawn(move || {
+                for i in 0..ITEMS {
+                    let mut item = t * ITEMS + i;
+                    while let Err(it) = tx.try_send(item) {
+                        item = it;
+                        std::thread::yield_now();
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(tx);
+
+    let mut received = Vec::new();
+    while received.len() < 2 * ITEMS as usize {
+        match rx.try_recv() {
+            Some(item) => received.push(item),
+            None => std::thread::yield_now(),
+        }
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    received.sort_unstable();
+    assert!(received.into_iter().eq(0..2 * ITEMS));
+}
@@ -0,0 +1,70 @@
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+use mnemos_alloc::{
+    containers::{HeapArc, HeapArray, HeapBox, HeapFixedVec},
+    heap::AHeap,
+};
+
+/// Poll a future that is expected to complete immediately
+fn now<F: Future>(fut: F) -> F::Output {
+    let mut cx = Context::from_waker(Waker::noop());
+    match pin!(fut).poll(&mut cx) {
+        Poll::Ready(t) => t,
+        Poll::Pending => panic!("future was not immediately ready"),
+    }
+}
+
+fn new_heap() -> &'static AHeap {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    drop(guard);
+    unsafe { heap.as_ref() }
+}
+
+// NOTE: These are all in one test, as the current heap is global state.
+#[test]
+fn current_heap() {
+    assert!(AHeap::current().is_none());
+    let res = std::panic::catch_unwind(|| HeapBox::new(1u32));
+    assert!(res.is_err());
+
+    // Lazy vecs can't allocate until there is a current heap
+    let mut lazy: HeapFixedVec<u32> = HeapFixedVec::new_lazy(4);
+    assert_eq!(lazy.push(1), Err(1));
+    assert!(!lazy.is_allocated());
+
+    let heap_a = new_heap();
+    let heap_b = new_heap();
+
+    heap_a.set_current();
+    assert!(std::ptr::eq(AHeap::current().unwrap(), heap_a));
+
+    let bx = now(HeapBox::new(123u32));
+    assert_eq!(*bx, 123);
+    let arc = now(HeapArc::new([1u8; 4]));
+    assert_eq!(*arc, [1u8; 4]);
+    let pinned = now(HeapArc::pin(5u8));
+    assert_eq!(*pinned, 5);
+    let arr: HeapArray<u16> = now(HeapArray::new(16));
+    assert_eq!(&*arr, &[0u16; 16]);
+    let mut uninit = now(HeapBox::<u32>::new_uninit());
+    uninit.write(789);
+    assert_eq!(*unsafe { uninit.assume_init() }, 789);
+
+    lazy.push(1).unwrap();
+    assert!(lazy.is_allocated());
+    assert_eq!(lazy.capacity(), 4);
+    assert_eq!(&*lazy, &[1]);
+
+    let on_b = heap_b.with_current(|| {
+        assert!(std::ptr::eq(AHeap::current().unwrap(), heap_b));
+        now(HeapBox::new(456u32))
+    });
+    assert_eq!(*on_b, 456);
+    // The previous heap is restored afterwards.
+    assert!(std::ptr::eq(AHeap::current().unwrap(), heap_a));
+}
@@ -0,0 +1,23 @@
+#![cfg(feature = "debug-arc")]
+
+use mnemos_alloc::{containers::HeapArc, heap::AHeap};
+
+#[test]
+fn refcount_info() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let line = line!() + 1;
+    let arc = guard.alloc_arc(123u32).map_err(drop).unwrap();
+    let arc2 = arc.clone();
+
+    let info = HeapArc::debug_refcount_info(&arc);
+    assert_eq!(info.count, 2);
+    assert_eq!(info.location.file(), file!());
+    assert_eq!(info.location.line(), line);
+
+    drop(arc2);
+    assert_eq!(HeapArc::debug_refcount_info(&arc).count, 1);
+}
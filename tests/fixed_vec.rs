@@ -0,0 +1,545 @@
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+use mnemos_alloc::{
+    containers::{FixedVecError, HeapArc, HeapFixedVec},
+    heap::{AHeap, AllocErrorKind},
+};
+
+/// Poll a future that is expected to complete immediately
+fn now<F: Future>(fut: F) -> F::Output {
+    let mut cx = Context::from_waker(Waker::noop());
+    match pin!(fut).poll(&mut cx) {
+        Poll::Ready(t) => t,
+        Poll::Pending => panic!("future was not immediately ready"),
+    }
+}
+
+fn new_heap() -> &'static AHeap {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    drop(guard);
+    unsafe { heap.as_ref() }
+}
+
+#[test]
+fn append() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut dst: HeapFixedVec<u32> = guard.alloc_fixed_vec(8).unwrap();
+    let mut src: HeapFixedVec<u32> = guard.alloc_fixed_vec(8).unwrap();
+
+    dst.push(1).unwrap();
+    dst.push(2).unwrap();
+    for i in 3..=6 {
+        src.push(i).unwrap();
+    }
+
+    dst.append(&mut src).unwrap();
+    assert_eq!(&*dst, &[1, 2, 3, 4, 5, 6]);
+    assert!(src.is_empty());
+
+    // Not enough room for everything: nothing is moved.
+    for i in 7..=9 {
+        src.push(i).unwrap();
+    }
+    assert!(dst.append(&mut src).is_err());
+    assert_eq!(&*dst, &[1, 2, 3, 4, 5, 6]);
+    assert_eq!(&*src, &[7, 8, 9]);
+}
+
+#[test]
+fn fmt_write() {
+    use core::fmt::Write;
+
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut buf: HeapFixedVec<u8> = guard.alloc_fixed_vec(16).unwrap();
+    write!(&mut buf, "x = {}", 42).unwrap();
+    assert_eq!(&*buf, b"x = 42");
+
+    // Out of room: this is an error, not a silent truncation.
+    assert!(buf.write_str("a very long string").is_err());
+    assert_eq!(&*buf, b"x = 42");
+}
+
+#[test]
+fn raw_parts_roundtrip() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec: HeapFixedVec<u32> = guard.alloc_fixed_vec(4).unwrap();
+    vec.push(10).unwrap();
+    vec.push(20).unwrap();
+
+    let (ptr, len, cap) = vec.into_raw_parts();
+    assert_eq!((len, cap), (2, 4));
+
+    let mut vec = unsafe { HeapFixedVec::<u32>::from_raw_parts(ptr, len, cap) };
+    assert_eq!(&*vec, &[10, 20]);
+    vec.push(30).unwrap();
+    vec.push(40).unwrap();
+    assert!(vec.is_full());
+    assert_eq!(&*vec, &[10, 20, 30, 40]);
+}
+
+#[test]
+fn swap_and_fill() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec: HeapFixedVec<u32> = guard.alloc_fixed_vec(8).unwrap();
+    for i in 0..4 {
+        vec.push(i).unwrap();
+    }
+
+    vec.swap(0, 3);
+    assert_eq!(&*vec, &[3, 1, 2, 0]);
+
+    vec.fill(9);
+    assert_eq!(&*vec, &[9, 9, 9, 9]);
+    assert_eq!(vec.len(), 4);
+
+    // Within capacity, but past the initialized length
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| vec.swap(0, 5)));
+    assert!(res.is_err());
+}
+
+#[test]
+fn indexed_iter_mut() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec: HeapFixedVec<usize> = guard.alloc_fixed_vec(8).unwrap();
+    for _ in 0..3 {
+        vec.push(100).unwrap();
+    }
+
+    let mut visited = 0;
+    for (i, item) in vec.indexed_iter_mut() {
+        *item += i;
+        visited += 1;
+    }
+    // Only the initialized elements, not the whole capacity
+    assert_eq!(visited, 3);
+    assert_eq!(&*vec, &[100, 101, 102]);
+}
+
+#[test]
+fn try_from_slice() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec = HeapFixedVec::try_from_slice(&mut guard, 4, &[String::from("a")]).unwrap();
+    assert_eq!(&*vec, &[String::from("a")]);
+    // Room left over for more
+    vec.push(String::from("b")).unwrap();
+
+    let vec = HeapFixedVec::try_from_slice_copy(&mut guard, 8, &[1u16, 2, 3]).unwrap();
+    assert_eq!(&*vec, &[1, 2, 3]);
+    assert!(!vec.is_full());
+
+    // Too long for the capacity
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        HeapFixedVec::try_from_slice_copy(&mut guard, 2, &[1u16, 2, 3])
+    }));
+    assert!(res.is_err());
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        HeapFixedVec::try_from_slice(&mut guard, 0, &[()])
+    }));
+    assert!(res.is_err());
+
+    // Running out of memory is reported
+    let err = HeapFixedVec::try_from_slice(&mut guard, SIZE, &[0u8]).unwrap_err();
+    assert_eq!(err.kind(), AllocErrorKind::TooLarge);
+}
+
+#[test]
+fn extract_if() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec: HeapFixedVec<String> = guard.alloc_fixed_vec(8).unwrap();
+    for i in 0..8 {
+        vec.push(i.to_string()).unwrap();
+    }
+
+    let odds: Vec<String> = vec
+        .extract_if(|s| s.parse::<u32>().unwrap() % 2 == 1)
+        .collect();
+    assert_eq!(odds, ["1", "3", "5", "7"]);
+    assert_eq!(&*vec, &["0", "2", "4", "6"]);
+
+    // Dropping the iterator early still removes the rest of the matches
+    let mut iter = vec.extract_if(|s| s != "4");
+    assert_eq!(iter.next().as_deref(), Some("0"));
+    drop(iter);
+    assert_eq!(&*vec, &["4"]);
+}
+
+#[test]
+fn push_mut() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec: HeapFixedVec<(u32, u32)> = guard.alloc_fixed_vec(2).unwrap();
+    let item = vec.push_mut((1, 0)).unwrap();
+    item.1 = 10;
+    vec.push_mut((2, 0)).unwrap().1 = 20;
+    assert_eq!(&*vec, &[(1, 10), (2, 20)]);
+
+    assert_eq!(vec.push_mut((3, 0)), Err((3, 0)));
+}
+
+#[test]
+fn checked_capacity_ops() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec: HeapFixedVec<u32> = guard.alloc_fixed_vec(4).unwrap();
+    vec.insert(0, 2).unwrap();
+    vec.insert(0, 1).unwrap();
+    vec.insert(2, 3).unwrap();
+    assert_eq!(&*vec, &[1, 2, 3]);
+
+    // Out of bounds, like `Vec`
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _ = vec.insert(usize::MAX, 9);
+    }));
+    assert!(res.is_err());
+    assert_eq!(&*vec, &[1, 2, 3]);
+    assert_eq!(
+        vec.resize(usize::MAX, 0),
+        Err(FixedVecError::CapacityExceeded {
+            requested: usize::MAX,
+            capacity: 4
+        })
+    );
+    // Known to be too long up front: nothing is pushed
+    assert_eq!(
+        vec.extend(0..u32::MAX),
+        Err(FixedVecError::CapacityExceeded {
+            requested: 3 + u32::MAX as usize,
+            capacity: 4
+        })
+    );
+    // Only found out while pushing
+    let mut iter = (10..20).filter(|_| true);
+    assert!(vec.extend(&mut iter).is_err());
+    assert_eq!(&*vec, &[1, 2, 3, 10]);
+    assert_eq!(iter.next(), Some(12));
+    assert_eq!(vec.insert(4, 9), Err(9));
+
+    vec.resize(2, 0).unwrap();
+    assert_eq!(&*vec, &[1, 2]);
+    vec.resize(4, 7).unwrap();
+    assert_eq!(&*vec, &[1, 2, 7, 7]);
+}
+
+#[test]
+fn spare_capacity_fill() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec = guard.alloc_fixed_vec::<u8>(8).unwrap();
+    vec.push(1).unwrap();
+    let spare = vec.spare_capacity_mut();
+    assert_eq!(spare.len(), 7);
+    for (i, slot) in spare.iter_mut().take(3).enumerate() {
+        slot.write(10 + i as u8);
+    }
+    unsafe { vec.set_len(4) };
+    assert_eq!(&*vec, &[1, 10, 11, 12]);
+    assert_eq!(vec.spare_capacity_mut().len(), 4);
+}
+
+#[test]
+fn clone_keeps_capacity() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut bytes = guard.alloc_fixed_vec::<u8>(16).unwrap();
+    bytes.extend([1, 2, 3]).unwrap();
+    let mut copy = bytes.try_clone_copy(&mut guard).unwrap();
+    assert_eq!(&*copy, &[1, 2, 3]);
+    assert_eq!(copy.capacity(), 16);
+    copy.push(4).unwrap();
+    assert_eq!(&*bytes, &[1, 2, 3]);
+
+    let mut strings = guard.alloc_fixed_vec::<String>(4).unwrap();
+    strings.push(String::from("a")).unwrap();
+    let cloned = strings.try_clone(&mut guard).unwrap();
+    assert_eq!(&*cloned, &["a"]);
+    assert_eq!(cloned.capacity(), 4);
+}
+
+#[test]
+fn subslice_checks_len() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec = guard.alloc_fixed_vec::<u8>(8).unwrap();
+    vec.extend([1, 2, 3]).unwrap();
+    assert_eq!(vec.subslice(1..), Ok(&[2, 3][..]));
+    // Within the capacity, but past the initialized elements
+    assert!(vec.subslice(..4).is_err());
+    vec.subslice_mut(..1).unwrap()[0] = 10;
+    assert_eq!(&*vec, &[10, 2, 3]);
+}
+
+#[test]
+fn raw_pointers() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec = guard.alloc_fixed_vec::<u16>(4).unwrap();
+    vec.extend([1, 2]).unwrap();
+    assert_eq!(vec.as_ptr(), vec.first().unwrap() as *const u16);
+
+    let ptr = vec.as_mut_ptr();
+    unsafe { ptr.add(1).write(20) };
+    let (ptr, len) = (vec.as_ptr(), vec.len());
+    assert_eq!(unsafe { core::slice::from_raw_parts(ptr, len) }, &[1, 20]);
+}
+
+#[test]
+fn push_all_counts() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec = guard.alloc_fixed_vec::<u32>(4).unwrap();
+    assert_eq!(vec.push_all([1, 2]), 2);
+
+    let mut iter = 3..10;
+    assert_eq!(vec.push_all(iter.by_ref()), 2);
+    assert_eq!(&*vec, &[1, 2, 3, 4]);
+    // The overflowing item was left in the iterator
+    assert_eq!(iter.next(), Some(5));
+
+    assert_eq!(vec.push_all(iter), 0);
+}
+
+#[test]
+fn rotate_view_wraps_within_len() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec = guard.alloc_fixed_vec::<u32>(8).unwrap();
+    assert_eq!(vec.rotate_view(3).count(), 0);
+
+    // Only the first 5 of the 8 slots are initialized
+    vec.push_all([0, 1, 2, 3, 4]);
+    let rotated = |start| vec.rotate_view(start).copied().collect::<Vec<_>>();
+    assert_eq!(rotated(0), [0, 1, 2, 3, 4]);
+    assert_eq!(rotated(2), [2, 3, 4, 0, 1]);
+    assert_eq!(rotated(4), [4, 0, 1, 2, 3]);
+    // Positions past the end wrap around
+    assert_eq!(rotated(5), [0, 1, 2, 3, 4]);
+    assert_eq!(rotated(12), [2, 3, 4, 0, 1]);
+}
+
+#[test]
+fn push_with() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec: HeapFixedVec<u32> = guard.alloc_fixed_vec(2).unwrap();
+    vec.push_with(|| 1).unwrap();
+
+    // A panicking initializer leaves the vec as it was
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _ = vec.push_with(|| panic!("initializer panicked"));
+    }));
+    assert!(res.is_err());
+    assert_eq!(&*vec, &[1]);
+
+    vec.push_with(|| 2).unwrap();
+    // The initializer isn't called once the vec is full
+    assert_eq!(
+        vec.push_with(|| unreachable!("called on a full vec")),
+        Err(FixedVecError::CapacityExceeded {
+            requested: 3,
+            capacity: 2
+        })
+    );
+    assert_eq!(&*vec, &[1, 2]);
+}
+
+#[test]
+fn sort_unstable_by_key() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec: HeapFixedVec<(u8, char)> = guard.alloc_fixed_vec(8).unwrap();
+    for item in [(3, 'c'), (1, 'a'), (2, 'b')] {
+        vec.push(item).unwrap();
+    }
+    vec.sort_unstable_by_key(|&(k, _)| k);
+    assert_eq!(&*vec, &[(1, 'a'), (2, 'b'), (3, 'c')]);
+    assert_eq!(vec.capacity(), 8);
+}
+
+#[test]
+fn pop_truncate_clear() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec: HeapFixedVec<String> = guard.alloc_fixed_vec(4).unwrap();
+    assert_eq!(vec.pop(), None);
+    for s in ["a", "b", "c", "d"] {
+        vec.push(String::from(s)).unwrap();
+    }
+    assert_eq!(vec.pop().as_deref(), Some("d"));
+
+    // Growing is a no-op
+    vec.truncate(5);
+    assert_eq!(vec.len(), 3);
+    vec.truncate(1);
+    assert_eq!(&*vec, &["a"]);
+
+    vec.clear();
+    assert!(vec.is_empty());
+    assert_eq!(vec.capacity(), 4);
+
+    // Reusable as a scratch buffer
+    vec.push(String::from("e")).unwrap();
+    assert_eq!(&*vec, &["e"]);
+}
+
+#[test]
+fn remove_and_swap_remove() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec: HeapFixedVec<String> = guard.alloc_fixed_vec(8).unwrap();
+    for s in ["a", "b", "c", "d", "e"] {
+        vec.push(String::from(s)).unwrap();
+    }
+
+    assert_eq!(vec.remove(1), "b");
+    assert_eq!(&*vec, &["a", "c", "d", "e"]);
+    assert_eq!(vec.remove(3), "e");
+    assert_eq!(vec.swap_remove(0), "a");
+    assert_eq!(&*vec, &["d", "c"]);
+
+    // Past the initialized elements, though within capacity
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| vec.remove(2)));
+    assert!(res.is_err());
+    assert_eq!(&*vec, &["d", "c"]);
+}
+
+#[test]
+fn extend_from_slice() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec: HeapFixedVec<u8> = guard.alloc_fixed_vec(6).unwrap();
+    vec.push(0).unwrap();
+    vec.extend_from_slice(&[1, 2, 3]).unwrap();
+    assert_eq!(&*vec, &[0, 1, 2, 3]);
+
+    // All or nothing
+    assert_eq!(vec.extend_from_slice(&[4, 5, 6]), Err(2));
+    assert_eq!(&*vec, &[0, 1, 2, 3]);
+    vec.extend_from_slice(&[4, 5]).unwrap();
+    assert_eq!(&*vec, &[0, 1, 2, 3, 4, 5]);
+    vec.extend_from_slice(&[]).unwrap();
+}
+
+#[test]
+fn full_vec_into_array() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut vec: HeapFixedVec<u32> = guard.alloc_fixed_vec(3).unwrap();
+    vec.push(1).unwrap();
+    vec.push(2).unwrap();
+
+    // Not full yet, so handed back unchanged
+    let mut vec = vec.into_array().unwrap_err();
+    assert_eq!(&*vec, &[1, 2]);
+
+    vec.push(3).unwrap();
+    let addr = vec.as_ptr();
+    let arr = vec.into_array().unwrap();
+    assert_eq!(&*arr, &[1, 2, 3]);
+    assert_eq!(arr.as_ptr(), addr);
+
+    assert!(HeapFixedVec::<u32>::new_lazy(2).into_array().is_err());
+}
+
+#[test]
+fn empty_fixed_vec() {
+    static mut EMPTY: HeapFixedVec<u8> = HeapFixedVec::new_empty();
+
+    let empty = unsafe { &mut *std::ptr::addr_of_mut!(EMPTY) };
+    assert!(!empty.is_allocated());
+    assert_eq!(empty.capacity(), 0);
+    assert_eq!(empty.push(1), Err(1));
+    assert!(empty.is_empty());
+
+    let (ptr, len, cap) = HeapFixedVec::<u32>::new_empty().into_raw_parts();
+    let vec = unsafe { HeapFixedVec::<u32>::from_raw_parts(ptr, len, cap) };
+    assert!(!vec.is_allocated());
+}
+
+#[test]
+fn arc_clone_into_fixed_vec() {
+    let heap = new_heap();
+    let arc = now(heap.allocate_arc(5u32));
+
+    let clones = now(HeapArc::clone_into_fixed_vec(&arc, heap, 3));
+    assert_eq!(clones.len(), 3);
+    assert_eq!(HeapArc::strong_count(&arc), 4);
+    assert!(clones.iter().all(|c| HeapArc::ptr_eq(c, &arc)));
+
+    drop(clones);
+    assert_eq!(HeapArc::strong_count(&arc), 1);
+}
@@ -0,0 +1,99 @@
+#![cfg(feature = "heap-accounting")]
+
+use std::alloc::Layout;
+
+use mnemos_alloc::heap::{deallocate_raw, AHeap, HeapBuilder, HeapGuard};
+
+#[test]
+fn used_and_free_bytes() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    let heap = unsafe { heap.as_ref() };
+    let total = heap.free_bytes();
+    assert!(total > 15 * 1024);
+    assert_eq!((heap.used_bytes(), heap.high_water_mark()), (0, 0));
+
+    // Whole blocks are counted, including the header
+    let bx = guard.alloc_box([0u8; 100]).map_err(drop).unwrap();
+    let arr = guard.alloc_box_array_with(|| 0u32, 64).unwrap();
+    let used = bx.layout().size() + arr.layout().size();
+    assert!(used > 100 + 256);
+    assert_eq!(heap.used_bytes(), used);
+    assert_eq!(heap.free_bytes(), total - used);
+
+    drop(guard);
+
+    drop(bx);
+    assert_eq!(heap.used_bytes(), arr.layout().size());
+    assert_eq!(heap.high_water_mark(), used);
+
+    drop(arr);
+    assert_eq!((heap.used_bytes(), heap.free_bytes()), (0, total));
+    assert_eq!(heap.high_water_mark(), used);
+}
+
+#[test]
+fn rounding_and_regions_are_counted() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, mut guard) = unsafe {
+        HeapBuilder::new()
+            .min_alloc_granularity(64)
+            .bootstrap(bufptr.cast::<u8>(), SIZE)
+            .unwrap()
+    };
+    let heap = unsafe { heap.as_ref() };
+    let total = heap.free_bytes();
+
+    let bx = guard.alloc_box(1u8).map_err(drop).unwrap();
+    assert_eq!(heap.used_bytes(), 64);
+
+    let extra = Box::into_raw(Box::new([0u8; SIZE]));
+    unsafe {
+        guard
+            .add_region(std::ptr::NonNull::new(extra.cast::<u8>()).unwrap(), SIZE)
+            .unwrap()
+    };
+    let grown = heap.free_bytes() + heap.used_bytes();
+    assert!(grown > total + 15 * 1024);
+    drop(guard);
+
+    drop(bx);
+    assert_eq!(heap.free_bytes(), grown);
+}
+
+#[test]
+fn odd_sizes_match_the_allocator() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    let heap = unsafe { heap.as_ref() };
+    // A failed allocation reports the allocator's own count of free bytes
+    let backend_free = |guard: &mut HeapGuard| {
+        guard
+            .alloc_raw(Layout::from_size_align(2 * SIZE, 1).unwrap())
+            .unwrap_err()
+            .free_bytes()
+            .unwrap()
+    };
+    assert_eq!(backend_free(&mut guard), heap.free_bytes());
+
+    let arr = guard.alloc_box_array_with(|| 0u8, 5).unwrap();
+    let raws = [1, 3, 7, 13].map(|size| {
+        guard
+            .alloc_raw(Layout::from_size_align(size, 1).unwrap())
+            .unwrap()
+    });
+    assert_eq!(backend_free(&mut guard), heap.free_bytes());
+
+    drop(arr);
+    for (ptr, size) in raws.into_iter().zip([1, 3, 7, 13]) {
+        unsafe { deallocate_raw(ptr, Layout::from_size_align(size, 1).unwrap()) };
+    }
+    assert_eq!(backend_free(&mut guard), heap.free_bytes());
+    assert_eq!(heap.used_bytes(), 0);
+}
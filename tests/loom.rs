@@ -0,0 +1,59 @@
+#![cfg(loom)]
+//! Model checks the reference counting of `HeapArc`.
+//!
+//! Run with `RUSTFLAGS="--cfg loom" cargo test --release --features loom
+//! --test loom`.
+
+use loom::cell::UnsafeCell;
+use loom::thread;
+
+use mnemos_alloc::heap::AHeap;
+
+/// Written to by each thread, right before it drops its arc.
+struct Writes {
+    main: UnsafeCell<usize>,
+    spawned: UnsafeCell<usize>,
+}
+
+// SAFETY: Each cell is only written by one thread, and only read once both
+// threads are done with it, when the contents are dropped.
+unsafe impl Sync for Writes {}
+
+impl Drop for Writes {
+    fn drop(&mut self) {
+        // Whichever thread drops the contents must see both writes, or loom
+        // reports a causality violation
+        let main = self.main.with(|ptr| unsafe { *ptr });
+        let spawned = self.spawned.with(|ptr| unsafe { *ptr });
+        assert_eq!((main, spawned), (1, 2));
+    }
+}
+
+/// Two threads drop clones of the same arc at once. Both threads' uses of
+/// the contents must happen before they are dropped.
+#[test]
+fn concurrent_drops() {
+    const SIZE: usize = 4 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    drop(guard);
+    let heap: &'static AHeap = unsafe { heap.as_ref() };
+
+    loom::model(move || {
+        let writes = Writes {
+            main: UnsafeCell::new(0),
+            spawned: UnsafeCell::new(0),
+        };
+        let arc = heap.lock().unwrap().alloc_arc(writes).ok().unwrap();
+        let other = arc.clone();
+
+        let t = thread::spawn(move || {
+            other.spawned.with_mut(|ptr| unsafe { *ptr = 2 });
+            drop(other);
+        });
+        arc.main.with_mut(|ptr| unsafe { *ptr = 1 });
+        drop(arc);
+        t.join().unwrap();
+    });
+}
@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use mnemos_alloc::{heap::AHeap, once::HeapOnce};
+
+#[test]
+fn set_once_take_once() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let once = HeapOnce::try_new(&mut guard).unwrap();
+    let other = once.clone();
+    assert!(other.take().is_none());
+
+    once.set(String::from("hello")).unwrap();
+    assert!(other.is_full());
+    assert_eq!(once.set(String::from("again")), Err(String::from("again")));
+
+    assert_eq!(other.take().as_deref(), Some("hello"));
+    assert!(once.take().is_none());
+    // Still can't set it again after it was taken
+    assert!(once.set(String::new()).is_err());
+
+    // A value that is never taken is dropped with the last handle
+    let value = Arc::new(());
+    let once = HeapOnce::try_new(&mut guard).unwrap();
+    once.set(value.clone()).unwrap();
+    drop(once);
+    assert_eq!(Arc::strong_count(&value), 1);
+}
+
+#[test]
+fn handoff_between_threads() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    for i in 0..100u32 {
+        let once = HeapOnce::try_new(&mut guard).unwrap();
+        let tx = once.clone();
+        let setter = std::thread::spawn(move || tx.set(vec![i; 8]).unwrap());
+        let value = loop {
+            if let Some(value) = once.take() {
+                break value;
+            }
+            std::thread::yield_now();
+        };
+        assert_eq!(value, vec![i; 8]);
+        setter.join().unwrap();
+    }
+}
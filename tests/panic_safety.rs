@@ -0,0 +1,189 @@
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Waker};
+
+use mnemos_alloc::heap::AHeap;
+
+const SIZE: usize = 16 * 1024;
+
+/// Poll a future that is expected to complete immediately
+fn now<F: Future>(fut: F) -> F::Output {
+    let mut cx = Context::from_waker(Waker::noop());
+    match pin!(fut).poll(&mut cx) {
+        Poll::Ready(t) => t,
+        Poll::Pending => panic!("future was not immediately ready"),
+    }
+}
+
+fn new_heap() -> &'static AHeap {
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    drop(guard);
+    unsafe { heap.as_ref() }
+}
+
+/// Counts the live instances, to check that the ones created before a panic
+/// are dropped.
+struct Counted;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+impl Counted {
+    fn new() -> Self {
+        LIVE.fetch_add(1, Ordering::SeqCst);
+        Counted
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        LIVE.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Returns a closure that makes a few `Counted`s, and then panics
+fn panics_after(n: usize) -> impl Fn() -> Counted {
+    let made = AtomicUsize::new(0);
+    move || {
+        if made.fetch_add(1, Ordering::SeqCst) == n {
+            panic!("initializer panicked");
+        }
+        Counted::new()
+    }
+}
+
+/// Run `f`, which is expected to panic, and check that the heap is back in
+/// exactly the state it was in beforehand.
+fn assert_panic_safe(heap: &'static AHeap, f: impl FnOnce()) {
+    heap.poll();
+    let (allocs, frees) = (heap.alloc_count(), heap.free_count());
+
+    assert!(catch_unwind(AssertUnwindSafe(f)).is_err());
+    // Frees made while the heap was locked are deferred until now
+    heap.poll();
+
+    assert_eq!(LIVE.load(Ordering::SeqCst), 0);
+    assert_eq!(
+        heap.alloc_count() - allocs,
+        heap.free_count() - frees,
+        "the allocation was leaked"
+    );
+    // The heap is still usable, and all of it is available
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+// NOTE: These are all in one test, as they share the `LIVE` count.
+#[test]
+fn panicking_initializers() {
+    let heap = new_heap();
+
+    assert_panic_safe(heap, || {
+        let mut guard = heap.lock().unwrap();
+        let _ = guard.alloc_box_array_with(panics_after(3), 8);
+    });
+    assert_panic_safe(heap, || {
+        let mut guard = heap.lock().unwrap();
+        let _ = guard.alloc_box_array_aligned_with(panics_after(3), 8, 64);
+    });
+    assert_panic_safe(heap, || {
+        let mut guard = heap.lock().unwrap();
+        let _ = guard.alloc_arc_array_with(panics_after(3), 8);
+    });
+    assert_panic_safe(heap, || {
+        let mut guard = heap.lock().unwrap();
+        let make = panics_after(3);
+        let _ = guard.alloc_array_from_exact((0..8).map(|_| make()));
+    });
+    assert_panic_safe(heap, || {
+        let make = panics_after(3);
+        let _ = now(heap.allocate_array_from_iter((0..8).map(|_| make()), 8));
+    });
+    assert_panic_safe(heap, || {
+        let mut guard = heap.lock().unwrap();
+        let make = panics_after(3);
+        let _ = guard
+            .try_alloc_array_from_fn::<_, mnemos_alloc::heap::AllocError, _>(8, |_| Ok(make()));
+    });
+    assert_panic_safe(heap, || {
+        let _ = now(heap.allocate_array_with(panics_after(3), 8));
+    });
+    assert_panic_safe(heap, || {
+        let boxed = now(heap.allocate_array_const::<Counted, 8>());
+        let make = panics_after(3);
+        let _ = boxed.init_with(|_| make());
+    });
+    assert_panic_safe(heap, || {
+        let mut vec = now(heap.allocate_fixed_vec(8));
+        let make = panics_after(3);
+        for _ in 0..8 {
+            let _ = vec.push_with(&make);
+        }
+    });
+    assert_panic_safe(heap, || {
+        let arr = now(heap.allocate_array_with(Counted::new, 8));
+        let mut seen = 0;
+        let _ = now(arr.try_map(heap, |c| {
+            seen += 1;
+            if seen == 4 {
+                panic!("initializer panicked");
+            }
+            Ok::<_, ()>(c)
+        }));
+    });
+}
+
+#[test]
+fn panicking_element_drop() {
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Panics when the third of them is dropped
+    struct Bomb;
+
+    impl Drop for Bomb {
+        fn drop(&mut self) {
+            if DROPS.fetch_add(1, Ordering::SeqCst) == 2 {
+                panic!("element drop panicked");
+            }
+        }
+    }
+
+    let heap = new_heap();
+    let check = |f: &mut dyn FnMut()| {
+        DROPS.store(0, Ordering::SeqCst);
+        heap.poll();
+        let (allocs, frees) = (heap.alloc_count(), heap.free_count());
+        assert!(catch_unwind(AssertUnwindSafe(f)).is_err());
+        // Every element was dropped, and the array was freed
+        assert_eq!(DROPS.load(Ordering::SeqCst), 5);
+        heap.poll();
+        assert_eq!(heap.alloc_count() - allocs, heap.free_count() - frees);
+    };
+
+    check(&mut || drop(now(heap.allocate_array_with(|| Bomb, 5))));
+    check(&mut || {
+        let mut guard = heap.lock().unwrap();
+        let arr = guard.alloc_arc_array_with(|| Bomb, 5).unwrap();
+        drop(guard);
+        drop(arr);
+    });
+    check(&mut || {
+        let mut vec = now(heap.allocate_fixed_vec(8));
+        for _ in 0..5 {
+            let _ = vec.push(Bomb);
+        }
+        drop(vec);
+    });
+    check(&mut || {
+        let mut vec = now(heap.allocate_fixed_vec(8));
+        for _ in 0..5 {
+            let _ = vec.push(Bomb);
+        }
+        // The dropped elements must no longer be counted, or unwinding
+        // would drop them again
+        vec.truncate(1);
+    });
+    check(&mut || now(heap.allocate_array_with(|| Bomb, 5)).drop_in_reverse());
+}
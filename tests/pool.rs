@@ -0,0 +1,93 @@
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+use mnemos_alloc::{
+    heap::AHeap,
+    pool::{BoxPool, PoolBox, PoolStats},
+};
+
+const SIZE: usize = 16 * 1024;
+
+/// Poll a future that is expected to complete immediately
+fn now<F: Future>(fut: F) -> F::Output {
+    let mut cx = Context::from_waker(Waker::noop());
+    match pin!(fut).poll(&mut cx) {
+        Poll::Ready(t) => t,
+        Poll::Pending => panic!("future was not immediately ready"),
+    }
+}
+
+#[test]
+fn recycles_boxes() {
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    drop(guard);
+    let heap: &'static AHeap = unsafe { heap.as_ref() };
+
+    let pool = BoxPool::new(heap, 2);
+    let a = now(pool.alloc(String::from("a")));
+    let b = now(pool.alloc(String::from("b")));
+    let c = now(pool.alloc(String::from("c")));
+    assert_eq!(pool.stats(), PoolStats { hits: 0, misses: 3 });
+    let addr_a = &*a as *const String;
+
+    // Only two of the three fit in the pool
+    drop(a);
+    drop(b);
+    let frees = heap.free_count();
+    drop(c);
+    assert_eq!(heap.free_count(), frees + 1);
+    assert_eq!(pool.len(), 2);
+
+    // Reusing doesn't touch the heap
+    let allocs = heap.alloc_count();
+    let mut guard = heap.lock().unwrap();
+    let d = pool.try_alloc(&mut guard, String::from("d")).unwrap();
+    let mut e = now(pool.alloc(String::from("e")));
+    drop(guard);
+    assert_eq!(heap.alloc_count(), allocs);
+    assert_eq!(pool.stats(), PoolStats { hits: 2, misses: 3 });
+    assert!(pool.is_empty());
+    assert!(std::ptr::eq(&*d, addr_a) || std::ptr::eq(&*e, addr_a));
+    e.push('!');
+    assert_eq!(&*e, "e!");
+
+    // Detached boxes go back to the heap
+    let frees = heap.free_count();
+    drop(PoolBox::into_box(d));
+    assert_eq!(heap.free_count(), frees + 1);
+    assert_eq!(pool.len(), 0);
+
+    // Dropping the pool frees what's left in it
+    drop(e);
+    drop(pool);
+    assert_eq!(heap.alloc_count(), heap.free_count());
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn try_alloc_rejects_other_heap() {
+    let new_heap = || {
+        let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+        let (heap, guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+        drop(guard);
+        let heap: &'static AHeap = unsafe { heap.as_ref() };
+        heap
+    };
+    let (heap, other) = (new_heap(), new_heap());
+
+    // Its allocation would later be freed into the wrong heap
+    let pool = BoxPool::new(heap, 2);
+    let mut guard = other.lock().unwrap();
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let _ = pool.try_alloc(&mut guard, 1u32);
+    }));
+    assert!(res.is_err());
+    drop(guard);
+
+    let mut guard = heap.lock().unwrap();
+    assert_eq!(*pool.try_alloc(&mut guard, 2u32).unwrap(), 2);
+}
@@ -0,0 +1,106 @@
+use mnemos_alloc::{
+    containers::HeapArc,
+    heap::{AHeap, AllocErrorKind},
+    registry::{HeapArcRegistry, WeakList},
+};
+
+#[test]
+fn interns_while_alive() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut reg = HeapArcRegistry::try_new(&mut guard, 2).unwrap();
+    let a = reg.try_get_or_insert(&mut guard, "a", || 1u32).unwrap();
+    let a2 = reg
+        .try_get_or_insert(&mut guard, "a", || unreachable!("already live"))
+        .unwrap();
+    assert!(HeapArc::ptr_eq(&a, &a2));
+    assert!(HeapArc::ptr_eq(&reg.get(&"a").unwrap(), &a));
+
+    let b = reg.try_get_or_insert(&mut guard, "b", || 2).unwrap();
+    // Full of live entries
+    assert_eq!(
+        reg.try_get_or_insert(&mut guard, "c", || 3).err(),
+        Some(("c", None))
+    );
+
+    // Once the last arc is gone, the entry is replaced
+    drop(a);
+    drop(a2);
+    assert!(reg.get(&"a").is_none());
+    let a3 = reg.try_get_or_insert(&mut guard, "a", || 4).unwrap();
+    assert_eq!(*a3, 4);
+
+    // ...or its slot reused by another key
+    drop(a3);
+    let c = reg.try_get_or_insert(&mut guard, "c", || 3).unwrap();
+    assert_eq!(*c, 3);
+    assert_eq!(*reg.get(&"b").unwrap(), 2);
+
+    drop(b);
+    drop(c);
+    assert_eq!(reg.prune(), 2);
+    drop(reg);
+
+    // Everything was freed
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn reports_alloc_errors() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut reg = HeapArcRegistry::try_new(&mut guard, 1).unwrap();
+    let (key, err) = reg
+        .try_get_or_insert(&mut guard, "big", || [0u8; SIZE])
+        .unwrap_err();
+    assert_eq!(key, "big");
+    assert_eq!(err.map(|err| err.kind()), Some(AllocErrorKind::TooLarge));
+}
+
+#[test]
+fn weak_list_prunes_dead() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let subs: Vec<HeapArc<u32>> = (0..4).map(|i| guard.alloc_arc(i).unwrap()).collect();
+    let mut list = WeakList::try_new(&mut guard, 4).unwrap();
+    for sub in &subs {
+        list.push(HeapArc::downgrade(sub)).unwrap();
+    }
+    let extra = guard.alloc_arc(4).unwrap();
+    assert!(list.push(HeapArc::downgrade(&extra)).is_err());
+
+    // Drop two adjacent subscribers, so that a dead entry is swapped into
+    // the place of a removed one
+    let mut subs = subs.into_iter();
+    let first = subs.next().unwrap();
+    drop(subs.next());
+    let rest: Vec<_> = subs.collect();
+    drop(rest);
+
+    let mut seen = Vec::new();
+    list.for_each_live(|arc| seen.push(**arc));
+    assert_eq!(seen, [0]);
+    assert_eq!(list.len(), 1);
+
+    // Dead entries make room when the list is full
+    list.push(HeapArc::downgrade(&extra)).unwrap();
+    drop(first);
+    let mut seen = Vec::new();
+    list.for_each_live(|arc| seen.push(**arc));
+    assert_eq!(seen, [4]);
+
+    drop(extra);
+    drop(list);
+
+    // Everything was freed
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
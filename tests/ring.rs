@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use mnemos_alloc::{
+    heap::{AHeap, AllocErrorKind},
+    ring::HeapRingBuffer,
+};
+
+const SIZE: usize = 16 * 1024;
+
+#[test]
+fn push_pop_wrapping() {
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut ring = HeapRingBuffer::try_new(&mut guard, 3).unwrap();
+    assert_eq!(ring.capacity(), 4);
+    assert!(ring.is_empty());
+    assert_eq!(ring.try_pop(), None);
+
+    for round in 0..3 {
+        for i in 0..4 {
+            ring.try_push(round * 10 + i).unwrap();
+        }
+        assert!(ring.is_full());
+        assert_eq!(ring.try_push(99), Err(99));
+        assert_eq!(ring.try_pop(), Some(round * 10));
+        ring.try_push(round * 10 + 4).unwrap();
+        let items: Vec<_> = core::iter::from_fn(|| ring.try_pop()).collect();
+        assert_eq!(items, (1..5).map(|i| round * 10 + i).collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn capacity_overflow() {
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let err = HeapRingBuffer::<u8>::try_new(&mut guard, usize::MAX).unwrap_err();
+    assert_eq!(err.kind(), AllocErrorKind::Overflow);
+}
+
+#[test]
+fn drops_remaining_items() {
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Tracked;
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut ring = HeapRingBuffer::try_new(&mut guard, 4).unwrap();
+    for _ in 0..3 {
+        assert!(ring.try_push(Tracked).is_ok());
+    }
+    drop(ring.try_pop());
+    assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+    drop(ring);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn split_across_threads() {
+    const COUNT: u32 = 10_000;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut ring = HeapRingBuffer::try_new(&mut guard, 8).unwrap();
+    let (mut tx, mut rx) = ring.split();
+    std::thread::scope(|s| {
+        s.spawn(move || {
+            for i in 0..COUNT {
+                let mut item = i;
+                while let Err(it) = tx.try_push(item) {
+                    item = it;
+                    std::thread::yield_now();
+                }
+            }
+        });
+        let mut next = 0;
+        while next < COUNT {
+            match rx.try_pop() {
+                Some(i) => {
+                    assert_eq!(i, next);
+                    next += 1;
+                }
+                None => std::thread::yield_now(),
+            }
+        }
+        assert!(rx.is_empty());
+    });
+}
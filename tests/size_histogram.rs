@@ -0,0 +1,40 @@
+#![cfg(feature = "size-histogram")]
+
+use core::alloc::Layout;
+
+use mnemos_alloc::heap::{deallocate_raw, AHeap};
+
+#[test]
+fn size_histogram() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    let heap = unsafe { heap.as_ref() };
+    assert_eq!(heap.size_histogram(), [0; AHeap::HISTOGRAM_BUCKETS]);
+
+    // Sizes include the header, so each of these lands in a single bucket,
+    // whatever the exact size of the header is.
+    let bx = guard.alloc_box([0u8; 1000]).map_err(drop).unwrap();
+    let bucket = bx.layout().size().ilog2() as usize;
+    assert_eq!(bucket, 9);
+    let layout = Layout::from_size_align(1500, 1).unwrap();
+    let raw = guard.alloc_raw(layout).unwrap();
+    let arr = guard.alloc_box_array_with(|| 0u8, 12 * 1024).unwrap();
+
+    let hist = heap.size_histogram();
+    assert_eq!(hist[9], 1);
+    assert_eq!(hist[10], 1);
+    assert_eq!(hist[13], 1);
+    assert_eq!(hist.iter().sum::<u32>(), 3);
+
+    // Frees aren't counted, or reset the counts
+    drop((bx, arr));
+    unsafe { deallocate_raw(raw, layout) };
+    drop(guard);
+    heap.poll();
+    assert_eq!(heap.size_histogram(), hist);
+
+    heap.reset_histogram();
+    assert_eq!(heap.size_histogram(), [0; AHeap::HISTOGRAM_BUCKETS]);
+}
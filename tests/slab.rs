@@ -0,0 +1,31 @@
+use mnemos_alloc::{heap::AHeap, slab::Slab};
+
+#[test]
+fn insert_remove_reuse() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut slab = Slab::try_new(&mut guard, 2).unwrap();
+    assert_eq!(slab.capacity(), 2);
+
+    let a = slab.insert(String::from("a")).unwrap();
+    let b = slab.insert(String::from("b")).unwrap();
+    assert_eq!(slab.insert(String::from("c")), Err(String::from("c")));
+    assert_eq!(slab.len(), 2);
+    assert_eq!(slab.get(a).unwrap(), "a");
+
+    slab.get_mut(b).unwrap().push('!');
+    assert_eq!(slab.remove(b).as_deref(), Some("b!"));
+    assert!(slab.get(b).is_none());
+    assert!(slab.remove(b).is_none());
+
+    // The freed slot is reused, but the old key stays invalid
+    let c = slab.insert(String::from("c")).unwrap();
+    assert_eq!(c.index(), b.index());
+    assert_ne!(c.generation(), b.generation());
+    assert!(slab.get(b).is_none());
+    assert_eq!(slab.get(c).unwrap(), "c");
+    assert!(slab.contains(a));
+}
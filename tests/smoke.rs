@@ -1,10 +1,17 @@
+use std::future::Future;
+use std::pin::pin;
 use std::ptr::{addr_of_mut, NonNull};
+use std::task::{Context, Poll, Waker};
 use std::{alloc::Layout, ops::Deref};
 
 use mnemos_alloc::heap::deallocate_raw;
 use mnemos_alloc::{
-    containers::{HeapArray, HeapBox},
-    heap::AHeap,
+    containers::{
+        HeapArc, HeapArcArray, HeapArray, HeapBox, HeapDynBox, HeapFixedVec, HeapVec,
+        IterLengthError,
+    },
+    heap::{AHeap, AllocErrorKind},
+    heap_box_dyn,
 };
 
 #[derive(Debug, Eq, PartialEq)]
@@ -14,6 +21,24 @@ struct Demo {
     three: [u16; 7],
 }
 
+/// Poll a future that is expected to complete immediately
+fn now<F: Future>(fut: F) -> F::Output {
+    let mut cx = Context::from_waker(Waker::noop());
+    match pin!(fut).poll(&mut cx) {
+        Poll::Ready(t) => t,
+        Poll::Pending => panic!("future was not immediately ready"),
+    }
+}
+
+fn new_heap() -> &'static AHeap {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    drop(guard);
+    unsafe { heap.as_ref() }
+}
+
 #[test]
 fn basic() {
     const SIZE: usize = 16 * 1024;
@@ -188,4 +213,1516 @@ fn allocating_futures_are_send() {
     assert_send(heap.allocate_arc(1));
     assert_send(heap.allocate_array_with(|| 1, 1));
     assert_send(heap.allocate(1));
-}
\ No newline at end of file
+}
+
+#[test]
+fn alloc_error_kinds() {
+    use mnemos_alloc::heap::AllocErrorKind;
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    // Larger than the whole heap
+    let err = guard
+        .alloc_raw(Layout::array::<u8>(SIZE * 2).unwrap())
+        .unwrap_err();
+    assert_eq!(err.kind(), AllocErrorKind::TooLarge);
+    assert!(err.layout().size() > SIZE * 2);
+
+    // Fits in the heap, but not while it's mostly full
+    let big = guard
+        .alloc_raw(Layout::array::<u8>(SIZE / 2).unwrap())
+        .unwrap();
+    let err = guard
+        .alloc_raw(Layout::array::<u8>(SIZE / 2).unwrap())
+        .unwrap_err();
+    assert_eq!(err.kind(), AllocErrorKind::OutOfMemory);
+    assert!(err.free_bytes().unwrap() < SIZE / 2);
+
+    // Overflows the size calculation
+    let err = guard
+        .alloc_box_array_with(|| 0u64, usize::MAX / 4)
+        .unwrap_err();
+    assert_eq!(err.kind(), AllocErrorKind::Overflow);
+
+    unsafe {
+        deallocate_raw(big, Layout::array::<u8>(SIZE / 2).unwrap());
+    }
+}
+
+#[test]
+fn box_clone_from_value() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut target = guard.alloc_box([1u32; 4]).map_err(drop).unwrap();
+    let addr: *const [u32; 4] = &*target;
+
+    target.clone_from_value(&[7u32; 4]);
+    assert_eq!(*target, [7u32; 4]);
+    assert_eq!(addr, &*target as *const _);
+}
+
+#[test]
+fn aligned_array() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    for _ in 0..4 {
+        let mut arrs = Vec::new();
+        for align in [1, 8, 32, 64, 256] {
+            let mut arr: HeapArray<u8> = guard
+                .alloc_box_array_aligned_with(|| 0xA5, 100, align)
+                .unwrap();
+            assert_eq!(arr.as_ptr() as usize % align, 0);
+            assert_eq!(arr.len(), 100);
+            assert!(arr.iter().all(|b| *b == 0xA5));
+            arr[99] = 0;
+            arrs.push(arr);
+        }
+        // Dropping them must return everything to the heap, otherwise we'll
+        // eventually run out.
+        drop(arrs);
+    }
+
+    let err = guard
+        .alloc_box_array_aligned_with(|| 0u8, 16, 3)
+        .unwrap_err();
+    assert_eq!(err.kind(), mnemos_alloc::heap::AllocErrorKind::InvalidAlign);
+    let err = guard.alloc_aligned_box_or_err(0u8, 3).unwrap_err().1;
+    assert_eq!(err.kind(), mnemos_alloc::heap::AllocErrorKind::InvalidAlign);
+}
+
+#[test]
+fn arc_map_ref() {
+    use mnemos_alloc::containers::HeapArc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Parent {
+        name: &'static str,
+        config: [u32; 4],
+    }
+
+    impl Drop for Parent {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let arc = guard
+        .alloc_arc(Parent {
+            name: "parent",
+            config: [1, 2, 3, 4],
+        })
+        .map_err(drop)
+        .unwrap();
+    let other = arc.clone();
+
+    let config = HeapArc::map_ref(arc, |p| &p.config);
+    let config2 = config.clone();
+    assert_eq!(*config, [1, 2, 3, 4]);
+    assert_eq!(other.name, "parent");
+
+    drop(other);
+    drop(config);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 0);
+    assert_eq!(config2[3], 4);
+    drop(config2);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn highly_aligned_box() {
+    #[repr(align(128))]
+    struct Aligned128([u8; 3]);
+
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    // Allocate something small first, so the heap cursor isn't already aligned
+    let _small = guard.alloc_box(1u8).map_err(drop).unwrap();
+
+    let boxes = (0..4)
+        .map(|_| {
+            guard
+                .alloc_box(Aligned128([1, 2, 3]))
+                .map_err(drop)
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    for b in boxes.iter() {
+        assert_eq!((&**b as *const Aligned128) as usize % 128, 0);
+        assert_eq!(b.0, [1, 2, 3]);
+    }
+
+    let arc = guard
+        .alloc_arc(Aligned128([4, 5, 6]))
+        .map_err(drop)
+        .unwrap();
+    assert_eq!((&*arc as *const Aligned128) as usize % 128, 0);
+}
+
+#[test]
+fn heap_box_is_unpin() {
+    use std::future::Future;
+    use std::marker::PhantomPinned;
+    use std::pin::{pin, Pin};
+    use std::task::{Context, Poll, Waker};
+
+    fn assert_unpin<T: Unpin>(_t: &T) {}
+
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let not_unpin = guard.alloc_box(PhantomPinned).map_err(drop).unwrap();
+    assert_unpin(&not_unpin);
+
+    // Async blocks are `!Unpin`
+    let fut = async {
+        let x = 5;
+        let r = &x;
+        std::future::ready(()).await;
+        *r + 1
+    };
+    let fut = guard.alloc_box(fut).map_err(drop).unwrap();
+    let fut: Pin<HeapBox<_>> = HeapBox::into_pin(fut);
+
+    // Move it somewhere else before polling
+    let mut moved = vec![fut];
+    let mut fut = moved.pop().unwrap();
+
+    let mut cx = Context::from_waker(Waker::noop());
+    assert_eq!(fut.as_mut().poll(&mut cx), Poll::Ready(6));
+
+    // `Unpin` futures in a `HeapBox` are futures themselves
+    let ready = guard
+        .alloc_box(std::future::ready(7))
+        .map_err(drop)
+        .unwrap();
+    assert_eq!(pin!(ready).poll(&mut cx), Poll::Ready(7));
+
+    // Futures of different types can be pinned behind one trait object
+    let fut = guard
+        .alloc_box(async {
+            std::future::ready(()).await;
+            8
+        })
+        .map_err(drop)
+        .unwrap();
+    let ready = guard
+        .alloc_box(std::future::ready(9))
+        .map_err(drop)
+        .unwrap();
+    let mut futs: Vec<Pin<HeapDynBox<dyn Future<Output = u32>>>> = vec![
+        HeapDynBox::into_pin(heap_box_dyn!(fut => dyn Future<Output = u32>)),
+        HeapDynBox::into_pin(heap_box_dyn!(ready => dyn Future<Output = u32>)),
+    ];
+    for (fut, expected) in futs.iter_mut().zip([8, 9]) {
+        assert_eq!(fut.as_mut().poll(&mut cx), Poll::Ready(expected));
+    }
+}
+
+#[test]
+fn array_pop_front() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let ctr = std::cell::Cell::new(0u32);
+    let mut arr = guard
+        .alloc_box_array_with(
+            || {
+                ctr.set(ctr.get() + 1);
+                std::rc::Rc::new(ctr.get())
+            },
+            4,
+        )
+        .unwrap();
+    let first = arr.pop_front().unwrap();
+    assert_eq!(*first, 1);
+    assert_eq!(arr.len(), 3);
+
+    let (second, rest) = arr.split_first_owned().unwrap();
+    assert_eq!(*second, 2);
+    assert_eq!(rest.iter().map(|r| **r).collect::<Vec<_>>(), [3, 4]);
+
+    // The remaining elements are still dropped, and the removed ones aren't
+    // dropped twice.
+    let weak = std::rc::Rc::downgrade(&rest[1]);
+    drop(rest);
+    assert!(weak.upgrade().is_none());
+    assert_eq!(std::rc::Rc::strong_count(&first), 1);
+
+    let empty: HeapArray<u8> = guard.alloc_box_array_with(|| 0, 1).unwrap();
+    let (_, empty) = empty.split_first_owned().unwrap();
+    assert!(empty.split_first_owned().is_none());
+}
+
+#[test]
+fn const_array_init_in_place() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const SIZE: usize = 64 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let arr: HeapBox<[u64; 4096]> = guard
+        .alloc_array_const::<u64, 4096>()
+        .unwrap()
+        .init_with(|i| i as u64 * 2);
+    assert_eq!(arr[0], 0);
+    assert_eq!(arr[4095], 8190);
+    drop(arr);
+
+    // A panic partway through drops only the initialized elements
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+    struct Counted;
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let uninit = guard.alloc_array_const::<Counted, 8>().unwrap();
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        uninit.init_with(|i| {
+            if i == 5 {
+                panic!("oh no");
+            }
+            Counted
+        })
+    }));
+    assert!(res.is_err());
+    assert_eq!(DROPS.load(Ordering::SeqCst), 5);
+
+    // And the memory was returned to the heap
+    let again = guard.alloc_array_const::<u64, 6144>().unwrap();
+    drop(again);
+}
+
+#[test]
+fn array_bytes_eq() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let arr: HeapArray<u32> = guard.alloc_box_array_with(|| 0xABCD, 64).unwrap();
+    let mut other = vec![0xABCDu32; 64];
+    assert!(arr.bytes_eq(&other));
+
+    other[63] = 0;
+    assert!(!arr.bytes_eq(&other));
+    assert!(!arr.bytes_eq(&other[..32]));
+
+    let arr: HeapArray<[u8; 3]> = guard.alloc_box_array_with(|| [1, 2, 3], 5).unwrap();
+    assert!(arr.bytes_eq(&[[1, 2, 3]; 5]));
+}
+
+#[test]
+fn box_token_roundtrip() {
+    use mnemos_alloc::containers::HeapBoxToken;
+    use std::rc::Rc;
+
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    assert_eq!(
+        core::mem::size_of::<HeapBoxToken<u64>>(),
+        core::mem::size_of::<*const u64>()
+    );
+
+    let bx = guard.alloc_box([1u32, 2, 3]).map_err(drop).unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    tx.send(bx.into_token()).unwrap();
+    let token = rx.recv().unwrap();
+    let bx = HeapBox::from_token(token);
+    assert_eq!(*bx, [1, 2, 3]);
+
+    // Dropping an unconsumed token drops the contents
+    let rc = Rc::new(());
+    let token = guard
+        .alloc_box(rc.clone())
+        .map_err(drop)
+        .unwrap()
+        .into_token();
+    assert_eq!(Rc::strong_count(&rc), 2);
+    drop(token);
+    assert_eq!(Rc::strong_count(&rc), 1);
+}
+
+#[test]
+fn heap_owns() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    let heap = unsafe { heap.as_ref() };
+
+    let raw = guard.alloc_raw(Layout::new::<[u8; 16]>()).unwrap();
+    assert!(heap.owns(raw.cast()));
+
+    let bx = guard.alloc_box(5u32).map_err(drop).unwrap();
+    assert!(heap.owns(NonNull::from(&*bx).cast()));
+
+    let foreign = 0u32;
+    assert!(!heap.owns(NonNull::from(&foreign).cast()));
+
+    unsafe { deallocate_raw(raw, Layout::new::<[u8; 16]>()) };
+}
+
+#[test]
+fn arc_array_get_mut() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut arr = guard.alloc_arc_array_with(|| 0u32, 4).unwrap();
+    // Fill it in while we are the only owner
+    for (i, x) in HeapArcArray::get_mut(&mut arr)
+        .unwrap()
+        .iter_mut()
+        .enumerate()
+    {
+        *x = i as u32;
+    }
+
+    let other = arr.clone();
+    assert!(HeapArcArray::get_mut(&mut arr).is_none());
+    assert_eq!(HeapArcArray::strong_count(&arr), 2);
+    assert!(HeapArcArray::ptr_eq(&arr, &other));
+    let view = HeapArcArray::slice(&other, 1..).unwrap();
+    assert_eq!(HeapArcArray::strong_count(&arr), 3);
+    drop(view);
+    assert_eq!(&*other, &[0, 1, 2, 3]);
+
+    drop(other);
+    assert!(HeapArcArray::get_mut(&mut arr).is_some());
+}
+
+#[test]
+fn array_from_exact() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let arr = guard
+        .alloc_array_from_exact((0..5).map(|i| i.to_string()))
+        .unwrap();
+    assert_eq!(&*arr, &["0", "1", "2", "3", "4"]);
+    drop(arr);
+
+    // A panicking iterator cleans up after itself
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        guard.alloc_array_from_exact((0..100).map(|i| {
+            assert!(i < 3);
+            vec![i; 16]
+        }))
+    }));
+    assert!(res.is_err());
+    drop(guard);
+
+    // Everything was freed: we can still allocate almost the whole heap
+    let mut guard = unsafe { heap.as_ref() }.lock().unwrap();
+    let big = guard.alloc_box_array_with(|| 0u8, SIZE - 512).unwrap();
+    drop(big);
+}
+
+#[test]
+fn array_windows_checked() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let arr = guard.alloc_array_from_exact([1u8, 2, 3]).unwrap();
+    assert!(arr.windows_checked(0).is_none());
+    let windows: Vec<&[u8]> = arr.windows_checked(2).unwrap().collect();
+    assert_eq!(windows, [&[1, 2][..], &[2, 3][..]]);
+    assert_eq!(arr.windows_checked(4).unwrap().count(), 0);
+}
+
+#[test]
+fn array_copy_from_slice() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut arr = guard.alloc_box_array_with(|| 0u16, 4).unwrap();
+    arr.copy_from_slice(&[1, 2, 3, 4]);
+    assert_eq!(&*arr, &[1, 2, 3, 4]);
+
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        arr.copy_from_slice(&[5, 6, 7]);
+    }));
+    assert!(res.is_err());
+    assert_eq!(&*arr, &[1, 2, 3, 4]);
+}
+
+#[test]
+fn array_split_into() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let arr = guard.alloc_array_from_exact([1u32, 2, 3, 4, 5]).unwrap();
+    let (head, tail) = arr.split_into(&mut guard, 2).map_err(drop).unwrap();
+    assert_eq!(&*head, &[1, 2]);
+    assert_eq!(&*tail, &[3, 4, 5]);
+    drop(head);
+    assert_eq!(&*tail, &[3, 4, 5]);
+
+    // If the new allocation fails, the array is handed back intact
+    let strings = guard
+        .alloc_array_from_exact([String::from("a"), String::from("b")])
+        .unwrap();
+    let mut hog = Vec::new();
+    while let Ok(b) = guard.alloc_box(0u8) {
+        hog.push(b);
+    }
+    let strings = strings.split_into(&mut guard, 1).map(drop).unwrap_err();
+    assert_eq!(&*strings, &["a", "b"]);
+
+    drop(hog);
+    let (left, right) = strings.split_into(&mut guard, 1).map_err(drop).unwrap();
+    assert_eq!(&*left, &["a"]);
+    assert_eq!(&*right, &["b"]);
+}
+
+#[test]
+fn compare_with_pointees() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let boxed = guard.alloc_box(5u32).map_err(drop).unwrap();
+    assert!(boxed == &5);
+    assert!(boxed != &6);
+
+    let arc = guard.alloc_arc(String::from("hi")).map_err(drop).unwrap();
+    assert_eq!(arc, &String::from("hi"));
+
+    let arr = guard.alloc_array_from_exact([1u8, 2, 3]).unwrap();
+    assert_eq!(arr, &[1, 2, 3][..]);
+    assert_ne!(arr, &[1, 2][..]);
+}
+
+#[test]
+fn raw_filled() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let layout = Layout::array::<u8>(100).unwrap();
+    let ptr = guard.alloc_raw_filled(layout, 0xFF).unwrap();
+    let bytes = unsafe { core::slice::from_raw_parts(ptr.cast::<u8>().as_ptr(), 100) };
+    assert!(bytes.iter().all(|b| *b == 0xFF));
+    unsafe { deallocate_raw(ptr, layout) };
+}
+
+#[test]
+fn cow_clones_on_write() {
+    use mnemos_alloc::containers::HeapCow;
+
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let original = [1u8, 2, 3];
+    let mut cow = HeapCow::Borrowed(&original);
+    assert!(cow.is_borrowed());
+    assert_eq!(*cow, [1, 2, 3]);
+
+    cow.to_mut(&mut guard).unwrap()[0] = 10;
+    assert!(cow.is_owned());
+    assert_eq!(*cow, [10, 2, 3]);
+    assert_eq!(original, [1, 2, 3]);
+
+    // Already owned, so no further allocation is needed
+    cow.to_mut(&mut guard).unwrap()[1] = 20;
+    assert_eq!(*cow, [10, 20, 3]);
+}
+
+#[test]
+fn array_builder() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut builder = guard.alloc_array_builder::<String>(2).unwrap();
+    builder.push(String::from("a")).unwrap();
+    let mut builder = builder.build().map(drop).unwrap_err();
+    builder.push(String::from("b")).unwrap();
+    assert!(builder.is_full());
+    assert_eq!(builder.push(String::from("c")), Err(String::from("c")));
+    let arr = builder.build().unwrap();
+    assert_eq!(&*arr, &["a", "b"]);
+
+    let mut builder = guard.alloc_array_builder::<u8>(4).unwrap();
+    builder.push(1).unwrap();
+    let arr = builder.build_partial();
+    assert_eq!(&*arr, &[1]);
+}
+
+#[test]
+fn array_builder_exact() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    let heap = unsafe { heap.as_ref() };
+
+    let mut builder = guard.alloc_array_builder::<String>(64).unwrap();
+    builder.push(String::from("a")).unwrap();
+    builder.push(String::from("b")).unwrap();
+    drop(guard);
+    let arr = now(builder.build_exact(heap));
+    assert_eq!(&*arr, &["a", "b"]);
+
+    // Only room for the two elements is kept
+    let mut guard = heap.lock().unwrap();
+    let exact = guard
+        .alloc_array_from_exact([String::new(), String::new()])
+        .unwrap();
+    assert_eq!(arr.layout(), exact.layout());
+    drop(exact);
+
+    // A full builder keeps its allocation
+    let mut builder = guard.alloc_array_builder::<u8>(1).unwrap();
+    builder.push(1).unwrap();
+    drop(guard);
+    assert_eq!(&*now(builder.build_exact(heap)), &[1]);
+}
+
+#[test]
+fn array_subslice() {
+    use mnemos_alloc::containers::OutOfBounds;
+
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut arr = guard.alloc_array_from_exact([1u8, 2, 3, 4]).unwrap();
+    assert_eq!(arr.subslice(1..3), Ok(&[2, 3][..]));
+    assert_eq!(arr.subslice(..=1), Ok(&[1, 2][..]));
+    assert_eq!(arr.subslice(4..), Ok(&[][..]));
+    assert_eq!(
+        arr.subslice(2..5),
+        Err(OutOfBounds {
+            start: 2,
+            end: 5,
+            len: 4
+        })
+    );
+    assert!(arr.subslice(core::ops::Range { start: 3, end: 2 }).is_err());
+    assert!(arr.subslice(..=usize::MAX).is_err());
+
+    arr.subslice_mut(2..).unwrap().fill(0);
+    assert_eq!(&*arr, &[1, 2, 0, 0]);
+}
+
+#[test]
+fn arc_array_uninit() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut arr = guard.alloc_arc_array_uninit::<String>(3).unwrap();
+    for (i, slot) in HeapArcArray::get_mut(&mut arr)
+        .unwrap()
+        .iter_mut()
+        .enumerate()
+    {
+        slot.write(i.to_string());
+    }
+    let arr = unsafe { HeapArcArray::assume_init(arr) };
+    let reader = arr.clone();
+    drop(arr);
+    assert_eq!(&*reader, &["0", "1", "2"]);
+}
+
+#[test]
+fn box_replace_with() {
+    const SIZE: usize = 16 * 1024;
+
+    enum State {
+        Idle,
+        Running(String),
+    }
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut state = guard.alloc_box(State::Idle).map_err(drop).unwrap();
+    let addr: *const State = &*state;
+    state.replace_with(|old| match old {
+        State::Idle => State::Running(String::from("job")),
+        running => running,
+    });
+    assert!(matches!(&*state, State::Running(job) if job == "job"));
+    assert_eq!(addr, &*state as *const State);
+}
+
+#[test]
+fn array_try_from_fn() {
+    use mnemos_alloc::heap::AllocError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const SIZE: usize = 16 * 1024;
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Handle(usize);
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum OpenError {
+        Alloc,
+        Busy(usize),
+    }
+
+    impl From<AllocError> for OpenError {
+        fn from(_: AllocError) -> Self {
+            OpenError::Alloc
+        }
+    }
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    let heap = unsafe { heap.as_ref() };
+
+    let arr = guard
+        .try_alloc_array_from_fn(3, |i| Ok::<_, OpenError>(Handle(i)))
+        .unwrap();
+    assert_eq!(arr.iter().map(|h| h.0).collect::<Vec<_>>(), [0, 1, 2]);
+    drop(arr);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 3);
+
+    // The prefix is dropped, and the allocation freed, on error
+    let frees = heap.free_count();
+    let res = guard.try_alloc_array_from_fn(4, |i| match i {
+        2 => Err(OpenError::Busy(i)),
+        i => Ok(Handle(i)),
+    });
+    assert_eq!(res.map(drop), Err(OpenError::Busy(2)));
+    assert_eq!(DROPS.load(Ordering::SeqCst), 5);
+    drop(guard);
+    heap.poll();
+    assert!(heap.free_count() > frees);
+
+    let mut guard = heap.lock().unwrap();
+    let res = guard.try_alloc_array_from_fn(SIZE, |i| Ok::<_, OpenError>(Handle(i)));
+    assert_eq!(res.map(drop), Err(OpenError::Alloc));
+}
+
+#[test]
+fn arc_array_slice() {
+    use mnemos_alloc::containers::HeapArcSlice;
+
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut arr = guard.alloc_arc_array_with(|| 0u32, 6).unwrap();
+    for (i, x) in HeapArcArray::get_mut(&mut arr)
+        .unwrap()
+        .iter_mut()
+        .enumerate()
+    {
+        *x = i as u32;
+    }
+
+    let mid = HeapArcArray::slice(&arr, 1..5).unwrap();
+    assert_eq!(&*mid, &[1, 2, 3, 4]);
+    let inner = HeapArcSlice::slice(&mid, 2..).unwrap();
+    assert_eq!(&*inner, &[3, 4]);
+    assert_eq!(HeapArcSlice::range(&inner), 3..5);
+
+    let err = HeapArcArray::slice(&arr, 4..7).unwrap_err();
+    assert_eq!((err.start, err.end, err.len), (4, 7, 6));
+    assert!(HeapArcSlice::slice(&mid, ..5).is_err());
+
+    // The views keep the array alive
+    assert!(HeapArcArray::get_mut(&mut arr).is_none());
+    drop(arr);
+    drop(mid);
+    assert_eq!(&*inner, &[3, 4]);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+fn from_leaked_checks_alignment() {
+    use mnemos_alloc::containers::HeapArc;
+
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let leaked = guard.alloc_box([0u32; 4]).map_err(drop).unwrap().leak();
+    let skewed = unsafe { NonNull::new_unchecked(leaked.as_ptr().cast::<u8>().add(1)) };
+
+    let res = std::panic::catch_unwind(|| unsafe { HeapBox::from_leaked(skewed.cast::<u32>()) });
+    assert!(res.is_err());
+    let res = std::panic::catch_unwind(|| unsafe { HeapArc::from_leaked(skewed.cast::<u32>()) });
+    assert!(res.is_err());
+
+    drop(unsafe { HeapBox::from_leaked(leaked) });
+}
+
+#[test]
+fn array_update_each() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut arr = guard
+        .alloc_array_from_exact(["a", "b", "c"].map(String::from))
+        .unwrap();
+    let start = arr.as_ptr();
+    arr.update_each(|mut s| {
+        s.push('!');
+        s
+    });
+    assert_eq!(&*arr, &["a!", "b!", "c!"]);
+    assert_eq!(arr.as_ptr(), start);
+}
+
+#[test]
+fn aligned_box() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut boxes = Vec::new();
+    for align in [1, 16, 64, 256] {
+        let mut b = guard.alloc_aligned_box(7u8, align).unwrap();
+        assert_eq!((&*b as *const u8).addr() % align, 0);
+        assert_eq!(b.layout().align(), align);
+        *b += 1;
+        assert_eq!(*b, 8);
+        boxes.push(b);
+    }
+    assert_eq!(guard.alloc_aligned_box(1u8, 3).map(drop), Err(1));
+
+    // Everything is freed with the layout it was allocated with
+    drop(boxes);
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn unsupported_align() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let heap = unsafe { heap.as_ref() };
+
+    // Any 16K region has a multiple of 8K in it
+    let max = heap.max_align();
+    assert!(max.is_power_of_two());
+    assert!(max >= 8 * 1024);
+
+    let too_big = max * 2;
+    let layout = Layout::from_size_align(8, too_big).unwrap();
+    let err = guard.alloc_raw(layout).unwrap_err();
+    assert_eq!(err.kind(), AllocErrorKind::UnsupportedAlign);
+    let err = guard
+        .alloc_box_array_aligned_with(|| 0u8, 8, too_big)
+        .unwrap_err();
+    assert_eq!(err.kind(), AllocErrorKind::UnsupportedAlign);
+    assert_eq!(guard.alloc_aligned_box(1u8, too_big).map(drop), Err(1));
+
+    // Nothing was allocated
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn box_same_allocation() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let a = guard.alloc_box(7u32).unwrap();
+    let b = guard.alloc_box(7u32).unwrap();
+    assert_eq!(*a, *b);
+    assert!(HeapBox::same_allocation(&a, &a));
+    assert!(!HeapBox::same_allocation(&a, &b));
+
+    // Reconstructing a box twice from the same leaked pointer is caught
+    let leaked = a.leak();
+    let first = unsafe { HeapBox::from_leaked(leaked) };
+    let second = unsafe { HeapBox::from_leaked(leaked) };
+    assert!(HeapBox::same_allocation(&first, &second));
+    std::mem::forget(second);
+}
+
+#[test]
+fn into_iter_both_ends() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const SIZE: usize = 16 * 1024;
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug)]
+    struct Tracked(u32);
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let arr = guard.alloc_array_from_exact((0..6).map(Tracked)).unwrap();
+    let mut iter = arr.into_iter();
+    assert_eq!(iter.len(), 6);
+    assert_eq!(iter.next().map(|t| t.0), Some(0));
+    assert_eq!(iter.next_back().map(|t| t.0), Some(5));
+    assert_eq!(iter.next_back().map(|t| t.0), Some(4));
+    assert_eq!(iter.size_hint(), (3, Some(3)));
+    let rest = iter.as_slice().iter().map(|t| t.0).collect::<Vec<_>>();
+    assert_eq!(rest, [1, 2, 3]);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 3);
+
+    // Only the middle is left to be dropped
+    drop(iter);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 6);
+
+    // Vecs only yield their initialized elements
+    let mut vec = guard.alloc_fixed_vec(8).unwrap();
+    vec.push_all((0..3).map(Tracked));
+    let rev = vec.into_iter().rev().map(|t| t.0).collect::<Vec<_>>();
+    assert_eq!(rev, [2, 1, 0]);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 9);
+    assert_eq!(HeapFixedVec::<Tracked>::new_empty().into_iter().count(), 0);
+
+    // Borrowing iteration leaves the array in place
+    let mut arr = guard.alloc_array_from_exact((0..3).map(Tracked)).unwrap();
+    for t in &mut arr {
+        t.0 *= 10;
+    }
+    let mut seen = Vec::new();
+    for t in &arr {
+        seen.push(t.0);
+    }
+    assert_eq!(seen, [0, 10, 20]);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 9);
+    drop(arr);
+
+    // Everything was freed
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn can_allocate_preflight() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    let heap = unsafe { heap.as_ref() };
+
+    let kib = |n: usize| Layout::array::<u8>(n * 1024).unwrap();
+    assert!(guard.can_allocate(kib(8)));
+    assert!(!guard.can_allocate(kib(16)));
+    assert!(guard.can_allocate_all(&[]));
+    assert!(guard.can_allocate_all(&[kib(6), kib(6)]));
+    // Each fits on its own, but not both at once
+    assert!(!guard.can_allocate_all(&[kib(8), kib(8)]));
+    assert!(!guard.can_allocate_all(&[kib(6), kib(6), kib(6)]));
+
+    // Nothing was actually allocated
+    assert_eq!(heap.alloc_count(), 0);
+    let a = guard.alloc_box_array_with(|| 0u8, 6 * 1024).unwrap();
+    let b = guard.alloc_box_array_with(|| 0u8, 6 * 1024).unwrap();
+    assert!(!guard.can_allocate(kib(6)));
+    drop((a, b));
+    assert!(guard.can_allocate(kib(12)));
+
+    // Many small layouts are placed without recursing, and all released
+    let tiny = vec![Layout::new::<u8>(); 4096];
+    assert!(guard.can_allocate_all(&tiny[..64]));
+    assert!(!guard.can_allocate_all(&tiny));
+    assert!(guard.can_allocate(kib(12)));
+}
+
+#[test]
+fn jagged_array() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let tri = guard
+        .alloc_jagged_with(4, |r| r + 1, |r, c| (r * 10 + c) as u32)
+        .unwrap();
+    assert_eq!(tri.len(), 4);
+    assert_eq!(&*tri[0], &[0]);
+    assert_eq!(&*tri[3], &[30, 31, 32, 33]);
+    drop(tri);
+
+    // The last row doesn't fit, so the others are freed too
+    let rows = [1024, 1024, 32 * 1024];
+    let err = guard
+        .alloc_jagged_with(3, |r| rows[r], |_, _| 0u8)
+        .map(drop)
+        .unwrap_err();
+    assert_eq!(err.kind(), AllocErrorKind::TooLarge);
+
+    // Everything was freed
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn box_cast() {
+    const SIZE: usize = 16 * 1024;
+
+    #[repr(transparent)]
+    struct Meters(u64);
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let inner = guard.alloc_box(42u64).unwrap();
+    let addr = &*inner as *const u64 as usize;
+    let wrapped: HeapBox<Meters> = unsafe { inner.cast() };
+    assert_eq!(wrapped.0, 42);
+    assert_eq!(&*wrapped as *const Meters as usize, addr);
+    let inner: HeapBox<u64> = unsafe { wrapped.cast() };
+    assert_eq!(*inner, 42);
+
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| unsafe {
+        inner.cast::<u32>()
+    }));
+    assert!(res.is_err());
+
+    // Everything was freed
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+// The overhead can be checked at compile time
+const _: () = assert!(HeapBox::<[u8; 256]>::OVERHEAD_BYTES <= 64);
+// Arcs add their reference counts
+const _: () =
+    assert!(HeapArc::<[usize; 32]>::OVERHEAD_BYTES > HeapBox::<[usize; 32]>::OVERHEAD_BYTES);
+
+#[test]
+fn overhead_bytes() {
+    // A large enough `T` only needs the heap pointer in front of it
+    assert_eq!(
+        HeapBox::<[usize; 32]>::OVERHEAD_BYTES,
+        core::mem::size_of::<usize>()
+    );
+    // Padding for alignment counts too
+    #[repr(align(64))]
+    struct Aligned;
+    assert_eq!(HeapBox::<Aligned>::OVERHEAD_BYTES, 64);
+}
+
+#[test]
+fn array_uninit_roundtrip() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let mut arr = guard.alloc_box_array_with(|| 1u8, 8).unwrap();
+    assert_eq!(arr.pop_front(), Some(1));
+    let addr = arr.as_ptr();
+
+    let mut scratch = arr.into_uninit();
+    assert_eq!(scratch.len(), 7);
+    assert_eq!(scratch.as_ptr().cast::<u8>(), addr);
+    for (i, slot) in scratch.iter_mut().enumerate() {
+        slot.write(i as u8);
+    }
+
+    let arr = unsafe { HeapArray::assume_all_init(scratch) };
+    assert_eq!(arr.as_ptr(), addr);
+    assert_eq!(&*arr, &[0, 1, 2, 3, 4, 5, 6]);
+    drop(arr);
+
+    // Everything was freed
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn box_clones() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    let heap = unsafe { heap.as_ref() };
+
+    let a = guard.alloc_box(String::from("config")).unwrap();
+    let b = a.try_clone(&mut guard).unwrap();
+    assert_eq!(*a, *b);
+    assert_ne!(&*a as *const String, &*b as *const String);
+    drop(guard);
+
+    use std::future::Future;
+
+    let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+    let fut = std::pin::pin!(a.clone_in(heap));
+    let std::task::Poll::Ready(c) = fut.poll(&mut cx) else {
+        panic!("allocation should have completed");
+    };
+    assert_eq!(*c, "config");
+    assert_ne!(&*a as *const String, &*c as *const String);
+}
+
+#[test]
+fn dyn_boxes() {
+    use std::cell::Cell;
+
+    trait Command {
+        fn run(&self) -> u32;
+    }
+
+    struct Small<'a>(u8, &'a Cell<u32>);
+    struct Large<'a>([u64; 16], &'a Cell<u32>);
+
+    impl Command for Small<'_> {
+        fn run(&self) -> u32 {
+            self.0 as u32
+        }
+    }
+    impl Command for Large<'_> {
+        fn run(&self) -> u32 {
+            self.0.iter().sum::<u64>() as u32
+        }
+    }
+    impl Drop for Small<'_> {
+        fn drop(&mut self) {
+            self.1.set(self.1.get() + 1);
+        }
+    }
+    impl Drop for Large<'_> {
+        fn drop(&mut self) {
+            self.1.set(self.1.get() + 1);
+        }
+    }
+
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let drops = Cell::new(0);
+    let small = guard.alloc_box(Small(3, &drops)).map_err(drop).unwrap();
+    let large = guard
+        .alloc_box(Large([2; 16], &drops))
+        .map_err(drop)
+        .unwrap();
+    let commands: Vec<HeapDynBox<dyn Command + '_>> = vec![
+        heap_box_dyn!(small => dyn Command + '_),
+        heap_box_dyn!(large => dyn Command + '_),
+    ];
+    assert_eq!(
+        commands.iter().map(|c| c.run()).collect::<Vec<_>>(),
+        [3, 32]
+    );
+
+    drop(commands);
+    assert_eq!(drops.get(), 2);
+
+    // Everything was freed
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn containers_compare_by_value() {
+    use std::collections::HashSet;
+
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let a = guard.alloc_box(String::from("uart")).unwrap();
+    let b = guard.alloc_box(String::from("uart")).unwrap();
+    let c = guard.alloc_box(String::from("spi")).unwrap();
+    assert!(a == b);
+    assert!(c < a);
+
+    let mut names = HashSet::new();
+    assert!(names.insert(a));
+    assert!(!names.insert(b));
+    assert!(names.insert(c));
+
+    let x = guard.alloc_box_array_with(|| 1u8, 4).unwrap();
+    let y = guard.alloc_box_array_with(|| 1u8, 4).unwrap();
+    let z = guard.alloc_box_array_with(|| 1u8, 5).unwrap();
+    assert_eq!(x, y);
+    assert!(x < z);
+
+    let mut v = guard.alloc_fixed_vec(8).unwrap();
+    v.extend_from_slice(&[1, 1, 1, 1]).unwrap();
+    assert!(*v == *x);
+    let mut w = guard.alloc_fixed_vec(4).unwrap();
+    w.extend_from_slice(&[1, 1, 1, 1]).unwrap();
+    assert_eq!(v, w);
+
+    let p = guard.alloc_arc(7u32).map_err(drop).unwrap();
+    let q = guard.alloc_arc(7u32).map_err(drop).unwrap();
+    assert_eq!(p, q);
+    assert!(!HeapArc::ptr_eq(&p, &q));
+}
+
+#[test]
+fn box_into_inner() {
+    use std::rc::Rc;
+
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    // The contents are moved out, not dropped (or cloned)
+    let rc = Rc::new(5u32);
+    let bx = guard.alloc_box(rc.clone()).map_err(drop).unwrap();
+    let out = HeapBox::into_inner(bx);
+    assert_eq!(Rc::strong_count(&rc), 2);
+    drop(out);
+    assert_eq!(Rc::strong_count(&rc), 1);
+
+    // Everything was freed
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn forwarding_traits() {
+    use std::collections::{HashMap, HashSet};
+
+    fn checksum(bytes: impl AsRef<[u8]>) -> u32 {
+        bytes.as_ref().iter().map(|&b| b as u32).sum()
+    }
+
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let name = guard.alloc_box(String::from("uart0")).unwrap();
+    assert_eq!(format!("{name} {name:?}"), "uart0 \"uart0\"");
+    let mut ports = HashMap::new();
+    ports.insert(name, 3);
+    assert_eq!(ports.get(&String::from("uart0")), Some(&3));
+
+    let arr = guard.alloc_array_from_exact([1u8, 2, 3]).unwrap();
+    assert_eq!(checksum(&arr), 6);
+    let mut vec = guard.alloc_fixed_vec(4).unwrap();
+    vec.extend_from_slice(&[4u8, 5]).unwrap();
+    assert_eq!(checksum(&vec), 9);
+
+    let mut seen = HashSet::new();
+    seen.insert(arr);
+    assert!(seen.contains(&[1u8, 2, 3][..]));
+}
+
+#[test]
+fn pinned_arc() {
+    use std::marker::PhantomPinned;
+
+    struct Unmovable {
+        val: u32,
+        _pin: PhantomPinned,
+    }
+
+    let heap = new_heap();
+    let mut guard = heap.lock().unwrap();
+    let arc = guard
+        .alloc_arc(Unmovable {
+            val: 7,
+            _pin: PhantomPinned,
+        })
+        .map_err(drop)
+        .unwrap();
+    drop(guard);
+
+    let pinned = HeapArc::into_pin(arc);
+    let other = pinned.clone();
+    let a: std::pin::Pin<&Unmovable> = pinned.as_ref();
+    let b: std::pin::Pin<&Unmovable> = other.as_ref();
+    assert!(std::ptr::eq(&*a, &*b));
+    assert_eq!(b.val, 7);
+}
+
+#[test]
+fn box_uninit() {
+    use std::ptr::addr_of_mut;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Big {
+        id: u32,
+        buf: [u8; 4096],
+    }
+
+    impl Drop for Big {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let heap = new_heap();
+
+    // Build it up field by field, in place
+    let mut uninit = now(heap.allocate_uninit::<Big>());
+    let addr = uninit.as_ptr();
+    unsafe {
+        let ptr = uninit.as_mut_ptr();
+        addr_of_mut!((*ptr).id).write(3);
+        addr_of_mut!((*ptr).buf).cast::<u8>().write_bytes(7, 4096);
+    }
+    let big = unsafe { uninit.assume_init() };
+    assert!(std::ptr::eq(&*big, addr));
+    assert_eq!(big.id, 3);
+    assert!(big.buf.iter().all(|&b| b == 7));
+    drop(big);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+
+    // An uninitialized box is freed without dropping its contents
+    drop(now(heap.allocate_uninit::<Big>()));
+    assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+
+    // Everything was freed
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn array_try_map() {
+    let heap = new_heap();
+
+    let raw = now(heap.allocate_array_with(|| String::from("7"), 3));
+    let parsed: HeapArray<u32> = now(raw.try_map(heap, |s| s.parse())).unwrap();
+    assert_eq!(&*parsed, &[7, 7, 7]);
+
+    let raw = now(heap.allocate_array_from_exact(["1", "x", "3", "4"].map(String::from)));
+    let res: Result<HeapArray<Box<u32>>, _> = now(raw.try_map(heap, |s| s.parse().map(Box::new)));
+    assert!(res.is_err());
+
+    // Both arrays were freed
+    drop(parsed);
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn vec_shrink() {
+    let heap = new_heap();
+
+    let mut vec = HeapVec::new();
+    for i in 0..5u32 {
+        now(vec.push(heap, i));
+    }
+    assert_eq!(vec.capacity(), 8);
+    assert_eq!(vec.pop(), Some(4));
+
+    now(vec.shrink_to(heap, 6));
+    assert_eq!(vec.capacity(), 6);
+    // Never grows
+    now(vec.shrink_to(heap, 10));
+    assert_eq!(vec.capacity(), 6);
+
+    now(vec.shrink_to_fit(heap));
+    assert_eq!(vec.capacity(), 4);
+    assert_eq!(&*vec, &[0, 1, 2, 3]);
+    // Already a no-op
+    now(vec.shrink_to_fit(heap));
+    assert_eq!(vec.capacity(), 4);
+
+    while vec.pop().is_some() {}
+    now(vec.shrink_to_fit(heap));
+    assert_eq!(vec.capacity(), 0);
+
+    // Everything was freed
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn array_dedup_to_new() {
+    let heap = new_heap();
+
+    let arr = now(heap.allocate_array_from_exact([1u32, 1, 2, 3, 3, 3, 1]));
+    let deduped = now(arr.dedup_to_new(heap));
+    assert_eq!(&*deduped, &[1, 2, 3, 1]);
+    // The source is untouched
+    assert_eq!(&*arr, &[1, 1, 2, 3, 3, 3, 1]);
+
+    let empty = now(heap.allocate_array_from_exact([0u32; 0]));
+    assert!(now(empty.dedup_to_new(heap)).is_empty());
+}
+
+#[test]
+fn array_from_array() {
+    let heap = new_heap();
+
+    // Not `Clone`, so it must be moved
+    struct NoClone(u32);
+    let arr = now(heap.allocate_array_from([NoClone(1), NoClone(2), NoClone(3)]));
+    assert_eq!(arr.iter().map(|x| x.0).collect::<Vec<_>>(), [1, 2, 3]);
+
+    let mut guard = heap.lock().unwrap();
+    let strings = guard
+        .alloc_array_from([String::from("a"), String::from("b")])
+        .unwrap();
+    assert_eq!(&*strings, &["a", "b"]);
+
+    // Failing hands the array back
+    let big = guard
+        .alloc_array_from([0u8; 32 * 1024])
+        .map(drop)
+        .unwrap_err();
+    assert_eq!(big.len(), 32 * 1024);
+}
+
+#[test]
+fn box_try_map() {
+    let heap = new_heap();
+
+    // Same size, so the allocation is reused
+    let boxed = now(heap.allocate(String::from("42")));
+    let addr = &*boxed as *const String as usize;
+    let parsed: HeapBox<u64> = now(boxed.try_map(heap, |s| s.parse())).unwrap();
+    assert_eq!(*parsed, 42);
+    assert_eq!(&*parsed as *const u64 as usize, addr);
+
+    // Larger, so it is moved to a new allocation
+    let big: HeapBox<[u64; 64]> = now(parsed.try_map(heap, |x| Ok::<_, ()>([x; 64]))).unwrap();
+    assert!(big.iter().all(|x| *x == 42));
+
+    let res: Result<HeapBox<u8>, _> = now(big.try_map(heap, |_| Err("nope")));
+    assert_eq!(res.map(drop), Err("nope"));
+
+    // Everything was freed
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn array_into_shared() {
+    let heap = new_heap();
+
+    // A whole array keeps its node
+    let arr = now(heap.allocate_array_from_exact([1u32, 2, 3, 4]));
+    let addr = arr.as_ptr();
+    let shared = now(arr.into_shared(heap));
+    assert_eq!(shared.as_ptr(), addr);
+    let other = shared.clone();
+    assert_eq!(&*other, &[1, 2, 3, 4]);
+    drop(shared);
+    assert_eq!(&*other, &[1, 2, 3, 4]);
+    drop(other);
+
+    // With elements removed from the front, they are moved to a new node
+    let mut arr = now(heap.allocate_array_from_exact(["a", "b", "c"].map(String::from)));
+    drop(arr.pop_front());
+    let shared = now(arr.into_shared(heap));
+    assert_eq!(&*shared, &["b", "c"]);
+    drop(shared);
+
+    // Everything was freed
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn array_from_iter() {
+    let heap = new_heap();
+
+    let arr = now(heap.allocate_array_from_iter((0..4).map(|i| i.to_string()), 4)).unwrap();
+    assert_eq!(&*arr, &["0", "1", "2", "3"]);
+    drop(arr);
+
+    // Mismatched lengths drop the items written so far, and free the array
+    let short = now(heap.allocate_array_from_iter((0..3).map(|i| i.to_string()), 4));
+    assert_eq!(
+        short.map(drop),
+        Err(IterLengthError::TooShort {
+            yielded: 3,
+            capacity: 4
+        })
+    );
+    let mut iter = (0..6).map(|i| i.to_string());
+    let long = now(heap.allocate_array_from_iter(&mut iter, 4));
+    assert_eq!(
+        long.map(drop),
+        Err(IterLengthError::TooLong { capacity: 4 })
+    );
+    assert_eq!(iter.next().as_deref(), Some("5"));
+
+    // Everything was freed
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn array_split_off() {
+    let heap = new_heap();
+
+    let arr = now(heap.allocate_array_from_exact(["h0", "h1", "p0", "p1", "p2"].map(String::from)));
+    let (head, tail) = now(arr.split_off(heap, 2));
+    assert_eq!(&*head, &["h0", "h1"]);
+    assert_eq!(&*tail, &["p0", "p1", "p2"]);
+    drop(head);
+    assert_eq!(&*tail, &["p0", "p1", "p2"]);
+
+    // Either side may be empty
+    let (empty, all) = now(tail.split_off(heap, 0));
+    assert!(empty.is_empty());
+    assert_eq!(&*all, &["p0", "p1", "p2"]);
+    let (all, empty) = now(all.split_off(heap, 3));
+    assert_eq!(&*all, &["p0", "p1", "p2"]);
+    assert!(empty.is_empty());
+    drop((all, empty));
+
+    // Everything was freed
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
@@ -0,0 +1,129 @@
+//! A stress test of many threads allocating and freeing from one heap at
+//! once, through the async API.
+//!
+//! Run with `cargo test --release --test stress -- --nocapture` to see the
+//! throughput.
+
+use std::future::Future;
+use std::pin::pin;
+use std::sync::{Arc, Barrier};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+use mnemos_alloc::heap::{AHeap, HeapBuilder};
+
+const SIZE: usize = 16 * 1024;
+const THREADS: usize = 4;
+const OPS: usize = if cfg!(miri) {
+    50
+} else if cfg!(debug_assertions) {
+    20_000
+} else {
+    200_000
+};
+/// How many boxes each thread keeps alive at once
+const LIVE: usize = 8;
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run a future to completion on the current thread
+fn block_on<F: Future>(fut: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut fut = pin!(fut);
+    loop {
+        if let Poll::Ready(t) = fut.as_mut().poll(&mut cx) {
+            return t;
+        }
+        // A lost wakeup would otherwise hang the test forever
+        let start = Instant::now();
+        thread::park_timeout(Duration::from_secs(5));
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "allocation stalled"
+        );
+    }
+}
+
+/// Hammer `heap` from `THREADS` threads at once, returning the throughput
+/// in allocations per second.
+fn run(heap: &'static AHeap) -> f64 {
+    let barrier = Arc::new(Barrier::new(THREADS));
+    let start = Instant::now();
+    let threads: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                let mut live = Vec::with_capacity(LIVE);
+                for i in 0..OPS {
+                    let fill = (t * OPS + i) as u8;
+                    let b = block_on(heap.allocate([fill; 48]));
+                    live.push((fill, b));
+                    if live.len() == LIVE {
+                        // Check nobody else was handed the same block
+                        for (fill, b) in live.drain(..) {
+                            assert!(b.iter().all(|x| *x == fill));
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    // No blocks were lost
+    heap.poll();
+    assert_eq!(heap.alloc_count(), heap.free_count());
+
+    (THREADS * OPS) as f64 / elapsed.as_secs_f64()
+}
+
+#[test]
+fn concurrent_alloc_free() {
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    drop(guard);
+    let heap: &'static AHeap = unsafe { heap.as_ref() };
+
+    let rate = run(heap);
+    println!("{THREADS} threads: {rate:.0} allocations/s");
+
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+/// The same, with all of the boxes served from the node cache.
+#[test]
+fn concurrent_alloc_free_cached() {
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, guard) = unsafe {
+        HeapBuilder::new()
+            .with_node_cache(128, THREADS * LIVE)
+            .bootstrap(bufptr.cast::<u8>(), SIZE)
+            .unwrap()
+    };
+    drop(guard);
+    let heap: &'static AHeap = unsafe { heap.as_ref() };
+
+    let rate = run(heap);
+    println!("{THREADS} threads, node cache: {rate:.0} allocations/s");
+    // A block freed while its shard is busy goes back to the heap instead,
+    // so the odd miss is expected, but nearly everything should hit
+    let stats = heap.node_cache_stats();
+    assert!(stats.misses * 20 < stats.hits, "{stats:?}");
+
+    // The rest of the heap is untouched by the cached blocks
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 8 * 1024).unwrap());
+}
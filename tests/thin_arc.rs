@@ -0,0 +1,20 @@
+#![cfg(feature = "thin-arc")]
+
+use mnemos_alloc::{containers::HeapArc, heap::AHeap};
+
+#[test]
+fn counts() {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+
+    let arc = guard.alloc_arc(123u64).map_err(drop).unwrap();
+    let arc2 = arc.clone();
+    let weak = HeapArc::downgrade(&arc);
+    assert_eq!(HeapArc::strong_count(&arc), 2);
+    assert_eq!(HeapArc::weak_count(&arc), 1);
+
+    drop((arc, arc2));
+    assert!(weak.upgrade().is_none());
+}
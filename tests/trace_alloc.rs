@@ -0,0 +1,55 @@
+#![cfg(feature = "trace-alloc")]
+
+use core::{alloc::Layout, ptr::NonNull};
+use std::sync::Mutex;
+
+use mnemos_alloc::heap::{AHeap, AllocTraceHook};
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Alloc(usize, &'static str),
+    Free(usize),
+}
+
+struct Recorder(Mutex<Vec<Event>>);
+
+impl AllocTraceHook for Recorder {
+    fn on_alloc(&self, ptr: NonNull<u8>, _layout: Layout, type_name: &'static str) {
+        let event = Event::Alloc(ptr.as_ptr() as usize, type_name);
+        self.0.lock().unwrap().push(event);
+    }
+
+    fn on_free(&self, ptr: NonNull<u8>, _layout: Layout) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(Event::Free(ptr.as_ptr() as usize));
+    }
+}
+
+#[test]
+fn alloc_and_free() {
+    const SIZE: usize = 16 * 1024;
+    static RECORDER: Recorder = Recorder(Mutex::new(Vec::new()));
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    guard.set_trace_hook(Some(&RECORDER));
+
+    let boxed = guard.alloc_box(1u32).map_err(drop).unwrap();
+    let arr = guard.alloc_box_array_with(|| 0u16, 4).unwrap();
+    let box_addr = match RECORDER.0.lock().unwrap()[..] {
+        [Event::Alloc(b, "u32"), Event::Alloc(_, "[u16]")] => b,
+        ref events => panic!("unexpected events: {events:?}"),
+    };
+
+    // Freed while the heap is locked, so processed on the next allocation
+    drop(boxed);
+    let _ = guard.alloc_box(2u8).map_err(drop).unwrap();
+    assert_eq!(RECORDER.0.lock().unwrap()[2], Event::Free(box_addr));
+
+    guard.set_trace_hook(None);
+    drop(arr);
+    let _ = guard.alloc_box(3u8).map_err(drop).unwrap();
+    assert_eq!(RECORDER.0.lock().unwrap().len(), 4);
+}
@@ -0,0 +1,108 @@
+use std::cell::RefCell;
+
+use mnemos_alloc::{
+    containers::{HeapArc, HeapArray, HeapBox, HeapFixedVec, HeapWeak},
+    heap::{AHeap, HeapGuard},
+};
+
+// These only need to compile: each container is covariant in `T`.
+#[allow(dead_code)]
+fn box_is_covariant<'a>(b: HeapBox<&'static str>) -> HeapBox<&'a str> {
+    b
+}
+
+#[allow(dead_code)]
+fn arc_is_covariant<'a>(b: HeapArc<&'static str>) -> HeapArc<&'a str> {
+    b
+}
+
+#[allow(dead_code)]
+fn weak_is_covariant<'a>(b: HeapWeak<&'static str>) -> HeapWeak<&'a str> {
+    b
+}
+
+#[allow(dead_code)]
+fn array_is_covariant<'a>(b: HeapArray<&'static str>) -> HeapArray<&'a str> {
+    b
+}
+
+#[allow(dead_code)]
+fn fixed_vec_is_covariant<'a>(b: HeapFixedVec<&'static str>) -> HeapFixedVec<&'a str> {
+    b
+}
+
+/// Records its id in the log when dropped
+struct Noisy<'a> {
+    id: u32,
+    log: &'a RefCell<Vec<u32>>,
+}
+
+impl Drop for Noisy<'_> {
+    fn drop(&mut self) {
+        self.log.borrow_mut().push(self.id);
+    }
+}
+
+fn with_heap(f: impl FnOnce(&mut HeapGuard)) {
+    const SIZE: usize = 16 * 1024;
+
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (_heap, mut guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    f(&mut guard);
+}
+
+#[test]
+fn contents_are_dropped_in_order() {
+    with_heap(|guard| {
+        let log = RefCell::new(Vec::new());
+
+        let next = std::cell::Cell::new(0);
+        let arr = guard
+            .alloc_box_array_with(
+                || {
+                    let id = next.get();
+                    next.set(id + 1);
+                    Noisy { id, log: &log }
+                },
+                3,
+            )
+            .unwrap();
+        drop(arr);
+        assert_eq!(&*log.borrow(), &[0, 1, 2]);
+
+        let mut vec = guard.alloc_fixed_vec(4).unwrap();
+        for id in 10..13 {
+            vec.push(Noisy { id, log: &log }).map_err(drop).unwrap();
+        }
+        drop(vec);
+        assert_eq!(&*log.borrow(), &[0, 1, 2, 10, 11, 12]);
+
+        let bx = guard
+            .alloc_box(Noisy { id: 20, log: &log })
+            .map_err(drop)
+            .unwrap();
+        let arc = guard
+            .alloc_arc(Noisy { id: 30, log: &log })
+            .map_err(drop)
+            .unwrap();
+        let weak = HeapArc::downgrade(&arc);
+        drop(bx);
+        drop(arc);
+        assert_eq!(&*log.borrow(), &[0, 1, 2, 10, 11, 12, 20, 30]);
+        // The weak doesn't own a value, and dropping it drops nothing
+        drop(weak);
+        assert_eq!(log.borrow().len(), 8);
+    });
+}
+
+#[test]
+fn array_drop_in_reverse() {
+    with_heap(|guard| {
+        let log = RefCell::new(Vec::new());
+
+        let ids = (0..4).map(|id| Noisy { id, log: &log });
+        let arr = guard.alloc_array_from_exact(ids).unwrap();
+        arr.drop_in_reverse();
+        assert_eq!(&*log.borrow(), &[3, 2, 1, 0]);
+    });
+}
@@ -0,0 +1,279 @@
+use std::alloc::Layout;
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::pin;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::task::{Context, Poll, Wake, Waker};
+
+use mnemos_alloc::heap::{AHeap, AllocErrorKind};
+
+const SIZE: usize = 16 * 1024;
+
+#[derive(Default)]
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+fn new_heap() -> &'static AHeap {
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    drop(guard);
+    unsafe { heap.as_ref() }
+}
+
+/// A task waiting on an allocation is woken (and completes) when another
+/// task frees enough memory, without anyone calling `AHeap::poll`.
+#[test]
+fn waiting_alloc_is_woken_by_free() {
+    let heap = new_heap();
+    let flag = Arc::new(Flag::default());
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    // Task B holds most of the heap
+    let big = heap
+        .lock()
+        .unwrap()
+        .alloc_box_array_with(|| 0u8, SIZE / 2)
+        .unwrap();
+
+    // Task A wants more than is left
+    let mut task_a = pin!(heap.allocate_array_with(|| 1u8, SIZE / 2));
+    assert!(task_a.as_mut().poll(&mut cx).is_pending());
+    assert!(!flag.0.load(Ordering::SeqCst));
+
+    // Task B frees its block...
+    drop(big);
+
+    // ...so A is woken, and can complete.
+    assert!(flag.0.load(Ordering::SeqCst));
+    match task_a.as_mut().poll(&mut cx) {
+        Poll::Ready(arr) => assert_eq!(arr.len(), SIZE / 2),
+        Poll::Pending => panic!("allocation should have completed"),
+    }
+}
+
+/// Frees that happen while the heap is locked go to the free list, but must
+/// still wake waiting allocations.
+#[test]
+fn waiting_alloc_is_woken_by_deferred_free() {
+    let heap = new_heap();
+    let flag = Arc::new(Flag::default());
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    let big = heap
+        .lock()
+        .unwrap()
+        .alloc_box_array_with(|| 0u8, SIZE / 2)
+        .unwrap();
+
+    let mut task_a = pin!(heap.allocate_array_with(|| 1u8, SIZE / 2));
+    assert!(task_a.as_mut().poll(&mut cx).is_pending());
+
+    // Free while someone else holds the lock
+    let guard = heap.lock().unwrap();
+    drop(big);
+    drop(guard);
+
+    assert!(flag.0.load(Ordering::SeqCst));
+    assert!(task_a.as_mut().poll(&mut cx).is_ready());
+}
+
+/// Fill the heap with 1000 byte arrays.
+fn fill(heap: &'static AHeap) -> Vec<mnemos_alloc::containers::HeapArray<u8>> {
+    let mut guard = heap.lock().unwrap();
+    let mut blocks = Vec::new();
+    while let Ok(arr) = guard.alloc_box_array_with(|| 0u8, 1000) {
+        blocks.push(arr);
+    }
+    blocks
+}
+
+/// A large waiting allocation isn't starved by small allocations that would
+/// each fit in the space freed so far.
+#[test]
+fn large_waiter_goes_first() {
+    let heap = new_heap();
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut blocks = fill(heap);
+
+    let mut large = pin!(heap.allocate_array_with(|| 1u8, 4096));
+    assert!(large.as_mut().poll(&mut cx).is_pending());
+
+    // There's room for a small allocation now, but the large one was first
+    drop(blocks.pop());
+    let mut small = pin!(heap.allocate_array_with(|| 2u8, 512));
+    assert!(small.as_mut().poll(&mut cx).is_pending());
+
+    for _ in 0..4 {
+        drop(blocks.pop());
+        assert!(small.as_mut().poll(&mut cx).is_pending());
+    }
+
+    let Poll::Ready(large) = large.as_mut().poll(&mut cx) else {
+        panic!("large allocation should have completed");
+    };
+    assert_eq!(large.len(), 4096);
+    assert!(small.as_mut().poll(&mut cx).is_ready());
+}
+
+/// A higher priority allocation overtakes a lower priority one that is
+/// already waiting.
+#[test]
+fn higher_priority_goes_first() {
+    let heap = new_heap();
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut blocks = fill(heap);
+
+    let mut low = pin!(heap.allocate_array_with(|| 1u8, 2048));
+    assert!(low.as_mut().poll(&mut cx).is_pending());
+    let mut high = pin!(heap.allocate_prioritized([2u8; 2048], 1));
+    assert!(high.as_mut().poll(&mut cx).is_pending());
+
+    // Room for one of them
+    for _ in 0..3 {
+        drop(blocks.pop());
+    }
+    assert!(low.as_mut().poll(&mut cx).is_pending());
+    let Poll::Ready(high) = high.as_mut().poll(&mut cx) else {
+        panic!("high priority allocation should have completed");
+    };
+    assert_eq!(*high, [2u8; 2048]);
+    assert!(low.as_mut().poll(&mut cx).is_pending());
+
+    drop(high);
+    assert!(low.as_mut().poll(&mut cx).is_ready());
+}
+
+/// Non-blocking allocations fail straight away, rather than waiting for
+/// space, or for their turn.
+#[test]
+fn try_alloc_never_waits() {
+    let heap = new_heap();
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut blocks = fill(heap);
+
+    // Out of space
+    let (item, err) = heap.try_alloc_box([3u8; 2048]).map(drop).unwrap_err();
+    assert_eq!(item, [3u8; 2048]);
+    assert_eq!(err.kind(), AllocErrorKind::OutOfMemory);
+    let err = heap.try_alloc_array_with(|| 3u8, 2048).unwrap_err();
+    assert_eq!(err.kind(), AllocErrorKind::OutOfMemory);
+
+    // Locked
+    drop(blocks.pop());
+    let guard = heap.lock().unwrap();
+    let (item, err) = heap.try_alloc_box(4u8).map(drop).unwrap_err();
+    assert_eq!(item, 4);
+    assert_eq!(err.kind(), AllocErrorKind::Busy);
+    let err = heap.try_alloc_array_with(|| 4u8, 16).unwrap_err();
+    assert_eq!(err.kind(), AllocErrorKind::Busy);
+    drop(guard);
+
+    // An async allocation is waiting its turn, so goes first
+    let mut large = pin!(heap.allocate_array_with(|| 1u8, 2048));
+    assert!(large.as_mut().poll(&mut cx).is_pending());
+    let (_, err) = heap.try_alloc_box(5u8).map(drop).unwrap_err();
+    assert_eq!(err.kind(), AllocErrorKind::Busy);
+    let err = heap.try_alloc_array_with(|| 5u8, 16).unwrap_err();
+    assert_eq!(err.kind(), AllocErrorKind::Busy);
+
+    drop(blocks.pop());
+    drop(blocks.pop());
+    let Poll::Ready(large) = large.as_mut().poll(&mut cx) else {
+        panic!("large allocation should have completed");
+    };
+    assert_eq!(large.len(), 2048);
+
+    // Nothing is waiting any more
+    assert_eq!(*heap.try_alloc_box(6u8).map_err(drop).unwrap(), 6);
+    assert_eq!(*heap.try_alloc_array_with(|| 6u8, 16).unwrap(), [6u8; 16]);
+}
+
+/// An async allocation that could never fit panics straight away, rather
+/// than waiting forever and holding up every allocation behind it.
+#[test]
+fn impossible_alloc_does_not_wait() {
+    let heap = new_heap();
+    let mut cx = Context::from_waker(Waker::noop());
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let mut huge = pin!(heap.allocate_array_with(|| 0u8, 2 * SIZE));
+        let _ = huge.as_mut().poll(&mut cx);
+    }));
+    assert!(res.is_err());
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let mut huge = pin!(heap.allocate([0u8; 2 * SIZE]));
+        let _ = huge.as_mut().poll(&mut cx);
+    }));
+    assert!(res.is_err());
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let row_len = |r| if r == 1 { 2 * SIZE } else { 8 };
+        let mut huge = pin!(heap.allocate_jagged_with(3, row_len, |_, _| 0u8));
+        let _ = huge.as_mut().poll(&mut cx);
+    }));
+    assert!(res.is_err());
+
+    // Checked before the future is even created, so it is never polled
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        drop(heap.allocate_array_aligned_with(|| 0u8, 8, 2 * heap.max_align()));
+    }));
+    assert!(res.is_err());
+
+    // The fallible variants return an error instead, without waiting
+    let Poll::Ready(res) = pin!(heap.allocate_array_with_checked(|| 0u8, 2 * SIZE)).poll(&mut cx)
+    else {
+        panic!("impossible allocation should have failed immediately");
+    };
+    assert_eq!(res.unwrap_err().kind(), AllocErrorKind::TooLarge);
+    let Poll::Ready(res) = pin!(heap.allocate_checked([7u8; 2 * SIZE])).poll(&mut cx) else {
+        panic!("impossible allocation should have failed immediately");
+    };
+    let (item, err) = res.unwrap_err();
+    assert_eq!((item[0], err.kind()), (7, AllocErrorKind::TooLarge));
+    let Poll::Ready(res) = pin!(heap.allocate_arc_checked([7u8; 2 * SIZE])).poll(&mut cx) else {
+        panic!("impossible allocation should have failed immediately");
+    };
+    assert_eq!(
+        res.map(drop).unwrap_err().1.kind(),
+        AllocErrorKind::TooLarge
+    );
+    let layout = Layout::from_size_align(8, 2 * heap.max_align()).unwrap();
+    let Poll::Ready(res) = pin!(heap.allocate_raw_checked(layout)).poll(&mut cx) else {
+        panic!("impossible allocation should have failed immediately");
+    };
+    assert_eq!(res.unwrap_err().kind(), AllocErrorKind::UnsupportedAlign);
+    let fut = heap.allocate_array_aligned_with_checked(|| 0u8, 8, 3);
+    let Poll::Ready(res) = pin!(fut).poll(&mut cx) else {
+        panic!("impossible allocation should have failed immediately");
+    };
+    assert_eq!(res.unwrap_err().kind(), AllocErrorKind::InvalidAlign);
+
+    // The sync equivalent reports it as an error that can never succeed
+    let err = heap
+        .lock()
+        .unwrap()
+        .alloc_box_array_with(|| 0u8, 2 * SIZE)
+        .unwrap_err();
+    assert_eq!(err.kind(), AllocErrorKind::TooLarge);
+
+    // Nothing was left waiting its turn
+    let mut small = pin!(heap.allocate(1u8));
+    let Poll::Ready(small) = small.as_mut().poll(&mut cx) else {
+        panic!("small allocation should have completed");
+    };
+    assert_eq!(*small, 1);
+    let Poll::Ready(res) = pin!(heap.allocate_checked(2u8)).poll(&mut cx) else {
+        panic!("small allocation should have completed");
+    };
+    assert_eq!(*res.map_err(drop).unwrap(), 2);
+}
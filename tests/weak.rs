@@ -0,0 +1,569 @@
+use std::cell::UnsafeCell;
+use std::future::Future;
+use std::pin::pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Waker};
+
+use mnemos_alloc::{
+    containers::{ByAddress, HeapArc, HeapDynArc, HeapWeak},
+    heap::{AHeap, AllocErrorKind},
+    heap_arc_dyn,
+};
+
+const SIZE: usize = 16 * 1024;
+
+/// Poll a future that is expected to complete immediately
+fn now<F: Future>(fut: F) -> F::Output {
+    let mut cx = Context::from_waker(Waker::noop());
+    match pin!(fut).poll(&mut cx) {
+        Poll::Ready(t) => t,
+        Poll::Pending => panic!("future was not immediately ready"),
+    }
+}
+
+fn new_heap() -> &'static AHeap {
+    let bufptr = Box::into_raw(Box::new([0u8; SIZE]));
+    let (heap, guard) = unsafe { AHeap::bootstrap(bufptr.cast::<u8>(), SIZE).unwrap() };
+    drop(guard);
+    unsafe { heap.as_ref() }
+}
+
+/// Allocate an arc, retrying if the heap is briefly locked by another thread
+fn alloc_arc<T>(heap: &'static AHeap, mut item: T) -> HeapArc<T> {
+    loop {
+        if let Ok(mut guard) = heap.lock() {
+            match guard.alloc_arc(item) {
+                Ok(arc) => return arc,
+                Err(it) => item = it,
+            }
+        }
+        std::thread::yield_now();
+    }
+}
+
+#[test]
+fn upgrade_and_counts() {
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Tracked(u32);
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let heap = new_heap();
+    let arc = alloc_arc(heap, Tracked(42));
+    assert_eq!(HeapArc::weak_count(&arc), 0);
+
+    let weak = HeapArc::downgrade(&arc);
+    let weak2 = weak.clone();
+    assert_eq!(HeapArc::weak_count(&arc), 2);
+    assert_eq!(HeapArc::strong_count(&arc), 1);
+
+    let arc2 = weak.upgrade().unwrap();
+    assert_eq!(arc2.0, 42);
+    assert_eq!(weak.strong_count(), 2);
+
+    drop(arc);
+    drop(arc2);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+
+    // The value is gone, and can't be resurrected
+    assert!(weak.upgrade().is_none());
+    assert_eq!(weak.strong_count(), 0);
+    assert_eq!(weak.weak_count(), 0);
+
+    drop(weak);
+    drop(weak2);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+}
+
+/// Race `upgrade` on many threads against the last strong reference being
+/// dropped. The value must be dropped exactly once, and never observed
+/// after it was dropped.
+#[test]
+fn upgrade_races_last_drop() {
+    const ITERS: usize = 200;
+    const THREADS: usize = 4;
+    const MAGIC: usize = 0xC0FF_EE00;
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Checked(AtomicUsize);
+
+    impl Drop for Checked {
+        fn drop(&mut self) {
+            // Catch double drops
+            assert_eq!(self.0.swap(0, Ordering::SeqCst), MAGIC);
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let heap = new_heap();
+
+    for i in 0..ITERS {
+        let arc = alloc_arc(heap, Checked(AtomicUsize::new(MAGIC)));
+        let weaks: Vec<HeapWeak<Checked>> =
+            (0..THREADS).map(|_| HeapArc::downgrade(&arc)).collect();
+
+        let handles = weaks
+            .into_iter()
+            .map(|weak| {
+                std::thread::spawn(move || {
+                    while let Some(arc) = weak.upgrade() {
+                        // Catch use-after-drop
+                        assert_eq!(arc.0.load(Ordering::SeqCst), MAGIC);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        drop(arc);
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(DROPS.load(Ordering::SeqCst), i + 1);
+    }
+
+    // Everything was returned to the heap: we can still allocate a large chunk.
+    let mut guard = heap.lock().unwrap();
+    let big = guard.alloc_box_array_with(|| 0u8, SIZE / 2).unwrap();
+    drop(big);
+}
+
+/// Writes made through one arc happen before the contents are dropped by
+/// another, on a different thread. Under miri, a missing fence shows up as a
+/// data race.
+#[test]
+fn concurrent_last_drop_sees_writes() {
+    const ITERS: usize = if cfg!(miri) { 10 } else { 200 };
+
+    struct Slots([UnsafeCell<usize>; 2]);
+
+    // Each thread only writes its own slot
+    unsafe impl Sync for Slots {}
+
+    impl Drop for Slots {
+        fn drop(&mut self) {
+            assert_eq!(*self.0[0].get_mut(), 1);
+            assert_eq!(*self.0[1].get_mut(), 2);
+        }
+    }
+
+    let heap = new_heap();
+
+    for _ in 0..ITERS {
+        let arc = alloc_arc(heap, Slots([UnsafeCell::new(0), UnsafeCell::new(0)]));
+        let handles = (0..2)
+            .map(|i| {
+                let arc = arc.clone();
+                std::thread::spawn(move || {
+                    // Plain, non-atomic writes
+                    unsafe { *arc.0[i].get() = i + 1 };
+                    drop(arc);
+                })
+            })
+            .collect::<Vec<_>>();
+
+        drop(arc);
+        for h in handles {
+            h.join().unwrap();
+        }
+    }
+}
+
+/// A listener refers back to its bus weakly, so dropping the last outside
+/// handle to the bus collects both, rather than leaking the cycle.
+#[test]
+fn weak_cycle_is_collected() {
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Bus {
+        listeners: std::sync::Mutex<Vec<HeapArc<Listener>>>,
+    }
+
+    struct Listener {
+        bus: HeapWeak<Bus>,
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    impl Drop for Listener {
+        fn drop(&mut self) {
+            // The bus is already being dropped
+            assert!(self.bus.upgrade().is_none());
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let heap = new_heap();
+    let bus = alloc_arc(
+        heap,
+        Bus {
+            listeners: std::sync::Mutex::new(Vec::new()),
+        },
+    );
+    let listener = alloc_arc(
+        heap,
+        Listener {
+            bus: HeapArc::downgrade(&bus),
+        },
+    );
+    assert!(listener.bus.upgrade().is_some());
+    bus.listeners.lock().unwrap().push(listener);
+
+    drop(bus);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 2);
+
+    // Everything was freed
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn is_unique() {
+    let heap = new_heap();
+    let arc = alloc_arc(heap, 1u32);
+    assert!(HeapArc::is_unique(&arc));
+
+    let arc2 = arc.clone();
+    assert!(!HeapArc::is_unique(&arc));
+    drop(arc2);
+    assert!(HeapArc::is_unique(&arc));
+
+    // A weak could be upgraded at any time
+    let weak = HeapArc::downgrade(&arc);
+    assert!(!HeapArc::is_unique(&arc));
+    drop(weak);
+    assert!(HeapArc::is_unique(&arc));
+}
+
+#[test]
+fn try_unwrap_and_get_mut() {
+    let heap = new_heap();
+    let mut arc = alloc_arc(heap, String::from("config"));
+    HeapArc::get_mut(&mut arc).unwrap().push('!');
+
+    let mut other = arc.clone();
+    assert!(HeapArc::get_mut(&mut other).is_none());
+    let arc = HeapArc::try_unwrap(arc).unwrap_err();
+    drop(other);
+
+    let weak = HeapArc::downgrade(&arc);
+    let mut arc = arc;
+    assert!(HeapArc::get_mut(&mut arc).is_none());
+
+    // Weaks don't stop the contents being moved out, but can't upgrade after
+    assert_eq!(HeapArc::try_unwrap(arc).unwrap(), "config!");
+    assert!(weak.upgrade().is_none());
+    drop(weak);
+
+    // Everything was freed
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn unwrap_or_clone() {
+    let heap = new_heap();
+    let arc = alloc_arc(heap, String::from("shared"));
+    let other = arc.clone();
+    let weak = HeapArc::downgrade(&arc);
+
+    // Shared: the contents are cloned
+    let cloned = HeapArc::unwrap_or_clone(other);
+    assert_eq!(cloned, "shared");
+    assert_eq!(HeapArc::strong_count(&arc), 1);
+
+    // Unique: the contents are moved out, and the weak can't upgrade
+    let moved = HeapArc::unwrap_or_clone(arc);
+    assert_eq!(moved, "shared");
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn sharable_box_into_arc() {
+    let heap = new_heap();
+    let mut guard = heap.lock().unwrap();
+    let mut bx = guard.alloc_box_sharable(vec![1, 2]).unwrap();
+    drop(guard);
+
+    bx.push(3);
+    let addr = &*bx as *const Vec<i32>;
+
+    let arc = bx.into_arc();
+    assert!(std::ptr::eq(&*arc, addr));
+    assert_eq!(*arc, [1, 2, 3]);
+    assert_eq!(HeapArc::strong_count(&arc), 1);
+    assert_eq!(HeapArc::weak_count(&arc), 0);
+}
+
+#[test]
+fn try_alloc_arc() {
+    let heap = new_heap();
+    let arc = heap.try_alloc_arc(5u32).map_err(drop).unwrap();
+    assert_eq!(*arc, 5);
+
+    // Fails (without dropping the value) while the heap is locked
+    let guard = heap.lock().unwrap();
+    let (vec, err) = heap.try_alloc_arc(vec![1, 2, 3]).map(drop).unwrap_err();
+    assert_eq!(vec, [1, 2, 3]);
+    assert_eq!(err.kind(), AllocErrorKind::Busy);
+    drop(guard);
+
+    // Fails if the value can never fit
+    let (big, err) = heap.try_alloc_arc([0u8; SIZE]).map(drop).unwrap_err();
+    assert_eq!(big.len(), SIZE);
+    assert_eq!(err.kind(), AllocErrorKind::TooLarge);
+}
+
+#[test]
+fn into_raw_from_raw() {
+    let heap = new_heap();
+    let arc = alloc_arc(heap, 7u32);
+    let arc2 = arc.clone();
+
+    let ptr = HeapArc::into_raw(arc);
+    assert_eq!(unsafe { *ptr }, 7);
+    // The raw pointer still holds its strong reference
+    assert_eq!(HeapArc::strong_count(&arc2), 2);
+
+    let arc = unsafe { HeapArc::from_raw(ptr) };
+    assert_eq!(HeapArc::strong_count(&arc2), 2);
+    drop(arc);
+    assert_eq!(HeapArc::strong_count(&arc2), 1);
+}
+
+#[test]
+fn by_address() {
+    use std::collections::HashSet;
+
+    let heap = new_heap();
+    let a = alloc_arc(heap, 1u32);
+    let b = alloc_arc(heap, 1u32);
+    assert!(a == &*b);
+    assert!(!HeapArc::ptr_eq(&a, &b));
+
+    let mut set = HashSet::new();
+    assert!(set.insert(ByAddress(a.clone())));
+    assert!(set.insert(ByAddress(b.clone())));
+    assert!(!set.insert(ByAddress(a.clone())));
+    assert_eq!(set.len(), 2);
+    assert!(set.contains(&ByAddress(b)));
+}
+
+#[test]
+fn with_info() {
+    let heap = new_heap();
+    let arc = alloc_arc(heap, "obj");
+    let _other = arc.clone();
+
+    let line = HeapArc::with_info(&arc, |count, obj| format!("[count={count}] {obj}"));
+    assert_eq!(line, "[count=2] obj");
+}
+
+#[test]
+fn on_last_drop() {
+    use std::sync::Mutex;
+
+    static EVENTS: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+    // Stands in for a foreign type, with its own destructor
+    struct Device(&'static str);
+
+    impl Drop for Device {
+        fn drop(&mut self) {
+            EVENTS.lock().unwrap().push("drop");
+        }
+    }
+
+    fn close(dev: &mut Device) {
+        EVENTS.lock().unwrap().push(dev.0);
+    }
+
+    let heap = new_heap();
+    let arc: HeapArc<Device> = now(heap.allocate_arc_with_drop(Device("close"), close));
+    let weak = HeapArc::downgrade(&arc);
+    let other = arc.clone();
+    assert_eq!(arc.0, "close");
+
+    drop(arc);
+    assert!(EVENTS.lock().unwrap().is_empty());
+    // The hook runs first, once the last arc is dropped
+    drop(other);
+    assert_eq!(*EVENTS.lock().unwrap(), ["close", "drop"]);
+    assert!(weak.upgrade().is_none());
+
+    // Moving the contents out skips the hook, for good
+    let arc = now(heap.allocate_arc_with_drop(Device("unwrapped"), close));
+    let dev = HeapArc::try_unwrap(arc).ok().unwrap();
+    drop(dev);
+    assert_eq!(*EVENTS.lock().unwrap(), ["close", "drop", "drop"]);
+
+    // It also runs when the last handle is a `HeapDynArc`
+    let arc = now(heap.allocate_arc_with_drop(Device("dyn"), close));
+    let dynarc: HeapDynArc<dyn Send> = heap_arc_dyn!(arc => dyn Send);
+    drop(dynarc);
+    assert_eq!(
+        *EVENTS.lock().unwrap(),
+        ["close", "drop", "drop", "dyn", "drop"]
+    );
+}
+
+/// `get_mut` never hands out the contents while another thread can still
+/// reach them, even if that thread downgrades its arc and drops it while
+/// `get_mut` is checking. Under miri, a wrong answer shows up as a data race.
+#[test]
+fn get_mut_races_downgrade() {
+    const ITERS: usize = if cfg!(miri) { 10 } else { 200 };
+
+    let heap = new_heap();
+    for _ in 0..ITERS {
+        let mut arc = alloc_arc(heap, 0usize);
+        let other = arc.clone();
+        let t = std::thread::spawn(move || {
+            let weak = HeapArc::downgrade(&other);
+            drop(other);
+            // Plain, non-atomic read
+            weak.upgrade().map(|arc| *arc)
+        });
+        let mine = HeapArc::get_mut(&mut arc).map(|data| {
+            *data += 1;
+            *data
+        });
+        // Our arc is still alive, so the upgrade always succeeds, and as the
+        // weak lives until after the read, `get_mut` only succeeds once the
+        // other thread is done with the contents.
+        assert_eq!(t.join().unwrap(), Some(0));
+        assert!(matches!(mine, None | Some(1)));
+    }
+
+    // Everything was freed
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn into_inner() {
+    let heap = new_heap();
+    let arc = alloc_arc(heap, String::from("last"));
+    let weak = HeapArc::downgrade(&arc);
+    let other = arc.clone();
+    assert_eq!(HeapArc::into_inner(other), None);
+    assert_eq!(HeapArc::into_inner(arc).as_deref(), Some("last"));
+    assert!(weak.upgrade().is_none());
+    drop(weak);
+
+    // Racing to drop the last two references, exactly one gets the contents
+    for _ in 0..8 {
+        let arc = alloc_arc(heap, String::from("race"));
+        let other = arc.clone();
+        let t = std::thread::spawn(move || HeapArc::into_inner(other));
+        let mine = HeapArc::into_inner(arc);
+        let theirs = t.join().unwrap();
+        assert!(mine.is_some() != theirs.is_some());
+    }
+
+    // Everything was freed
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn arc_from_box() {
+    let heap = new_heap();
+    let mut guard = heap.lock().unwrap();
+    let bx = guard.alloc_box(Rc::new(1u32)).map_err(drop).unwrap();
+    let rc = (*bx).clone();
+    let arc = guard.alloc_arc_from_box(bx).unwrap();
+    drop(guard);
+
+    // The contents were moved, not cloned or dropped
+    assert_eq!(Rc::strong_count(&rc), 2);
+    assert_eq!(HeapArc::strong_count(&arc), 1);
+    let weak = HeapArc::downgrade(&arc);
+    drop(arc);
+    assert!(weak.upgrade().is_none());
+    assert_eq!(Rc::strong_count(&rc), 1);
+    drop(weak);
+
+    // Everything was freed
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}
+
+#[test]
+fn dyn_arcs() {
+    trait Sensor: Send + Sync {
+        fn read(&self) -> u32;
+    }
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Small(u8);
+    struct Large([u64; 16]);
+
+    impl Sensor for Small {
+        fn read(&self) -> u32 {
+            self.0 as u32
+        }
+    }
+    impl Sensor for Large {
+        fn read(&self) -> u32 {
+            self.0.iter().sum::<u64>() as u32
+        }
+    }
+    impl Drop for Small {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    impl Drop for Large {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let heap = new_heap();
+    let small = alloc_arc(heap, Small(3));
+    let large = alloc_arc(heap, Large([2; 16]));
+    let weak = HeapArc::downgrade(&large);
+    let large_too = large.clone();
+
+    let sensors: Vec<HeapDynArc<dyn Sensor>> = vec![
+        heap_arc_dyn!(small => dyn Sensor),
+        heap_arc_dyn!(large => dyn Sensor),
+    ];
+    let shared = sensors.clone();
+    assert_eq!(HeapDynArc::strong_count(&shared[0]), 2);
+    assert_eq!(HeapDynArc::strong_count(&shared[1]), 3);
+    assert!(HeapDynArc::ptr_eq(&sensors[1], &shared[1]));
+    assert!(!HeapDynArc::ptr_eq(&sensors[0], &sensors[1]));
+
+    // Clones can be read (and dropped) from other threads
+    let reads = std::thread::spawn(move || shared.iter().map(|s| s.read()).collect::<Vec<_>>())
+        .join()
+        .unwrap();
+    assert_eq!(reads, [3, 32]);
+
+    drop(sensors);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+
+    // The original arc, and its weak, still work alongside the dyn ones
+    assert_eq!(HeapArc::strong_count(&large_too), 1);
+    drop(large_too);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 2);
+    assert!(weak.upgrade().is_none());
+    drop(weak);
+
+    // Everything was freed
+    let mut guard = heap.lock().unwrap();
+    drop(guard.alloc_box_array_with(|| 0u8, 15 * 1024).unwrap());
+}